@@ -0,0 +1,8 @@
+// HTMX ignores responses with an error status by default. The server renders
+// error messages as fragments meant to be swapped in, so show them.
+document.addEventListener("htmx:beforeSwap", function (event) {
+	if (event.detail.xhr.status >= 400) {
+		event.detail.shouldSwap = true;
+		event.detail.isError = false;
+	}
+});
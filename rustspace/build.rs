@@ -0,0 +1,4 @@
+// `sqlx::migrate!` embeds the migrations, so a new one has to trigger a rebuild
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
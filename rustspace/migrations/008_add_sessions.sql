@@ -0,0 +1,17 @@
+create table "sessions" (
+	id serial primary key,
+	token_id text unique not null,
+	user_agent text,
+	ip text,
+	created_at timestamptz not null default now(),
+	last_seen_at timestamptz not null default now(),
+	expires_at timestamptz not null,
+	revoked_at timestamptz,
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
+
+create index sessions_user_id_idx on sessions(user_id);
@@ -0,0 +1,16 @@
+alter table "sessions" add column remember boolean not null default false;
+
+create table "refresh_tokens" (
+	id serial primary key,
+	token_hash text unique not null,
+	created_at timestamptz not null default now(),
+	expires_at timestamptz not null,
+	used_at timestamptz,
+	session_id int not null,
+	constraint fk_session_id
+		foreign key(session_id)
+		references sessions(id)
+		on delete cascade
+);
+
+create index refresh_tokens_session_id_idx on refresh_tokens(session_id);
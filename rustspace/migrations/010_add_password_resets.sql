@@ -0,0 +1,14 @@
+create table "password_resets" (
+	id serial primary key,
+	token_hash text unique not null,
+	created_at timestamptz not null default now(),
+	expires_at timestamptz not null,
+	used_at timestamptz,
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
+
+create index password_resets_user_id_idx on password_resets(user_id);
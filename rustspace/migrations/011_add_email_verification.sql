@@ -0,0 +1,20 @@
+alter table "users" add column email_verified_at timestamptz;
+
+-- accounts created before verification existed are trusted
+update "users" set email_verified_at = now();
+
+create table "email_verifications" (
+	id serial primary key,
+	token_hash text unique not null,
+	email text not null,
+	created_at timestamptz not null default now(),
+	expires_at timestamptz not null,
+	used_at timestamptz,
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
+
+create index email_verifications_user_id_idx on email_verifications(user_id);
@@ -0,0 +1,30 @@
+alter table "users" add column totp_secret text;
+alter table "users" add column totp_enabled_at timestamptz;
+alter table "users" add column totp_last_step bigint;
+
+create table "recovery_codes" (
+	id serial primary key,
+	code_hash text not null,
+	used_at timestamptz,
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
+
+create index recovery_codes_user_id_idx on recovery_codes(user_id);
+
+create table "login_challenges" (
+	id serial primary key,
+	token_hash text unique not null,
+	remember boolean not null default false,
+	attempts int not null default 0,
+	created_at timestamptz not null default now(),
+	expires_at timestamptz not null,
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
@@ -0,0 +1,67 @@
+alter table "profiles"
+	drop constraint fk_user_id,
+	add constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade;
+
+alter table "friendships"
+	drop constraint fk_user_id,
+	drop constraint fk_friend_id,
+	add constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade,
+	add constraint fk_friend_id
+		foreign key(friend_id)
+		references users(id)
+		on delete cascade;
+
+alter table "posts"
+	drop constraint fk_user_id,
+	add constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade;
+
+alter table "comments"
+	drop constraint fk_user_id,
+	drop constraint fk_post_id,
+	add constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade,
+	add constraint fk_post_id
+		foreign key(post_id)
+		references posts(id)
+		on delete cascade;
+
+create index friendships_friend_id_idx on friendships(friend_id);
+create index posts_user_id_idx on posts(user_id);
+create index comments_user_id_idx on comments(user_id);
+create index comments_post_id_idx on comments(post_id);
+
+-- one profile per user, so saving it can be a single upsert; the oldest one stays
+delete from "profiles" p
+	using "profiles" older
+	where p.user_id = older.user_id
+		and older.id < p.id;
+
+create unique index profiles_user_id_key on profiles(user_id);
+
+-- one friendship per pair of users, whoever sent the request; the oldest one stays
+delete from "friendships" f
+	using "friendships" older
+	where least(f.user_id, f.friend_id) = least(older.user_id, older.friend_id)
+		and greatest(f.user_id, f.friend_id) = greatest(older.user_id, older.friend_id)
+		and older.id < f.id;
+
+create unique index friendships_pair_key
+	on friendships(least(user_id, friend_id), greatest(user_id, friend_id));
+
+update "posts" set title = '' where title is null;
+update "posts" set content = '' where content is null;
+
+alter table "posts"
+	alter column title set not null,
+	alter column content set not null;
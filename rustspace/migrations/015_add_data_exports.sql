@@ -0,0 +1,13 @@
+create table "data_exports" (
+	id serial primary key,
+	created_at timestamptz not null default now(),
+	completed_at timestamptz,
+	expires_at timestamptz not null,
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
+
+create index data_exports_user_id_idx on data_exports(user_id);
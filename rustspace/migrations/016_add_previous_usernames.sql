@@ -0,0 +1,12 @@
+create table "previous_usernames" (
+	id serial primary key,
+	screen_name text unique not null,
+	created_at timestamptz not null default now(),
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
+
+create index previous_usernames_user_id_idx on previous_usernames(user_id);
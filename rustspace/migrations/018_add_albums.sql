@@ -0,0 +1,44 @@
+create table "albums" (
+	id serial primary key,
+	name text not null,
+	visibility text not null default 'public'
+		check (visibility in ('public', 'friends', 'private')),
+	created_at timestamptz not null default now(),
+	updated_at timestamptz not null default now(),
+	user_id int not null,
+	constraint fk_user_id
+		foreign key(user_id)
+		references users(id)
+		on delete cascade
+);
+
+create index albums_user_id_idx on albums(user_id);
+
+create trigger update_timestamp
+before update
+on "albums"
+for each row
+	execute procedure update_modified_column();
+
+create table "photos" (
+	id serial primary key,
+	-- media key shared by every size of the photo
+	key text not null,
+	caption text,
+	created_at timestamptz not null default now(),
+	album_id int not null,
+	constraint fk_album_id
+		foreign key(album_id)
+		references albums(id)
+		on delete cascade
+);
+
+create index photos_album_id_idx on photos(album_id);
+
+-- without a chosen cover the album shows its first photo
+alter table "albums"
+	add column cover_id int,
+	add constraint fk_cover_id
+		foreign key(cover_id)
+		references photos(id)
+		on delete set null;
@@ -0,0 +1,2 @@
+alter table users
+  add column "banner_key" text;
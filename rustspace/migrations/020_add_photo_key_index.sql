@@ -0,0 +1,2 @@
+-- the same image uploaded twice shares its files, which stay until no photo uses them
+create index photos_key_idx on photos(key);
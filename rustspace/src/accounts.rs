@@ -0,0 +1,108 @@
+use std::{sync::Arc, time::Duration};
+
+use tracing::{info, warn, error, debug};
+
+use crate::{AppState, error::AppError, sized_image::{AVATARS, BANNERS}, images::ImageLimits, albums::remove_unused_photos};
+
+/// How often to look for accounts whose grace period is over.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60*60);
+
+/// Runs `purge_deleted_accounts` in the background for as long as the server runs.
+pub fn spawn_purge(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match purge_deleted_accounts(&state).await {
+                Ok(0) => {},
+                Ok(deleted) => info!("{} accounts deleted", deleted),
+                Err(err) => error!("couldn't delete accounts: {}", err),
+            }
+        }
+    });
+}
+
+/// Moves avatars from the directory they used to be kept in to the media store.
+/// They were either a single file named after the owner or their id, or one file per size.
+/// Returns how many were converted.
+pub async fn convert_old_avatars(state: &AppState) -> Result<usize, AppError> {
+    let users = sqlx::query!("SELECT id, screen_name FROM users WHERE avatar AND avatar_key IS NULL")
+        .fetch_all(&state.db)
+        .await?;
+    let config = &state.config.assets;
+    let mut converted = 0;
+    for user in users {
+        // the largest size is the best source, the sizes made from it come last
+        let old: Vec<_> = [format!("{}-{}.png", user.id, AVATARS.largest()), format!("{}.png", user.id), format!("{}.png", user.screen_name)].into_iter()
+            .chain(AVATARS.widths.iter().filter(|size| **size != AVATARS.largest()).map(|size| format!("{}-{}.png", user.id, size)))
+            .map(|name| config.avatars.join(name))
+            .filter(|path| path.exists())
+            .collect();
+        let Some(source) = old.first() else {
+            continue
+        };
+        let result = match std::fs::read(source) {
+            Ok(data) => AVATARS.make(&data, None, &ImageLimits::from_config(config)),
+            Err(_) => Err(AppError::Internal("Couldn't read file!")),
+        };
+        let avatars = match result {
+            Ok(avatars) => avatars,
+            Err(err) => {
+                warn!("couldn't convert avatar of user {}: {}", user.id, err);
+                continue
+            }
+        };
+        match AVATARS.save(state.media.as_ref(), user.id, &avatars).await {
+            Ok(key) => {
+                state.storage.users.set_avatar(user.id, Some(&key)).await?;
+                for path in old {
+                    _ = std::fs::remove_file(path);
+                }
+                converted += 1;
+            },
+            Err(err) => warn!("couldn't store avatar of user {}: {}", user.id, err),
+        }
+    }
+    Ok(converted)
+}
+
+/// Removes the accounts whose grace period is over, along with their avatar files.
+/// Returns how many were deleted.
+pub async fn purge_deleted_accounts(state: &AppState) -> Result<usize, AppError> {
+    let now = state.clock.now();
+    let mut deleted = 0;
+    for user in state.storage.users.due_for_deletion(now).await? {
+        let Some(user_id) = user.id else {
+            continue
+        };
+        // the photos go together with the user, so their keys have to be read first,
+        // without them the user waits for the next run
+        let photos = match state.storage.albums.photos_by_user(user_id).await {
+            Ok(photos) => photos,
+            Err(err) => {
+                warn!("couldn't read photos of user {}: {}", user_id, err);
+                continue
+            }
+        };
+        // the user may have logged in since the list was read
+        if !state.storage.users.delete_due(user_id, now).await? {
+            debug!("deletion of user {} was cancelled", user_id);
+            continue
+        }
+        if let Some(key) = &user.avatar_key {
+            if let Err(err) = AVATARS.remove(state.media.as_ref(), key).await {
+                warn!("couldn't delete avatar of user {}: {}", user_id, err);
+            }
+        }
+        if let Some(key) = &user.banner_key {
+            if let Err(err) = BANNERS.remove(state.media.as_ref(), key).await {
+                warn!("couldn't delete banner of user {}: {}", user_id, err);
+            }
+        }
+        for photo in photos {
+            remove_unused_photos(&state.storage, state.media.as_ref(), &photo.key).await;
+        }
+        deleted += 1;
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,121 @@
+use serde::{Serialize, Deserialize};
+use tracing::{debug, warn};
+
+use crate::{error::AppError, config::Config, storage::Storage, media::{MediaStore, MediaError}, images::{self, ImageLimits, Variants, store_variants, remove_variants}, template::FriendStatus};
+
+/// Size of the square thumbnails in the gallery, also used for album covers.
+pub const THUMBNAIL_SIZE: u32 = 256;
+/// Longest side of the photo shown on the album page.
+pub const PHOTO_SIZE: u32 = 1600;
+pub const PHOTO_SIZES: [u32; 2] = [THUMBNAIL_SIZE, PHOTO_SIZE];
+
+/// Who may see an album besides its owner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    /// Users with an accepted friendship that neither side cancelled.
+    Friends,
+    Private,
+}
+
+impl Visibility {
+    pub fn parse(value: &str) -> Option<Visibility> {
+        match value {
+            "public" => Some(Visibility::Public),
+            "friends" => Some(Visibility::Friends),
+            "private" => Some(Visibility::Private),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Friends => "friends",
+            Visibility::Private => "private",
+        }
+    }
+
+    /// Visibilities of the albums a viewer standing in `status` to the owner may see.
+    pub fn visible_to(status: &FriendStatus) -> &'static [Visibility] {
+        match status {
+            FriendStatus::User => &[Visibility::Public, Visibility::Friends, Visibility::Private],
+            FriendStatus::Friend => &[Visibility::Public, Visibility::Friends],
+            _ => &[Visibility::Public],
+        }
+    }
+
+    pub fn allows(&self, status: &FriendStatus) -> bool {
+        Visibility::visible_to(status).contains(self)
+    }
+}
+
+/// Photos may be larger than avatars, animations are capped the same way.
+pub fn photo_limits(config: &Config) -> ImageLimits {
+    ImageLimits { max_dimension: config.albums.max_photo_dimension, max_frames: config.assets.max_animation_frames }
+}
+
+/// Decodes the uploaded image and returns a square thumbnail and the photo
+/// scaled down to `PHOTO_SIZE`, as WebP, or as GIF when the upload is animated.
+pub fn make_photos(data: &[u8], limits: &ImageLimits) -> Result<Variants, AppError> {
+    let picture = images::decode(data, limits)?;
+    let images = vec![
+        (THUMBNAIL_SIZE, picture.resize_to_fill(THUMBNAIL_SIZE, THUMBNAIL_SIZE).encode()?),
+        (PHOTO_SIZE, picture.resize_to_fit(PHOTO_SIZE).encode()?),
+    ];
+    Ok(Variants { format: picture.format(), images })
+}
+
+/// Stores both sizes of the photo and returns the key they share, which is kept with the photo.
+pub async fn save_photos(media: &dyn MediaStore, user_id: i32, photos: &Variants) -> Result<String, MediaError> {
+    store_variants(media, &format!("photos/{}", user_id), photos).await
+}
+
+async fn remove_photos(media: &dyn MediaStore, key: &str) -> Result<(), MediaError> {
+    remove_variants(media, key, &PHOTO_SIZES).await
+}
+
+/// Removes the files under the key unless a photo still shows them. Keys name the content,
+/// so the same image uploaded twice gives two photos sharing their files.
+pub async fn remove_unused_photos(storage: &Storage, media: &dyn MediaStore, key: &str) {
+    match storage.albums.photo_key_in_use(key).await {
+        Ok(true) => debug!("files of photo {} are still in use", key),
+        Ok(false) => {
+            if let Err(err) = remove_photos(media, key).await {
+                warn!("couldn't delete files of photo {}: {}", key, err);
+            }
+        },
+        Err(err) => warn!("couldn't check whether files of photo {} are in use: {}", key, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{albums::{make_photos, Visibility, THUMBNAIL_SIZE, PHOTO_SIZE}, images::ImageLimits, template::FriendStatus, test::fixtures::png};
+
+    #[test]
+    fn test_making_photos() {
+        let limits = ImageLimits { max_dimension: 4000, max_frames: 10 };
+        let photos = make_photos(&png(2000, 1000), &limits).unwrap();
+
+        let thumbnail = image::load_from_memory(&photos.images[0].1).unwrap();
+        assert_eq!(photos.images[0].0, THUMBNAIL_SIZE);
+        assert_eq!((thumbnail.width(), thumbnail.height()), (THUMBNAIL_SIZE, THUMBNAIL_SIZE));
+        let photo = image::load_from_memory(&photos.images[1].1).unwrap();
+        assert_eq!(photos.images[1].0, PHOTO_SIZE);
+        assert_eq!((photo.width(), photo.height()), (PHOTO_SIZE, PHOTO_SIZE / 2));
+    }
+
+    #[test]
+    fn test_visibility() {
+        assert!(Visibility::Private.allows(&FriendStatus::User));
+        assert!(Visibility::Friends.allows(&FriendStatus::Friend));
+        assert!(!Visibility::Private.allows(&FriendStatus::Friend));
+        assert!(!Visibility::Friends.allows(&FriendStatus::Invitee));
+        assert!(Visibility::Public.allows(&FriendStatus::NotFriend));
+        assert_eq!(Visibility::parse("friends"), Some(Visibility::Friends));
+        assert_eq!(Visibility::parse("everyone"), None);
+    }
+}
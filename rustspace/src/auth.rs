@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+
+use crate::{error::AppError, AppState, UserData, UserModel};
+
+/// Logged in user, loaded from the database.
+///
+/// Rejects with `AppError::Unauthenticated`, which becomes a 401 fragment for
+/// HTMX requests and a page linking to the login form otherwise.
+#[derive(Clone)]
+pub struct CurrentUser {
+    pub id: i32,
+    pub user: UserModel,
+}
+
+/// Like `CurrentUser`, for pages that visitors can see too.
+pub struct OptionalUser(pub Option<CurrentUser>);
+
+/// Lookup result kept in the request extensions, so the user is queried
+/// once no matter how many extractors ask for it.
+#[derive(Clone)]
+struct ResolvedUser(Option<CurrentUser>);
+
+async fn resolve(parts: &mut Parts, state: &Arc<AppState>) -> Result<Option<CurrentUser>, AppError> {
+    if let Some(ResolvedUser(user)) = parts.extensions.get::<ResolvedUser>() {
+        return Ok(user.clone());
+    }
+    let Ok(data) = UserData::from_request_parts(parts, state).await;
+    let current = match data.user_id {
+        Some(user_id) => state.storage.users.find(user_id)
+            .await?
+            .and_then(|user| Some(CurrentUser { id: user.id?, user })),
+        None => None,
+    };
+    parts.extensions.insert(ResolvedUser(current.clone()));
+    Ok(current)
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for CurrentUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        resolve(parts, state).await?.ok_or(AppError::Unauthenticated)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for OptionalUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        Ok(OptionalUser(resolve(parts, state).await?))
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use axum::{extract::{Request, State}, http::{Method, HeaderValue, header::SET_COOKIE}, middleware::Next, response::{IntoResponse, Response}};
+use axum_extra::extract::CookieJar;
+use tracing::info;
+
+use crate::{AppState, session, security::random_token, error::AppError};
+
+pub const CSRF_COOKIE: &str = "Csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Token of the current request, rendered into `base.html` so that HTMX sends
+/// it back in the `X-CSRF-Token` header.
+#[derive(Clone)]
+pub struct CsrfToken(pub String);
+
+/// Double-submit check: requests that change state must repeat the value of the
+/// `Csrf` cookie in the `X-CSRF-Token` header. Other sites can make the browser
+/// send the cookie, but can't read it to set the header.
+pub async fn csrf_protection(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let cookie = CookieJar::from_headers(request.headers())
+        .get(CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|value| !value.is_empty());
+
+    if !is_safe(request.method()) {
+        let header = request.headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let valid = match (&cookie, header) {
+            (Some(cookie), Some(header)) => constant_time_eq(cookie.as_bytes(), header.as_bytes()),
+            _ => false,
+        };
+        if !valid {
+            info!("rejecting {} {} without a valid CSRF token", request.method(), request.uri().path());
+            return AppError::Forbidden("Invalid form token, please reload the page").into_response()
+        }
+    }
+
+    let issued = cookie.is_none();
+    let token = cookie.unwrap_or_else(|| random_token(32));
+    request.extensions_mut().insert(CsrfToken(token.clone()));
+    let mut response = next.run(request).await;
+    if issued {
+        if let Ok(value) = HeaderValue::from_str(&session::cookie(CSRF_COOKIE, &token, &state.config.session).to_string()) {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -1,10 +1,10 @@
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use tracing::info;
 
-pub async fn get_db(db_url: &str) -> PgPool {
+pub async fn get_db(db_url: &str, max_connections: u32) -> PgPool {
     info!("Connecting to database...");
     let pool = PgPoolOptions::new()
-        .max_connections(5)
+        .max_connections(max_connections)
         .connect(&db_url)
         .await
         .unwrap();
@@ -0,0 +1,188 @@
+use std::fmt;
+
+use axum::{extract::Request, http::{StatusCode, header}, middleware::Next, response::{IntoResponse, Response}};
+use tracing::{error, debug};
+
+use crate::{redirect::RedirectTarget, template::{ErrorsTemplate, ErrorPageTemplate, HtmlTemplate, UnauthorizedTemplate}};
+
+/// Failure of a request handler. Rendered as an error fragment for HTMX requests and
+/// as a full page otherwise, see `error_pages`.
+#[derive(Debug)]
+pub enum AppError {
+    /// The user isn't logged in. Pages send them to the login form.
+    Unauthenticated,
+    Forbidden(&'static str),
+    NotFound(&'static str),
+    Validation(Vec<&'static str>),
+    Conflict(Conflict),
+    Database(sqlx::Error),
+    /// Failure on our side that isn't caused by the database, like writing a file.
+    Internal(&'static str),
+}
+
+/// Requests that clash with the current state of the data, either detected by the
+/// handler or reported by a database constraint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conflict {
+    UsernameTaken,
+    EmailTaken,
+    AlreadyFriends,
+    FriendRequestRejected,
+    FriendRequestPending,
+    StateAlreadySet,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+}
+
+impl Conflict {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Conflict::UsernameTaken => "Username must be unique!",
+            Conflict::EmailTaken => "Email must be unique!",
+            Conflict::AlreadyFriends => "You're already friends!",
+            Conflict::FriendRequestRejected => "User already have rejected your request!",
+            Conflict::FriendRequestPending => "Request already created!",
+            Conflict::StateAlreadySet => "State is already set!",
+            Conflict::TwoFactorEnabled => "Two-factor authentication is already enabled!",
+            Conflict::TwoFactorDisabled => "Two-factor authentication is not enabled!",
+        }
+    }
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn messages(&self) -> Vec<&'static str> {
+        match self {
+            AppError::Unauthenticated => vec!["Unauthenticated!"],
+            AppError::Forbidden(message) | AppError::NotFound(message) | AppError::Internal(message) => vec![message],
+            AppError::Validation(errors) => errors.clone(),
+            AppError::Conflict(conflict) => vec![conflict.message()],
+            AppError::Database(_) => vec!["Database error, please try again later"],
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "database error: {}", err),
+            _ => write!(f, "{}", self.messages().join(" ")),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+/// Violated constraints are turned into the error the user can act upon.
+/// Anything else is an unexpected database error.
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        let constraint = err.as_database_error()
+            .and_then(|db_err| db_err.constraint().map(|constraint| (db_err.table().unwrap_or(""), constraint)));
+        match constraint {
+            Some(("users", "users_screen_name_key")) => AppError::Conflict(Conflict::UsernameTaken),
+            Some(("previous_usernames", "previous_usernames_screen_name_key")) => AppError::Conflict(Conflict::UsernameTaken),
+            Some(("users", "users_email_key")) => AppError::Conflict(Conflict::EmailTaken),
+            Some(("friendships", "friendships_pair_key")) => AppError::Conflict(Conflict::FriendRequestPending),
+            Some(("comments", "fk_post_id")) => AppError::NotFound("No such post!"),
+            _ => AppError::Database(err),
+        }
+    }
+}
+
+/// Marks responses built from an `AppError`, so that `error_pages` can render them
+/// as a full page.
+#[derive(Clone)]
+struct ErrorDetails {
+    status: StatusCode,
+    messages: Vec<&'static str>,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match &self {
+            AppError::Database(err) => error!("{}", err),
+            AppError::Internal(message) => error!("{}", message),
+            _ => debug!("request failed: {}", self),
+        }
+        let status = self.status();
+        let messages = self.messages();
+        let template = ErrorsTemplate {errors: messages.clone()};
+        let mut response = (status, HtmlTemplate(template)).into_response();
+        response.extensions_mut().insert(ErrorDetails {status, messages});
+        response
+    }
+}
+
+/// HTMX swaps the error fragment into the page it was sent from. Everything else,
+/// like following a link, gets a page of its own.
+pub async fn error_pages(request: Request, next: Next) -> Response {
+    let htmx = request.headers().contains_key("HX-Request");
+    let path = request.uri()
+        .path_and_query()
+        .map(|path| path.to_string())
+        .unwrap_or_else(|| String::from("/"));
+    let mut response = next.run(request).await;
+    if htmx {
+        return response
+    }
+    let Some(details) = response.extensions_mut().remove::<ErrorDetails>() else {
+        return response
+    };
+
+    let page = match details.status {
+        StatusCode::UNAUTHORIZED => {
+            let redir = RedirectTarget::parse(&path).unwrap_or_default();
+            HtmlTemplate(UnauthorizedTemplate {redir}).into_response()
+        },
+        status => {
+            let title = match status {
+                StatusCode::NOT_FOUND => "Not found!",
+                StatusCode::FORBIDDEN => "Forbidden!",
+                StatusCode::INTERNAL_SERVER_ERROR => "Something went wrong!",
+                _ => "Error!",
+            };
+            HtmlTemplate(ErrorPageTemplate {title, messages: details.messages}).into_response()
+        }
+    };
+    let (mut parts, _) = response.into_parts();
+    let (page_parts, body) = page.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    if let Some(content_type) = page_parts.headers.get(header::CONTENT_TYPE) {
+        parts.headers.insert(header::CONTENT_TYPE, content_type.clone());
+    }
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use crate::error::{AppError, Conflict};
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(AppError::Unauthenticated.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(AppError::Forbidden("").status(), StatusCode::FORBIDDEN);
+        assert_eq!(AppError::NotFound("").status(), StatusCode::NOT_FOUND);
+        assert_eq!(AppError::Validation(vec![]).status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(AppError::Conflict(Conflict::UsernameTaken).status(), StatusCode::CONFLICT);
+        assert_eq!(AppError::Database(sqlx::Error::RowNotFound).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_database_details_are_hidden() {
+        let err = AppError::from(sqlx::Error::Protocol(String::from("relation \"users\" does not exist")));
+        assert_eq!(err.messages(), vec!["Database error, please try again later"]);
+    }
+}
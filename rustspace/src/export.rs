@@ -0,0 +1,234 @@
+use std::{collections::HashSet, io::{Cursor, Write}, path::PathBuf, sync::Arc, time::Duration};
+
+use askama::Template;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{info, error, debug};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{AppState, error::AppError, config::ExportsConfig, sized_image::{AVATARS, BANNERS}, images::{key_extension, sized_key}, UserModel, ProfileModel, FriendshipRecord, BlogPostModel, BlogCommentModel, DataExportModel, AlbumModel, PhotoModel};
+
+/// How often expired archives are removed.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60*60);
+/// How long building an archive may take, though it takes seconds. Unfinished exports
+/// older than this were interrupted by a restart. Another server may still be building
+/// younger ones, so they are left alone, and so are archives of that age without a row.
+const EXPORT_DEADLINE: Duration = Duration::from_secs(60*60);
+
+/// Account fields that go into the archive, leaving out the password and
+/// two-factor secrets.
+#[derive(Serialize)]
+struct AccountData {
+    screen_name: String,
+    email: String,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    email_verified_at: Option<DateTime<Utc>>,
+    two_factor_enabled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Template)]
+#[template(path = "export.md", escape = "none")]
+struct ExportTemplate<'a> {
+    exported_at: DateTime<Utc>,
+    account: &'a AccountData,
+    profile: &'a Option<ProfileModel>,
+    friendships: &'a [FriendshipRecord],
+    posts: &'a [BlogPostModel],
+    comments: &'a [BlogCommentModel],
+    albums: &'a [AlbumModel],
+    photos: &'a [PhotoModel],
+}
+
+pub fn archive_path(config: &ExportsConfig, export_id: i32) -> PathBuf {
+    config.dir.join(format!("{}.zip", export_id))
+}
+
+/// Newest export of the user that hasn't expired, finished or not.
+pub async fn latest_export(db: &PgPool, user_id: i32, now: DateTime<Utc>) -> Result<Option<DataExportModel>, sqlx::Error> {
+    sqlx::query_as!(DataExportModel,
+        "SELECT id, user_id, created_at, completed_at, expires_at FROM data_exports
+        WHERE user_id = $1 AND expires_at > $2
+        ORDER BY created_at DESC, id DESC
+        LIMIT 1",
+        user_id, now)
+        .fetch_optional(db)
+        .await
+}
+
+/// Records the request and builds the archive in the background.
+pub async fn start_export(state: &Arc<AppState>, user: UserModel, user_id: i32) -> Result<DataExportModel, AppError> {
+    let now = state.clock.now();
+    let expires_at = now + chrono::Duration::seconds(state.config.exports.lifetime);
+    let export = sqlx::query_as!(DataExportModel,
+        "INSERT INTO data_exports (user_id, created_at, expires_at) VALUES ($1, $2, $3)
+        RETURNING id, user_id, created_at, completed_at, expires_at",
+        user_id, now, expires_at)
+        .fetch_one(&state.db)
+        .await?;
+
+    let state = state.clone();
+    let export_id = export.id;
+    tokio::spawn(async move {
+        if let Err(err) = generate_export(&state, export_id, &user, user_id).await {
+            error!("couldn't export data of user {}: {}", user_id, err);
+            // let the user ask again instead of waiting for an archive that never comes
+            _ = sqlx::query!("DELETE FROM data_exports WHERE id = $1", export_id)
+                .execute(&state.db)
+                .await;
+        }
+    });
+    Ok(export)
+}
+
+/// Writes the archive of the export and marks it as ready for download.
+pub async fn generate_export(state: &AppState, export_id: i32, user: &UserModel, user_id: i32) -> Result<(), AppError> {
+    let account = AccountData {
+        screen_name: user.screen_name.clone(),
+        email: user.email.clone(),
+        created_at: user.created_at,
+        updated_at: user.updated_at,
+        email_verified_at: user.email_verified_at,
+        two_factor_enabled_at: user.totp_enabled_at,
+    };
+    let profile = state.storage.profiles.find_by_user(user_id).await?;
+    let friendships = state.storage.friendships.all_for_user(user_id).await?;
+    let posts = state.storage.posts.list_by_user(user_id, i32::MAX, 0).await?;
+    let comments = state.storage.comments.all_by_user(user_id).await?;
+    let albums = state.storage.albums.all_by_user(user_id).await?;
+    let photos = state.storage.albums.photos_by_user(user_id).await?;
+    let avatar = match &user.avatar_key {
+        Some(key) => state.media.get(&sized_key(key, AVATARS.largest())).await.ok().flatten(),
+        None => None,
+    };
+    let banner = match &user.banner_key {
+        Some(key) => state.media.get(&sized_key(key, BANNERS.largest())).await.ok().flatten(),
+        None => None,
+    };
+
+    let now = state.clock.now();
+    let summary = ExportTemplate {
+        exported_at: now,
+        account: &account,
+        profile: &profile,
+        friendships: &friendships,
+        posts: &posts,
+        comments: &comments,
+        albums: &albums,
+        photos: &photos,
+    }.render().map_err(|_| AppError::Internal("Couldn't create the export!"))?;
+
+    let mut files = vec![
+        ("account.json", to_json(&account)?),
+        ("profile.json", to_json(&profile)?),
+        ("friendships.json", to_json(&friendships)?),
+        ("posts.json", to_json(&posts)?),
+        ("comments.json", to_json(&comments)?),
+        ("albums.json", to_json(&albums)?),
+        ("photos.json", to_json(&photos)?),
+        ("README.md", summary.into_bytes()),
+    ];
+    if let (Some(avatar), Some(key)) = (avatar, &user.avatar_key) {
+        let name = match key_extension(key) {
+            "webp" => "avatar.webp",
+            "gif" => "avatar.gif",
+            _ => "avatar.png",
+        };
+        files.push((name, avatar));
+    }
+    if let (Some(banner), Some(key)) = (banner, &user.banner_key) {
+        let name = match key_extension(key) {
+            "gif" => "banner.gif",
+            _ => "banner.webp",
+        };
+        files.push((name, banner));
+    }
+    let archive = tokio::task::spawn_blocking(move || build_archive(files))
+        .await
+        .map_err(|_| AppError::Internal("Couldn't create the export!"))?
+        .map_err(|_| AppError::Internal("Couldn't create the export!"))?;
+
+    tokio::fs::create_dir_all(&state.config.exports.dir)
+        .await
+        .map_err(|_| AppError::Internal("Couldn't save the export!"))?;
+    tokio::fs::write(archive_path(&state.config.exports, export_id), archive)
+        .await
+        .map_err(|_| AppError::Internal("Couldn't save the export!"))?;
+
+    let expires_at = now + chrono::Duration::seconds(state.config.exports.lifetime);
+    sqlx::query!("UPDATE data_exports SET completed_at = $1, expires_at = $2 WHERE id = $3", now, expires_at, export_id)
+        .execute(&state.db)
+        .await?;
+    info!("data of user {} exported.", user_id);
+    Ok(())
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, AppError> {
+    serde_json::to_vec_pretty(value).map_err(|_| AppError::Internal("Couldn't create the export!"))
+}
+
+fn build_archive(files: Vec<(&str, Vec<u8>)>) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(&content)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Runs `remove_expired_exports` in the background for as long as the server runs.
+pub fn spawn_cleanup(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        // the first tick is right away, so exports interrupted by a restart go at startup
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match remove_expired_exports(&state).await {
+                Ok(0) => {},
+                Ok(removed) => info!("{} expired exports removed", removed),
+                Err(err) => error!("couldn't remove expired exports: {}", err),
+            }
+        }
+    });
+}
+
+/// Deletes expired and interrupted exports, and archives left behind by deleted accounts.
+/// Returns how many archives were removed.
+pub async fn remove_expired_exports(state: &AppState) -> Result<usize, AppError> {
+    let now = state.clock.now();
+    let deadline = now - chrono::Duration::seconds(EXPORT_DEADLINE.as_secs() as i64);
+    sqlx::query!("DELETE FROM data_exports WHERE expires_at <= $1 OR (completed_at IS NULL AND created_at < $2)", now, deadline)
+        .execute(&state.db)
+        .await?;
+    let remaining: HashSet<String> = sqlx::query_scalar!("SELECT id FROM data_exports")
+        .fetch_all(&state.db)
+        .await?
+        .into_iter()
+        .map(|id| format!("{}.zip", id))
+        .collect();
+
+    let Ok(entries) = std::fs::read_dir(&state.config.exports.dir) else {
+        return Ok(0)
+    };
+    let mut removed = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.ends_with(".zip") || remaining.contains(&name) {
+            continue
+        }
+        // the export may have been requested after the rows were read
+        let recent = entry.metadata()
+            .and_then(|metadata| metadata.modified())
+            .map_or(true, |modified| DateTime::<Utc>::from(modified) > deadline);
+        if recent {
+            continue
+        }
+        match std::fs::remove_file(entry.path()) {
+            Ok(_) => removed += 1,
+            Err(err) => debug!("couldn't remove export {}: {}", name, err),
+        }
+    }
+    Ok(removed)
+}
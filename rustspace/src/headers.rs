@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use axum::{extract::{Request, State}, http::{HeaderMap, HeaderName, HeaderValue, header}, middleware::Next, response::Response};
+
+use crate::{AppState, config::HeadersConfig, security::random_token};
+
+/// Nonce of the current request's Content-Security-Policy. `base.html` puts it on
+/// the HTMX script and hands it to HTMX for scripts in swapped content.
+#[derive(Clone)]
+pub struct CspNonce(pub String);
+
+/// Adds the configured security headers to every response that doesn't set them itself.
+pub async fn security_headers(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let config = &state.config.headers;
+    let nonce = random_token(16);
+    request.extensions_mut().insert(CspNonce(nonce.clone()));
+    let mut response = next.run(request).await;
+    if !config.enabled {
+        return response
+    }
+
+    let headers = response.headers_mut();
+    set_default(headers, header::X_CONTENT_TYPE_OPTIONS, String::from("nosniff"));
+    set_default(headers, header::X_FRAME_OPTIONS, config.frame_options.clone());
+    set_default(headers, header::REFERRER_POLICY, config.referrer_policy.clone());
+    if config.hsts_max_age > 0 {
+        set_default(headers, header::STRICT_TRANSPORT_SECURITY, format!("max-age={}; includeSubDomains", config.hsts_max_age));
+    }
+    if config.content_security_policy {
+        set_default(headers, header::CONTENT_SECURITY_POLICY, content_security_policy(config, &nonce, state.config.media.origin().as_deref()));
+    }
+    response
+}
+
+/// HTMX only needs to load from the site itself, as long as it doesn't inject its
+/// indicator styles, which `base.html` turns off. Images may also come from the
+/// media store when it isn't served by the site.
+pub fn content_security_policy(config: &HeadersConfig, nonce: &str, media_origin: Option<&str>) -> String {
+    let frame_ancestors = match config.frame_options.as_str() {
+        "SAMEORIGIN" => "'self'",
+        _ => "'none'",
+    };
+    let img_src = match media_origin {
+        Some(origin) => format!("'self' data: {}", origin),
+        None => String::from("'self' data:"),
+    };
+    format!("default-src 'self'; script-src 'self' 'nonce-{nonce}'; style-src 'self'; img-src {img_src}; \
+        object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors {frame_ancestors}")
+}
+
+fn set_default(headers: &mut HeaderMap, name: HeaderName, value: String) {
+    if headers.contains_key(&name) {
+        return
+    }
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
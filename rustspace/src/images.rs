@@ -0,0 +1,501 @@
+use std::io::Cursor;
+
+use axum_extra::extract::multipart::Field;
+use image::{io::{Reader, Limits}, codecs::{gif::{GifDecoder, GifEncoder, Repeat}, webp::WebPDecoder}, imageops::{self, FilterType}, AnimationDecoder, DynamicImage, Frame, Frames, ImageDecoder, ImageError, ImageFormat};
+
+use crate::{config::AssetsConfig, error::AppError, media::{MediaStore, MediaError, content_key}};
+
+/// Most pixels all frames of an animation may add up to, so that a small file
+/// can't unpack into gigabytes of frames.
+const MAX_ANIMATION_PIXELS: u64 = 32 * 1024 * 1024;
+/// GIF quantization speed, from 1 (best colors) to 30 (fastest).
+const GIF_SPEED: i32 = 10;
+/// WebP quality, from 0 (smallest) to 100 (best looking). Photos come out several
+/// times smaller than lossless, without visible loss at the sizes shown.
+const WEBP_QUALITY: f32 = 80.0;
+
+/// How much of an upload is decoded before it's refused.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLimits {
+    pub max_dimension: u32,
+    pub max_frames: usize,
+}
+
+impl ImageLimits {
+    pub fn from_config(config: &AssetsConfig) -> ImageLimits {
+        ImageLimits { max_dimension: config.max_avatar_dimension, max_frames: config.max_animation_frames }
+    }
+}
+
+/// Format of the files made from uploads. Still images become lossy WebP,
+/// animations stay GIF since the WebP encoder can't write them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    WebP,
+    Gif,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::WebP => "webp",
+            OutputFormat::Gif => "gif",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::WebP => "image/webp",
+            OutputFormat::Gif => "image/gif",
+        }
+    }
+}
+
+/// Part of the uploaded image to keep, in its pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropBox {
+    /// Reads the box from the form fields, which are either all filled in or all empty.
+    pub fn from_fields(x: Option<&str>, y: Option<&str>, width: Option<&str>, height: Option<&str>) -> Result<Option<CropBox>, AppError> {
+        let fields: Vec<Option<&str>> = [x, y, width, height].into_iter()
+            .map(|field| field.map(str::trim).filter(|field| !field.is_empty()))
+            .collect();
+        if fields.iter().all(Option::is_none) {
+            return Ok(None)
+        }
+        let mut values = vec![];
+        for field in fields {
+            let Some(field) = field else {
+                return Err(AppError::Validation(vec!["Crop area must have position and size!"]))
+            };
+            let Ok(value) = field.parse::<u32>() else {
+                return Err(AppError::Validation(vec!["Crop area must be given in whole pixels!"]))
+            };
+            values.push(value);
+        }
+        Ok(Some(CropBox { x: values[0], y: values[1], width: values[2], height: values[3] }))
+    }
+
+    pub fn fits(&self, image: &Picture) -> bool {
+        self.width > 0 && self.height > 0
+            && self.x.checked_add(self.width).is_some_and(|right| right <= image.width())
+            && self.y.checked_add(self.height).is_some_and(|bottom| bottom <= image.height())
+    }
+}
+
+/// A decoded upload. Only pixels are kept, so nothing of the metadata the
+/// original file carried, like EXIF and GPS tags, makes it into stored files.
+#[derive(Clone)]
+pub enum Picture {
+    Still(DynamicImage),
+    /// Frames of the size of the whole canvas.
+    Animated(Vec<Frame>),
+}
+
+impl Picture {
+    pub fn width(&self) -> u32 {
+        match self {
+            Picture::Still(image) => image.width(),
+            Picture::Animated(frames) => frames[0].buffer().width(),
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            Picture::Still(image) => image.height(),
+            Picture::Animated(frames) => frames[0].buffer().height(),
+        }
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        match self {
+            Picture::Still(_) => OutputFormat::WebP,
+            Picture::Animated(_) => OutputFormat::Gif,
+        }
+    }
+
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Picture {
+        match self {
+            Picture::Still(image) => Picture::Still(image.crop_imm(x, y, width, height)),
+            Picture::Animated(frames) => Picture::Animated(frames.iter()
+                .map(|frame| Frame::from_parts(imageops::crop_imm(frame.buffer(), x, y, width, height).to_image(), 0, 0, frame.delay()))
+                .collect()),
+        }
+    }
+
+    /// The part of the picture inside the crop box, or all of it without one.
+    pub fn cropped(self, crop: Option<CropBox>) -> Result<Picture, AppError> {
+        match crop {
+            Some(crop) if !crop.fits(&self) => Err(AppError::Validation(vec!["Crop area must lie within the image!"])),
+            Some(crop) => Ok(self.crop(crop.x, crop.y, crop.width, crop.height)),
+            None => Ok(self),
+        }
+    }
+
+    /// Scales the picture down to fit in a `size` pixels square, keeping its proportions.
+    /// Pictures that already fit are left alone.
+    pub fn resize_to_fit(&self, size: u32) -> Picture {
+        if self.width() <= size && self.height() <= size {
+            return self.clone()
+        }
+        match self {
+            Picture::Still(image) => Picture::Still(image.resize(size, size, FilterType::Lanczos3)),
+            Picture::Animated(frames) => Picture::Animated(frames.iter()
+                .map(|frame| {
+                    let resized = DynamicImage::ImageRgba8(frame.buffer().clone()).resize(size, size, FilterType::Triangle);
+                    Frame::from_parts(resized.to_rgba8(), 0, 0, frame.delay())
+                })
+                .collect()),
+        }
+    }
+
+    /// Scales the picture to cover `width` by `height` and cuts off what sticks out.
+    pub fn resize_to_fill(&self, width: u32, height: u32) -> Picture {
+        match self {
+            Picture::Still(image) => Picture::Still(image.resize_to_fill(width, height, FilterType::Lanczos3)),
+            // a cheaper filter, since every frame is scaled
+            Picture::Animated(frames) => Picture::Animated(frames.iter()
+                .map(|frame| {
+                    let resized = DynamicImage::ImageRgba8(frame.buffer().clone()).resize_to_fill(width, height, FilterType::Triangle);
+                    Frame::from_parts(resized.to_rgba8(), 0, 0, frame.delay())
+                })
+                .collect()),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, AppError> {
+        match self {
+            Picture::Still(image) => {
+                let image = image.to_rgba8();
+                webp::Encoder::from_rgba(&image, image.width(), image.height())
+                    .encode_simple(false, WEBP_QUALITY)
+                    .map(|data| data.to_vec())
+                    .map_err(|_| AppError::Internal("Couldn't convert the image!"))
+            },
+            Picture::Animated(frames) => {
+                let mut bytes = Vec::new();
+                {
+                    let mut encoder = GifEncoder::new_with_speed(&mut bytes, GIF_SPEED);
+                    encoder.set_repeat(Repeat::Infinite)
+                        .and_then(|_| encoder.encode_frames(frames.iter().cloned()))
+                        .map_err(|_| AppError::Internal("Couldn't convert the image!"))?;
+                }
+                Ok(bytes)
+            },
+        }
+    }
+}
+
+/// Sizes of one upload, all in the same format, each named by the size in pixels it was made for.
+pub struct Variants {
+    pub format: OutputFormat,
+    pub images: Vec<(u32, Vec<u8>)>,
+}
+
+/// Extension of the files stored under `key`. Keys from before uploads were
+/// converted to WebP don't have one, those files are PNG.
+pub fn key_extension(key: &str) -> &str {
+    match key.rsplit_once('.') {
+        Some((_, extension)) if !extension.contains('/') => extension,
+        _ => "png",
+    }
+}
+
+/// Media key of one size of the upload stored under `key`.
+pub fn sized_key(key: &str, size: u32) -> String {
+    let stem = key.strip_suffix(&format!(".{}", key_extension(key))).unwrap_or(key);
+    format!("{}-{}.{}", stem, size, key_extension(key))
+}
+
+/// Stores every size under `prefix` and returns the key they share. It's made from the
+/// largest size, so new content never shows up under an old URL.
+pub async fn store_variants(media: &dyn MediaStore, prefix: &str, variants: &Variants) -> Result<String, MediaError> {
+    let largest = variants.images.iter()
+        .max_by_key(|(size, _)| *size)
+        .map(|(_, data)| data.as_slice())
+        .unwrap_or_default();
+    let key = format!("{}.{}", content_key(prefix, largest), variants.format.extension());
+    for (size, data) in &variants.images {
+        media.put(&sized_key(&key, *size), data.clone(), variants.format.content_type()).await?;
+    }
+    Ok(key)
+}
+
+pub async fn remove_variants(media: &dyn MediaStore, key: &str, sizes: &[u32]) -> Result<(), MediaError> {
+    for size in sizes {
+        media.delete(&sized_key(key, *size)).await?;
+    }
+    Ok(())
+}
+
+/// Reads an uploaded file and gives up as soon as it grows past `max_size` bytes,
+/// instead of buffering whatever the client keeps sending.
+pub async fn read_upload(mut field: Field, max_size: usize) -> Result<Vec<u8>, AppError> {
+    let mut data = Vec::new();
+    loop {
+        match field.chunk().await {
+            Ok(Some(chunk)) if data.len() + chunk.len() > max_size => return Err(AppError::Validation(vec!["File is too large!"])),
+            Ok(Some(chunk)) => data.extend_from_slice(&chunk),
+            Ok(None) => return Ok(data),
+            Err(_) => return Err(AppError::Validation(vec!["Couldn't read the upload!"])),
+        }
+    }
+}
+
+/// Decodes a PNG, JPEG, GIF or WebP upload, and AVIF with the `avif` feature. Pictures
+/// wider or taller than the limit are refused before they are decoded, and JPEG photos are
+/// turned upright as their EXIF orientation says, since the tag itself is dropped.
+pub fn decode(data: &[u8], limits: &ImageLimits) -> Result<Picture, AppError> {
+    if is_avif(data) {
+        return match cfg!(feature = "avif") {
+            true => decode_avif(data, limits),
+            false => Err(AppError::Validation(vec!["AVIF images aren't supported, please upload PNG, JPEG, GIF or WebP!"])),
+        }
+    }
+    match image::guess_format(data) {
+        Ok(ImageFormat::Png) => decode_still(data, limits),
+        Ok(ImageFormat::Jpeg) => decode_still(data, limits)
+            .map(|picture| match (picture, jpeg_orientation(data)) {
+                (Picture::Still(image), Some(orientation)) => Picture::Still(orient(image, orientation)),
+                (picture, _) => picture,
+            }),
+        Ok(ImageFormat::Gif) => {
+            let mut decoder = GifDecoder::new(Cursor::new(data)).map_err(image_error)?;
+            decoder.set_limits(image_limits(limits)).map_err(image_error)?;
+            collect_frames(decoder.into_frames(), limits)
+        },
+        Ok(ImageFormat::WebP) => {
+            let mut decoder = WebPDecoder::new(Cursor::new(data)).map_err(image_error)?;
+            if !decoder.has_animation() {
+                return decode_still(data, limits)
+            }
+            decoder.set_limits(image_limits(limits)).map_err(image_error)?;
+            collect_frames(decoder.into_frames(), limits)
+        },
+        _ => Err(AppError::Validation(vec!["Image must be PNG, JPEG, GIF or WebP!"])),
+    }
+}
+
+fn image_error(err: ImageError) -> AppError {
+    match err {
+        ImageError::Limits(_) => AppError::Validation(vec!["Image is too large!"]),
+        _ => AppError::Validation(vec!["Couldn't read the image!"]),
+    }
+}
+
+fn image_limits(limits: &ImageLimits) -> Limits {
+    let mut image_limits = Limits::default();
+    image_limits.max_image_width = Some(limits.max_dimension);
+    image_limits.max_image_height = Some(limits.max_dimension);
+    image_limits
+}
+
+fn decode_still(data: &[u8], limits: &ImageLimits) -> Result<Picture, AppError> {
+    let mut reader = Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|_| AppError::Validation(vec!["Couldn't read the image!"]))?;
+    reader.limits(image_limits(limits));
+    reader.decode().map(Picture::Still).map_err(image_error)
+}
+
+/// The format is given, since `image` only recognizes AVIF files with some header sizes.
+fn decode_avif(data: &[u8], limits: &ImageLimits) -> Result<Picture, AppError> {
+    let mut reader = Reader::with_format(Cursor::new(data), ImageFormat::Avif);
+    reader.limits(image_limits(limits));
+    reader.decode().map(Picture::Still).map_err(image_error)
+}
+
+/// Animations with a single frame are kept as still images.
+fn collect_frames(frames: Frames, limits: &ImageLimits) -> Result<Picture, AppError> {
+    let mut collected: Vec<Frame> = vec![];
+    let mut pixels = 0;
+    for frame in frames {
+        let frame = frame.map_err(image_error)?;
+        if collected.len() == limits.max_frames {
+            return Err(AppError::Validation(vec!["Animation has too many frames!"]))
+        }
+        pixels += frame.buffer().width() as u64 * frame.buffer().height() as u64;
+        if pixels > MAX_ANIMATION_PIXELS {
+            return Err(AppError::Validation(vec!["Animation is too large!"]))
+        }
+        collected.push(frame);
+    }
+    match collected.len() {
+        0 => Err(AppError::Validation(vec!["Couldn't read the image!"])),
+        1 => Ok(Picture::Still(DynamicImage::ImageRgba8(collected.remove(0).into_buffer()))),
+        _ => Ok(Picture::Animated(collected)),
+    }
+}
+
+/// Decoding AVIF is left to the `avif` feature, since it links libdav1d 1.3 or newer,
+/// which not every server has. The brand in the file type box gives it away.
+fn is_avif(data: &[u8]) -> bool {
+    matches!(data.get(4..12), Some(b"ftypavif") | Some(b"ftypavis"))
+}
+
+/// Value of the orientation tag in the EXIF segment of a JPEG file, if there is one.
+fn jpeg_orientation(data: &[u8]) -> Option<u16> {
+    let mut position = 2;
+    while data.get(position) == Some(&0xff) {
+        let marker = *data.get(position + 1)?;
+        let length = u16::from_be_bytes([*data.get(position + 2)?, *data.get(position + 3)?]) as usize;
+        // the image data starts with the start of scan, no metadata after it
+        if marker == 0xda || length < 2 {
+            return None
+        }
+        let segment = data.get(position + 4..position + 2 + length)?;
+        if let Some(tiff) = segment.strip_prefix(b"Exif\0\0".as_slice()) {
+            return exif_orientation(tiff)
+        }
+        position += 2 + length;
+    }
+    None
+}
+
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| tiff.get(offset..offset + 2).map(|bytes| match big_endian {
+        true => u16::from_be_bytes([bytes[0], bytes[1]]),
+        false => u16::from_le_bytes([bytes[0], bytes[1]]),
+    });
+    let read_u32 = |offset: usize| tiff.get(offset..offset + 4).map(|bytes| match big_endian {
+        true => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        false => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    });
+    let directory = read_u32(4)? as usize;
+    let entries = read_u16(directory)? as usize;
+    (0..entries)
+        .map(|entry| directory + 2 + entry * 12)
+        .find(|&entry| read_u16(entry) == Some(0x0112))
+        .and_then(|entry| read_u16(entry + 8))
+}
+
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{RgbImage, Rgb, DynamicImage, ImageOutputFormat};
+
+    use crate::images::{decode, jpeg_orientation, sized_key, ImageLimits, Picture, OutputFormat, CropBox};
+
+    const LIMITS: ImageLimits = ImageLimits { max_dimension: 1000, max_frames: 10 };
+
+    /// JPEG with an EXIF segment holding the orientation, inserted right after the start of image.
+    fn jpeg_with_orientation(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        // left half red, right half blue
+        let image = RgbImage::from_fn(width, height, |x, _| match x < width / 2 {
+            true => Rgb([255, 0, 0]),
+            false => Rgb([0, 0, 255]),
+        });
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(image).write_to(&mut std::io::Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90)).unwrap();
+        let mut exif = b"Exif\0\0MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend(orientation.to_be_bytes());
+        exif.extend([0, 0, 0, 0, 0, 0]);
+        let mut data = jpeg[..2].to_vec();
+        data.extend([0xff, 0xe1]);
+        data.extend((exif.len() as u16 + 2).to_be_bytes());
+        data.extend(exif);
+        data.extend(&jpeg[2..]);
+        data
+    }
+
+    #[test]
+    fn test_reading_jpeg_orientation() {
+        assert_eq!(jpeg_orientation(&jpeg_with_orientation(30, 20, 6)), Some(6));
+        assert_eq!(jpeg_orientation(&jpeg_with_orientation(30, 20, 1)), Some(1));
+        assert_eq!(jpeg_orientation(&[0xff, 0xd8, 0xff, 0xe1, 0x00]), None);
+    }
+
+    #[test]
+    fn test_turning_photo_upright() {
+        let picture = decode(&jpeg_with_orientation(30, 20, 6), &LIMITS).unwrap();
+
+        let Picture::Still(image) = picture else {
+            panic!("expected a still image")
+        };
+        assert_eq!((image.width(), image.height()), (20, 30));
+        let image = image.to_rgb8();
+        // turned clockwise, the red half is on top
+        assert!(image.get_pixel(10, 2)[0] > 200);
+        assert!(image.get_pixel(10, 27)[2] > 200);
+    }
+
+    #[test]
+    fn test_sized_keys() {
+        assert_eq!(sized_key("avatars/1/abc.webp", 64), "avatars/1/abc-64.webp");
+        assert_eq!(sized_key("photos/1/abc.gif", 1600), "photos/1/abc-1600.gif");
+        assert_eq!(sized_key("avatars/1/abc", 256), "avatars/1/abc-256.png");
+    }
+
+    #[test]
+    fn test_resizing_to_fit() {
+        let picture = decode(&jpeg_with_orientation(300, 200, 1), &LIMITS).unwrap();
+        let resized = picture.resize_to_fit(150);
+        assert_eq!((resized.width(), resized.height()), (150, 100));
+        let resized = picture.resize_to_fit(500);
+        assert_eq!((resized.width(), resized.height()), (300, 200));
+    }
+
+    #[cfg(not(feature = "avif"))]
+    #[test]
+    fn test_refusing_avif() {
+        let mut data = b"\0\0\0\x1cftypavif".to_vec();
+        data.extend([0; 32]);
+        assert!(decode(&data, &LIMITS).is_err());
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn test_decoding_avif() {
+        let picture = decode(include_bytes!("test/images/red-quarter.avif"), &LIMITS).unwrap();
+
+        let Picture::Still(image) = picture else {
+            panic!("expected a still image")
+        };
+        assert_eq!((image.width(), image.height()), (64, 48));
+        let image = image.to_rgb8();
+        assert!(image.get_pixel(4, 4)[0] > 200);
+        assert!(image.get_pixel(60, 44)[2] > 200);
+    }
+
+    #[test]
+    fn test_still_picture_is_stored_as_lossy_webp() {
+        let picture = decode(&jpeg_with_orientation(30, 20, 1), &LIMITS).unwrap();
+        assert_eq!(picture.format(), OutputFormat::WebP);
+        let data = picture.encode().unwrap();
+        assert_eq!(image::guess_format(&data).unwrap(), image::ImageFormat::WebP);
+        // lossless files keep their pixels in a VP8L chunk
+        assert_eq!(&data[12..16], b"VP8 ");
+    }
+
+    #[test]
+    fn test_reading_crop_box_from_fields() {
+        assert_eq!(CropBox::from_fields(None, Some(""), Some(" "), None).unwrap(), None);
+        assert_eq!(CropBox::from_fields(Some("1"), Some("2"), Some("3"), Some("4")).unwrap(),
+            Some(CropBox { x: 1, y: 2, width: 3, height: 4 }));
+        assert!(CropBox::from_fields(Some("1"), None, Some("3"), Some("4")).is_err());
+        assert!(CropBox::from_fields(Some("-1"), Some("2"), Some("3"), Some("4")).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use axum::async_trait;
+use lettre::{
+    message::{Mailbox, header::ContentType},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use tracing::info;
+
+use crate::config::{MailConfig, MailBackend, SmtpTls};
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), MailError>;
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Address(lettre::address::AddressError),
+    Message(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MailError::Address(err) => write!(f, "invalid address: {}", err),
+            MailError::Message(err) => write!(f, "couldn't build message: {}", err),
+            MailError::Smtp(err) => write!(f, "couldn't send message: {}", err),
+            MailError::Io(err) => write!(f, "couldn't write message: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+pub fn from_config(config: &MailConfig) -> Result<Box<dyn Mailer>, MailError> {
+    let from: Mailbox = config.from.parse().map_err(MailError::Address)?;
+    match config.backend {
+        MailBackend::Log => Ok(Box::new(LogMailer { from })),
+        MailBackend::File => Ok(Box::new(FileMailer { from, dir: config.dir.clone() })),
+        MailBackend::Smtp => {
+            let smtp = &config.smtp;
+            let builder = match smtp.tls {
+                SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+                SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host).map_err(MailError::Smtp)?,
+                SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host).map_err(MailError::Smtp)?,
+            };
+            let mut builder = builder.port(smtp.port);
+            if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+                builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+            Ok(Box::new(SmtpMailer { from, transport: builder.build() }))
+        }
+    }
+}
+
+fn build_message(from: &Mailbox, email: Email) -> Result<Message, MailError> {
+    let to: Mailbox = email.to.parse().map_err(MailError::Address)?;
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body)
+        .map_err(MailError::Message)
+}
+
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let message = build_message(&self.from, email)?;
+        self.transport.send(message).await.map_err(MailError::Smtp)?;
+        Ok(())
+    }
+}
+
+/// Writes every message as an `.eml` file instead of sending it.
+pub struct FileMailer {
+    from: Mailbox,
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let message = build_message(&self.from, email)?;
+        tokio::fs::create_dir_all(&self.dir).await.map_err(MailError::Io)?;
+        let name = format!("{}.eml", chrono::Utc::now().format("%Y%m%d%H%M%S%f"));
+        let path = self.dir.join(name);
+        tokio::fs::write(&path, message.formatted()).await.map_err(MailError::Io)?;
+        info!("mail written to {}", path.display());
+        Ok(())
+    }
+}
+
+pub struct LogMailer {
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let message = build_message(&self.from, email)?;
+        info!("mail not sent, printing instead:\n{}", String::from_utf8_lossy(&message.formatted()));
+        Ok(())
+    }
+}
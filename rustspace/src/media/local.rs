@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use axum::async_trait;
+
+use crate::media::{MediaStore, MediaError};
+
+/// URL path the router serves the directory under.
+pub const MEDIA_PATH: &str = "/media";
+
+/// Keeps files in a directory on the server, served by the app itself.
+pub struct LocalMediaStore {
+    dir: PathBuf,
+}
+
+impl LocalMediaStore {
+    pub fn new(dir: PathBuf) -> LocalMediaStore {
+        LocalMediaStore { dir }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+}
+
+#[async_trait]
+impl MediaStore for LocalMediaStore {
+    async fn put(&self, key: &str, data: Vec<u8>, _content_type: &str) -> Result<(), MediaError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(MediaError::Io)?;
+        }
+        tokio::fs::write(path, data).await.map_err(MediaError::Io)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, MediaError> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(MediaError::Io(err)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), MediaError> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(MediaError::Io(err)),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", MEDIA_PATH, key)
+    }
+}
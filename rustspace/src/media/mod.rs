@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::{config::{MediaConfig, MediaBackend}, images::sized_key};
+
+mod local;
+mod s3;
+
+pub use self::{local::{LocalMediaStore, MEDIA_PATH}, s3::S3MediaStore};
+
+/// Storage for files uploaded by users. Keys are paths like `avatars/12/3f2a...-64.png`,
+/// and since they name the content, a file never changes once it's written and its URL
+/// can be cached for good.
+#[async_trait]
+pub trait MediaStore: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), MediaError>;
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, MediaError>;
+    /// Removing a file that isn't there is not an error.
+    async fn delete(&self, key: &str) -> Result<(), MediaError>;
+    /// Address browsers load the file from.
+    fn url(&self, key: &str) -> String;
+
+    /// Address of one size of an image stored with `images::store_variants`.
+    fn sized_url(&self, key: &str, size: u32) -> String {
+        self.url(&sized_key(key, size))
+    }
+}
+
+#[derive(Debug)]
+pub enum MediaError {
+    Io(std::io::Error),
+    Http(hyper::Error),
+    Request(hyper_util::client::legacy::Error),
+    InvalidEndpoint(String),
+    /// The service didn't answer within the configured time.
+    Timeout,
+    /// The service answered, but not with success.
+    Status(u16),
+}
+
+impl std::fmt::Display for MediaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MediaError::Io(err) => write!(f, "couldn't access file: {}", err),
+            MediaError::Http(err) => write!(f, "request failed: {}", err),
+            MediaError::Request(err) => write!(f, "request failed: {}", err),
+            MediaError::Timeout => write!(f, "service didn't answer in time"),
+            MediaError::InvalidEndpoint(endpoint) => write!(f, "invalid endpoint: {}", endpoint),
+            MediaError::Status(status) => write!(f, "service responded with status {}", status),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {}
+
+/// The store is shared with the templates of pages listing avatars.
+pub fn from_config(config: &MediaConfig) -> Result<Arc<dyn MediaStore>, MediaError> {
+    match config.backend {
+        MediaBackend::Local => Ok(Arc::new(LocalMediaStore::new(config.dir.clone()))),
+        MediaBackend::S3 => Ok(Arc::new(S3MediaStore::new(config.s3.clone())?)),
+    }
+}
+
+/// Key under `prefix` named after the SHA-256 of `data`, so a new upload gets a new
+/// address while uploading the same image again doesn't.
+pub fn content_key(prefix: &str, data: &[u8]) -> String {
+    let hash: String = Sha256::digest(data)
+        .iter()
+        .take(16)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}/{}", prefix, hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::media::content_key;
+
+    #[test]
+    fn test_content_key() {
+        let key = content_key("avatars/1", b"image");
+        assert!(key.starts_with("avatars/1/"));
+        assert_eq!(key.len(), "avatars/1/".len() + 32);
+        assert_eq!(key, content_key("avatars/1", b"image"));
+        assert_ne!(key, content_key("avatars/1", b"other image"));
+    }
+}
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use axum::{extract::{Request, State}, http::{HeaderMap, StatusCode, header::RETRY_AFTER}, middleware::Next, response::{IntoResponse, Response}};
+use chrono::{DateTime, Duration, Utc};
+use tracing::info;
+
+use crate::{AppState, config::RateLimitConfig, session::ClientInfo, template::{ErrorsTemplate, HtmlTemplate}};
+
+/// Above this many tracked keys, full buckets and expired lockouts are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Request was refused, the client may try again after `retry_after` seconds.
+#[derive(Debug, PartialEq)]
+pub struct Limited {
+    pub retry_after: i64,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: DateTime<Utc>,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, now: DateTime<Utc>) -> TokenBucket {
+        TokenBucket { tokens: capacity as f64, updated: now }
+    }
+
+    fn refill(&mut self, capacity: u32, per_minute: u32, now: DateTime<Utc>) {
+        let elapsed = (now - self.updated).num_milliseconds().max(0) as f64 / 1000.0;
+        self.tokens = (self.tokens + elapsed * per_minute as f64 / 60.0).min(capacity as f64);
+        self.updated = now;
+    }
+
+    fn take(&mut self, capacity: u32, per_minute: u32, now: DateTime<Utc>) -> Result<(), Limited> {
+        self.refill(capacity, per_minute, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = (1.0 - self.tokens) * 60.0 / per_minute as f64;
+        Err(Limited { retry_after: wait.ceil() as i64 })
+    }
+
+    fn is_full(&mut self, capacity: u32, per_minute: u32, now: DateTime<Utc>) -> bool {
+        self.refill(capacity, per_minute, now);
+        self.tokens >= capacity as f64
+    }
+}
+
+struct Account {
+    bucket: TokenBucket,
+    failures: u32,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+/// In-memory token buckets for client addresses and usernames, plus the count of
+/// failed logins per username. Usernames are tracked whether the account exists
+/// or not, so that the limits don't reveal which usernames are taken.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    ips: Mutex<HashMap<String, TokenBucket>>,
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter { config, ips: Mutex::new(HashMap::new()), accounts: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn check_ip(&self, ip: &str, now: DateTime<Utc>) -> Result<(), Limited> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let (capacity, per_minute) = (self.config.ip_burst, self.config.ip_per_minute);
+        let mut ips = self.ips.lock().unwrap();
+        if ips.len() > PRUNE_THRESHOLD {
+            ips.retain(|_, bucket| !bucket.is_full(capacity, per_minute, now));
+        }
+        ips.entry(String::from(ip))
+            .or_insert_with(|| TokenBucket::new(capacity, now))
+            .take(capacity, per_minute, now)
+    }
+
+    /// Called before checking the password of `username`.
+    pub fn check_account(&self, username: &str, now: DateTime<Utc>) -> Result<(), Limited> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let (capacity, per_minute) = (self.config.account_burst, self.config.account_per_minute);
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.len() > PRUNE_THRESHOLD {
+            accounts.retain(|_, account| account.failures > 0 || !account.bucket.is_full(capacity, per_minute, now));
+        }
+        let account = accounts.entry(username.to_lowercase())
+            .or_insert_with(|| Account { bucket: TokenBucket::new(capacity, now), failures: 0, locked_until: None });
+        if let Some(locked_until) = account.locked_until {
+            if locked_until > now {
+                return Err(Limited { retry_after: (locked_until - now).num_seconds().max(1) });
+            }
+        }
+        account.bucket.take(capacity, per_minute, now)
+    }
+
+    /// Records a wrong password. Once `max_failures` is reached the account is locked,
+    /// for twice as long with every further failure.
+    pub fn login_failed(&self, username: &str, now: DateTime<Utc>) {
+        if !self.config.enabled {
+            return;
+        }
+        let mut accounts = self.accounts.lock().unwrap();
+        let Some(account) = accounts.get_mut(&username.to_lowercase()) else {
+            return;
+        };
+        account.failures += 1;
+        if account.failures < self.config.max_failures {
+            return;
+        }
+        let exponent = (account.failures - self.config.max_failures).min(31);
+        let lockout = self.config.lockout
+            .saturating_mul(1i64 << exponent)
+            .min(self.config.max_lockout);
+        info!("locking login for {} for {} seconds after {} failures", username, lockout, account.failures);
+        account.locked_until = Some(now + Duration::seconds(lockout));
+    }
+
+    pub fn login_succeeded(&self, username: &str) {
+        let mut accounts = self.accounts.lock().unwrap();
+        if let Some(account) = accounts.get_mut(&username.to_lowercase()) {
+            account.failures = 0;
+            account.locked_until = None;
+        }
+    }
+}
+
+/// Middleware refusing requests from addresses that ran out of tokens.
+pub async fn limit_by_ip(State(state): State<Arc<AppState>>, client: ClientInfo, request: Request, next: Next) -> Response {
+    let ip = client.ip.unwrap_or_else(|| String::from("unknown"));
+    if let Err(limited) = state.limiter.check_ip(&ip, state.clock.now()) {
+        info!("rate limit exceeded for {}", ip);
+        return too_many_requests(limited);
+    }
+    next.run(request).await
+}
+
+pub fn too_many_requests(limited: Limited) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, limited.retry_after.into());
+    let template = ErrorsTemplate {errors: vec!["Too many attempts, please try again later"]};
+    (StatusCode::TOO_MANY_REQUESTS, headers, HtmlTemplate(template)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use crate::{config::RateLimitConfig, rate_limit::{RateLimiter, Limited}};
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            ip_burst: 3,
+            ip_per_minute: 6,
+            account_burst: 10,
+            account_per_minute: 10,
+            max_failures: 3,
+            lockout: 60,
+            max_lockout: 200,
+            ..RateLimitConfig::default()
+        })
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    #[test]
+    fn test_bucket_refills() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            assert!(limiter.check_ip("127.0.0.1", now()).is_ok());
+        }
+        assert_eq!(limiter.check_ip("127.0.0.1", now()), Err(Limited { retry_after: 10 }));
+        assert!(limiter.check_ip("127.0.0.2", now()).is_ok());
+        assert!(limiter.check_ip("127.0.0.1", now() + Duration::seconds(10)).is_ok());
+        assert!(limiter.check_ip("127.0.0.1", now() + Duration::seconds(10)).is_err());
+    }
+
+    #[test]
+    fn test_lockout_grows_with_failures() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            assert!(limiter.check_account("Test", now()).is_ok());
+            limiter.login_failed("Test", now());
+        }
+        assert_eq!(limiter.check_account("test", now()), Err(Limited { retry_after: 60 }));
+
+        let later = now() + Duration::seconds(60);
+        assert!(limiter.check_account("Test", later).is_ok());
+        limiter.login_failed("Test", later);
+        assert_eq!(limiter.check_account("Test", later), Err(Limited { retry_after: 120 }));
+
+        let later = later + Duration::seconds(120);
+        assert!(limiter.check_account("Test", later).is_ok());
+        limiter.login_failed("Test", later);
+        assert_eq!(limiter.check_account("Test", later), Err(Limited { retry_after: 200 }));
+    }
+
+    #[test]
+    fn test_successful_login_resets_failures() {
+        let limiter = limiter();
+        for _ in 0..2 {
+            assert!(limiter.check_account("Test", now()).is_ok());
+            limiter.login_failed("Test", now());
+        }
+        limiter.login_succeeded("Test");
+        assert!(limiter.check_account("Test", now()).is_ok());
+        limiter.login_failed("Test", now());
+        assert!(limiter.check_account("Test", now()).is_ok());
+    }
+
+    #[test]
+    fn test_disabled_limiter_allows_everything() {
+        let limiter = RateLimiter::new(RateLimitConfig { enabled: false, ip_burst: 1, ..RateLimitConfig::default() });
+        for _ in 0..5 {
+            assert!(limiter.check_ip("127.0.0.1", now()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const DEFAULT_TARGET: &str = "/user";
+const MAX_LENGTH: usize = 1024;
+
+/// Path on this site to send the user to after logging in or registering.
+///
+/// Only relative paths like `/blog/12` are accepted, so a crafted link can't send
+/// users to another site. Anything else deserializes to `/user` instead of
+/// failing the request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedirectTarget(String);
+
+impl RedirectTarget {
+    pub fn parse(path: &str) -> Option<RedirectTarget> {
+        let valid = path.starts_with('/')
+            // "//host" and "/\host" are read by browsers as links to another host
+            && !path.starts_with("//")
+            && !path.contains('\\')
+            && path.len() <= MAX_LENGTH
+            && path.chars().all(|c| c.is_ascii_graphic());
+        match valid {
+            true => Some(RedirectTarget(String::from(path))),
+            false => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The target percent-encoded for use as a query parameter, e.g. in `/login?path=`.
+    pub fn encoded(&self) -> String {
+        let mut result = String::with_capacity(self.0.len());
+        for byte in self.0.bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => result.push(byte as char),
+                _ => result.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        result
+    }
+}
+
+impl Default for RedirectTarget {
+    fn default() -> Self {
+        RedirectTarget(String::from(DEFAULT_TARGET))
+    }
+}
+
+impl fmt::Display for RedirectTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for RedirectTarget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let path = String::deserialize(deserializer)?;
+        Ok(RedirectTarget::parse(&path).unwrap_or_default())
+    }
+}
+
+impl Serialize for RedirectTarget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::redirect::RedirectTarget;
+
+    #[test]
+    fn test_accepting_relative_paths() {
+        assert!(RedirectTarget::parse("/").is_some());
+        assert!(RedirectTarget::parse("/blog/12").is_some());
+        assert!(RedirectTarget::parse("/community/search?page=2&name=a").is_some());
+    }
+
+    #[test]
+    fn test_rejecting_other_origins() {
+        assert!(RedirectTarget::parse("https://evil.com").is_none());
+        assert!(RedirectTarget::parse("//evil.com").is_none());
+        assert!(RedirectTarget::parse("/\\evil.com").is_none());
+        assert!(RedirectTarget::parse("javascript:alert(1)").is_none());
+        assert!(RedirectTarget::parse("user").is_none());
+        assert!(RedirectTarget::parse("").is_none());
+    }
+
+    #[test]
+    fn test_rejecting_header_injection() {
+        assert!(RedirectTarget::parse("/user\r\nSet-Cookie: Token=x").is_none());
+        assert!(RedirectTarget::parse("/user page").is_none());
+        assert!(RedirectTarget::parse(&format!("/{}", "a".repeat(1024))).is_none());
+    }
+
+    #[test]
+    fn test_encoding_for_query() {
+        let target = RedirectTarget::parse("/community/search?page=2&name=a").unwrap();
+        assert_eq!(target.encoded(), "/community/search%3Fpage%3D2%26name%3Da");
+    }
+}
@@ -0,0 +1,335 @@
+use std::sync::Arc;
+
+use axum::{response::{IntoResponse, Response}, extract::{State, Path, Query}, Form};
+use axum_extra::extract::Multipart;
+use tracing::{info, debug, error};
+use serde::Deserialize;
+
+use crate::{template::{HtmlTemplate, AlbumsTemplate, AlbumTemplate, AlbumPhotosTemplate}, error::AppError, UserData, AppState, auth::{CurrentUser, OptionalUser}, validation::validate_non_empty, AlbumRequest, AlbumModel, CoverRequest, albums::{Visibility, photo_limits, make_photos, save_photos, remove_unused_photos}, images::read_upload};
+
+use super::{friendships::friend_status, verification::verified_email_required, records_to_count, hx_redirect};
+
+const ALBUMS_PAGE_SIZE: i32 = 12;
+const PHOTOS_PAGE_SIZE: i32 = 24;
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    page: Option<i32>,
+}
+
+fn validate_album(request: &AlbumRequest) -> Result<(String, Visibility), AppError> {
+    let mut errors = vec![];
+    if !validate_non_empty(&request.name) {
+        errors.push("Album name cannot be empty!");
+    } else if request.name.as_ref().is_some_and(|name| name.chars().count() > 100) {
+        errors.push("Album name cannot be longer than 100 characters!");
+    }
+    let visibility = match request.visibility.as_deref() {
+        None | Some("") => Some(Visibility::Public),
+        Some(value) => Visibility::parse(value),
+    };
+    if visibility.is_none() {
+        errors.push("Unknown album visibility!");
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+    let name = request.name.as_deref().unwrap_or_default().trim().to_string();
+    Ok((name, visibility.unwrap_or(Visibility::Public)))
+}
+
+/// Album the current user may see. Hidden albums look the same as missing ones.
+async fn visible_album(state: &AppState, current_id: Option<i32>, album_id: i32) -> Result<(AlbumModel, bool), AppError> {
+    let Some(album) = state.storage.albums.find(album_id).await? else {
+        return Err(AppError::NotFound("No such album!"))
+    };
+    let (status, _) = friend_status(&state.storage, current_id, album.user_id).await;
+    if !album.visibility.allows(&status) {
+        return Err(AppError::NotFound("No such album!"))
+    }
+    let owner = current_id == Some(album.user_id);
+    Ok((album, owner))
+}
+
+async fn owned_album(state: &AppState, current: &CurrentUser, album_id: i32, forbidden: &'static str) -> Result<AlbumModel, AppError> {
+    let Some(album) = state.storage.albums.find(album_id).await? else {
+        return Err(AppError::NotFound("No such album!"))
+    };
+    if album.user_id != current.id {
+        return Err(AppError::Forbidden(forbidden))
+    }
+    Ok(album)
+}
+
+pub async fn user_albums(
+    OptionalUser(current): OptionalUser,
+    State(state): State<Arc<AppState>>,
+    Path(username): Path<String>,
+    Query(query): Query<PageQuery>
+    ) -> Result<Response, AppError> {
+    info!("albums of user requested");
+
+    debug!("getting user from database");
+    let Some(user_id) = state.storage.users.find_by_name(&username).await?.and_then(|user| user.id) else {
+        return Err(AppError::NotFound("There is no such user."))
+    };
+    let current_id = current.map(|current| current.id);
+    let (status, _) = friend_status(&state.storage, current_id, user_id).await;
+    let visibilities = Visibility::visible_to(&status);
+
+    let page = query.page.unwrap_or(0).max(0);
+    debug!("getting albums from database");
+    let albums = state.storage.albums.list_by_user(user_id, visibilities, ALBUMS_PAGE_SIZE, page * ALBUMS_PAGE_SIZE).await?;
+    let records = state.storage.albums.count_by_user(user_id, visibilities).await?;
+    let pages = records_to_count(Some(records), ALBUMS_PAGE_SIZE);
+
+    let owner = current_id == Some(user_id);
+    let template = AlbumsTemplate {username, albums, owner, page, pages, media: state.media.clone()};
+    Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn create_album(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<AlbumRequest>
+    ) -> Result<Response, AppError> {
+    info!("creating album requested");
+    let (name, visibility) = validate_album(&request)?;
+    verified_email_required(&state, &current).await?;
+
+    let id = state.storage.albums.create(current.id, &name, visibility).await?;
+    info!("album succesfully created.");
+    Ok(hx_redirect(&format!("/albums/{}", id)))
+}
+
+pub async fn album(
+    user: UserData,
+    OptionalUser(current): OptionalUser,
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<i32>
+    ) -> Result<Response, AppError> {
+    info!("album requested");
+    let (album, owner) = visible_album(&state, current.map(|current| current.id), album_id).await?;
+
+    let Some(author) = state.storage.users.find(album.user_id).await? else {
+        return Err(AppError::NotFound("No such album!"))
+    };
+    let max_photos = state.config.albums.max_photos_per_upload;
+    let template = AlbumTemplate {path: "/album", user, album, username: author.screen_name, owner, max_photos};
+    Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn album_photos(
+    OptionalUser(current): OptionalUser,
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<i32>,
+    Query(query): Query<PageQuery>
+    ) -> Result<Response, AppError> {
+    info!("photos of album requested");
+    let (album, owner) = visible_album(&state, current.map(|current| current.id), album_id).await?;
+
+    let page = query.page.unwrap_or(0).max(0);
+    debug!("getting photos from database");
+    let photos = state.storage.albums.photos(album_id, PHOTOS_PAGE_SIZE, page * PHOTOS_PAGE_SIZE).await?;
+    let records = state.storage.albums.count_photos(album_id).await?;
+    let pages = records_to_count(Some(records), PHOTOS_PAGE_SIZE);
+
+    let template = AlbumPhotosTemplate {album, photos, owner, page, pages, media: state.media.clone()};
+    Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn update_album(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<i32>,
+    Form(request): Form<AlbumRequest>
+    ) -> Result<Response, AppError> {
+    info!("updating album requested");
+    let (name, visibility) = validate_album(&request)?;
+    owned_album(&state, &current, album_id, "You cannot edit this album!").await?;
+
+    state.storage.albums.update(album_id, &name, visibility).await?;
+    info!("album succesfully updated.");
+    Ok(hx_redirect(&format!("/albums/{}", album_id)))
+}
+
+pub async fn delete_album(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<i32>
+    ) -> Result<Response, AppError> {
+    info!("deleting album requested");
+    owned_album(&state, &current, album_id, "You cannot delete this album!").await?;
+
+    let count = state.storage.albums.count_photos(album_id).await?;
+    let photos = state.storage.albums.photos(album_id, count as i32, 0).await?;
+    state.storage.albums.delete(album_id).await?;
+    for photo in photos {
+        remove_unused_photos(&state.storage, state.media.as_ref(), &photo.key).await;
+    }
+
+    info!("album succesfully deleted.");
+    Ok(hx_redirect(&format!("/profile/{}", current.user.screen_name)))
+}
+
+/// Takes image fields each followed by an optional caption. All images are
+/// converted before any of them is stored and the photos are added together,
+/// so a bad file or a failed write adds no photos and leaves no files behind.
+pub async fn upload_photos(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<i32>,
+    mut multipart: Multipart
+    ) -> Result<Response, AppError> {
+    info!("uploading photos requested");
+    owned_album(&state, &current, album_id, "You cannot add photos to this album!").await?;
+    verified_email_required(&state, &current).await?;
+
+    let max_photos = state.config.albums.max_photos_per_upload;
+    let mut uploads: Vec<(Vec<u8>, Option<String>)> = vec![];
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name().unwrap_or_default() {
+            "image" => {
+                // browsers send file inputs left empty as empty parts
+                if field.file_name().is_none_or(str::is_empty) {
+                    continue;
+                }
+                if uploads.len() == max_photos {
+                    return Err(AppError::Validation(vec!["Too many photos at once!"]))
+                }
+                let data = read_upload(field, state.config.assets.max_upload_size).await?;
+                if !data.is_empty() {
+                    uploads.push((data, None));
+                }
+            },
+            "caption" => {
+                let caption = field.text().await.unwrap_or_default();
+                let caption = caption.trim();
+                if let Some(upload) = uploads.last_mut().filter(|upload| upload.1.is_none() && !caption.is_empty()) {
+                    upload.1 = Some(caption.to_string());
+                }
+            },
+            _ => {},
+        }
+    }
+    if uploads.is_empty() {
+        debug!("No image in form!");
+        return Err(AppError::Validation(vec!["Form is empty!"]))
+    }
+
+    let limits = photo_limits(&state.config);
+    let (variants, captions): (Vec<_>, Vec<_>) = tokio::task::spawn_blocking(move || {
+        uploads.into_iter()
+            .map(|(data, caption)| make_photos(&data, &limits).map(|photos| (photos, caption)))
+            .collect::<Result<Vec<_>, AppError>>()
+    })
+        .await
+        .map_err(|_| AppError::Internal("Couldn't convert the image!"))??
+        .into_iter()
+        .unzip();
+
+    let mut photos = Vec::with_capacity(variants.len());
+    for (variant, caption) in variants.iter().zip(captions) {
+        match save_photos(state.media.as_ref(), current.id, variant).await {
+            Ok(key) => photos.push((key, caption)),
+            Err(err) => {
+                error!("couldn't save photo of user {}: {}", current.id, err);
+                discard_photos(&state, &photos).await;
+                return Err(AppError::Internal("Couldn't save file!"))
+            }
+        }
+    }
+    if let Err(err) = state.storage.albums.add_photos(album_id, &photos).await {
+        discard_photos(&state, &photos).await;
+        return Err(err)
+    }
+
+    info!("{} photos succesfully added.", variants.len());
+    Ok(hx_redirect(&format!("/albums/{}", album_id)))
+}
+
+/// Removes the files of photos that didn't make it into the album, unless photos added before show them.
+async fn discard_photos(state: &AppState, photos: &[(String, Option<String>)]) {
+    for (key, _) in photos {
+        remove_unused_photos(&state.storage, state.media.as_ref(), key).await;
+    }
+}
+
+pub async fn set_cover(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<i32>,
+    Form(request): Form<CoverRequest>
+    ) -> Result<Response, AppError> {
+    info!("changing album cover requested");
+    owned_album(&state, &current, album_id, "You cannot edit this album!").await?;
+
+    if let Some(photo_id) = request.photo_id {
+        let photo = state.storage.albums.find_photo(photo_id).await?;
+        if photo.is_none_or(|photo| photo.album_id != album_id) {
+            return Err(AppError::NotFound("No such photo!"))
+        }
+    }
+    state.storage.albums.set_cover(album_id, request.photo_id).await?;
+    Ok(hx_redirect(&format!("/albums/{}", album_id)))
+}
+
+pub async fn delete_photo(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(photo_id): Path<i32>
+    ) -> Result<Response, AppError> {
+    info!("deleting photo requested");
+    let Some(photo) = state.storage.albums.find_photo(photo_id).await? else {
+        return Err(AppError::NotFound("No such photo!"))
+    };
+    owned_album(&state, &current, photo.album_id, "You cannot delete this photo!").await?;
+
+    state.storage.albums.delete_photo(photo_id).await?;
+    remove_unused_photos(&state.storage, state.media.as_ref(), &photo.key).await;
+
+    info!("photo succesfully deleted.");
+    Ok("".into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::{State, Path}, Form};
+
+    use crate::{AlbumRequest, CoverRequest, error::AppError, albums::Visibility, test::{memory_state, memory_user}};
+
+    use super::{create_album, set_cover, delete_photo};
+
+    #[tokio::test]
+    async fn test_creating_album_redirects_to_it() {
+        let state = memory_state();
+        let user = memory_user(&state, "Test").await;
+        let request = AlbumRequest { name: Some(String::from(" Holidays ")), visibility: None };
+
+        let response = create_album(user.clone(), State(state.clone()), Form(request)).await.unwrap();
+
+        let albums = state.storage.albums.all_by_user(user.id).await.unwrap();
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums[0].name, "Holidays");
+        assert_eq!(albums[0].visibility, Visibility::Public);
+        let location = format!("/albums/{}", albums[0].id);
+        assert_eq!(response.headers().get("HX-redirect").unwrap(), location.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_changing_photos_of_other_user() {
+        let state = memory_state();
+        let owner = memory_user(&state, "Test").await;
+        let other = memory_user(&state, "Other").await;
+        let album_id = state.storage.albums.create(owner.id, "Album", Visibility::Public).await.unwrap();
+        let photo_id = state.storage.albums.add_photos(album_id, &[(String::from("photos/1/photo.webp"), None)]).await.unwrap()[0];
+
+        let result = set_cover(other.clone(), State(state.clone()), Path(album_id), Form(CoverRequest { photo_id: Some(photo_id) })).await;
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+        let result = delete_photo(other, State(state.clone()), Path(photo_id)).await;
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+
+        assert!(state.storage.albums.find_photo(photo_id).await.unwrap().is_some());
+        assert_eq!(state.storage.albums.find(album_id).await.unwrap().unwrap().cover_id, None);
+    }
+}
@@ -1,11 +1,12 @@
 use std::sync::Arc;
 
-use axum::{response::IntoResponse, extract::{State, Path, Query}, Form};
-use sqlx::{PgPool, postgres::PgQueryResult, Postgres};
+use axum::{response::{IntoResponse, Response}, extract::{State, Path, Query}, Form};
 use tracing::{info, debug};
 use serde::Deserialize;
 
-use crate::{template::{HtmlTemplate, ErrorsTemplate, CommentsTemplate, CommentFormTemplate, CommentAddResultTemplate, DeletedCommentTemplate}, UserData, AppState, validation::validate_non_empty, CommentRequest, BlogCommentModel, BlogCommentDetails};
+use crate::{template::{HtmlTemplate, CommentsTemplate, CommentFormTemplate, CommentAddResultTemplate, DeletedCommentTemplate}, error::AppError, UserData, AppState, auth::CurrentUser, validation::validate_non_empty, CommentRequest, BlogCommentDetails, storage::Storage};
+
+use super::{records_to_count, PAGE_SIZE};
 
 fn validate_comment(request: &CommentRequest) -> Vec<&'static str> {
     let mut errors = vec![];
@@ -15,234 +16,112 @@ fn validate_comment(request: &CommentRequest) -> Vec<&'static str> {
     return errors;
 }
 
-fn comment_action_validate(user: &UserData, request: &CommentRequest) -> Option<ErrorsTemplate> {
-    if user.username.is_none() {
-        return Some(ErrorsTemplate {errors: vec!["Unauthenticated!"]});
-    }
+fn comment_action_validate(request: &CommentRequest) -> Result<(), AppError> {
     let errors = validate_comment(&request);
     if !errors.is_empty() {
-        return Some(ErrorsTemplate {errors});
+        return Err(AppError::Validation(errors));
     }
-    return None;
-}
-
-pub async fn get_user_by_name(db: &PgPool, username: &String) -> Result<Option<i32>, sqlx::Error> {
-    debug!("getting user from database");
-    return sqlx::query_scalar("SELECT id FROM users WHERE screen_name = $1")
-        .bind(username)
-        .fetch_optional(db)
-        .await;
-}
-
-pub async fn insert_comment(db: &PgPool, user_id: &i32, post_id: &i32, request: &CommentRequest) -> Result<PgQueryResult, String> {
-    debug!("saving comment in database");
-    return sqlx::query("INSERT INTO comments (user_id, post_id, content) VALUES ($1, $2, $3)")
-        .bind(user_id)
-        .bind(post_id)
-        .bind(&request.content)
-        .execute(db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
+    return Ok(());
 }
 
 pub async fn add_comment(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(post_id): Path<i32>,
     Form(request): Form<CommentRequest>,
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("adding blog comment requested");
-    let error = comment_action_validate(&user, &request);
-    if let Some(template) = error {
-        return HtmlTemplate(template).into_response()
-    }
+    comment_action_validate(&request)?;
 
-    let username = user.username.unwrap();
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let query_result = insert_comment(&state.db, &user_id, &post_id, &request).await;
-    if let Err(err) = query_result {
-        debug!("Db error: {:?}", err);
-        if err.contains("constraint") && err.contains("fk_post_id") {
-            let template = ErrorsTemplate {errors: vec!["No such post!"]};
-            return HtmlTemplate(template).into_response()
-        }
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    debug!("saving comment in database");
+    let content = request.content.unwrap();
+    state.storage.comments.create(current.id, post_id, &content).await?;
     info!("comment succesfully created.");
 
     let template = CommentAddResultTemplate {
-        comment: request.content.unwrap(),
-        screen_name: username,
+        comment: content,
+        screen_name: current.user.screen_name,
+        avatar: current.user.avatar_key.as_deref().map(|key| state.media.sized_url(key, 32)),
         id: String::from("comment-form"),
     };
-    return HtmlTemplate(template).into_response()
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn delete_comment(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(comment_id): Path<i32>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("deleting comment requested");
-    if user.username.is_none() {
-        let template = ErrorsTemplate {errors: vec!["Unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
-    }
-
-    debug!("getting user from database");
-    let username = user.username.unwrap();
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
 
     debug!("getting comment from database");
-    let comment_db = sqlx::query_as::<Postgres, BlogCommentModel>("SELECT * FROM comments WHERE id = $1")
-        .bind(&comment_id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(comment) = comment_db else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(comment) = comment else {
-        let template = ErrorsTemplate {errors: vec!["No such comment!"]};
-        return HtmlTemplate(template).into_response()
+    let comment_db = state.storage.comments.find(comment_id).await?;
+    let Some(comment) = comment_db else {
+        return Err(AppError::NotFound("No such comment!"))
     };
 
-    if &comment.user_id != &user_id {
-        let template = ErrorsTemplate {errors: vec!["You cannot delete this comment!"]};
-        return HtmlTemplate(template).into_response()
+    if comment.user_id != current.id {
+        return Err(AppError::Forbidden("You cannot delete this comment!"))
     }
 
-    let query_result = sqlx::query("DELETE FROM comments WHERE id = $1")
-        .bind(&comment.id)
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
-
-    if let Err(_) = query_result {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    }
+    state.storage.comments.delete(comment_id).await?;
 
     info!("comment succesfully deleted.");
     let template = DeletedCommentTemplate {id: comment.id.unwrap()};
-    return HtmlTemplate(template).into_response()
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn edit_comment(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(comment_id): Path<i32>,
     Form(request): Form<CommentRequest>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("updating blog comment requested");
-    let error = comment_action_validate(&user, &request);
-    if let Some(template) = error {
-        return HtmlTemplate(template).into_response()
-    }
-
-    debug!("getting user from database");
-    let username = user.username.unwrap();
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    comment_action_validate(&request)?;
 
     debug!("getting comment from database");
-    let comment_db = sqlx::query_as::<Postgres, BlogCommentModel>("SELECT * FROM comments WHERE id = $1")
-        .bind(&comment_id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(comment) = comment_db else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(comment) = comment else {
-        let template = ErrorsTemplate {errors: vec!["No such comment!"]};
-        return HtmlTemplate(template).into_response()
+    let comment_db = state.storage.comments.find(comment_id).await?;
+    let Some(comment) = comment_db else {
+        return Err(AppError::NotFound("No such comment!"))
     };
 
-    if &comment.user_id != &user_id {
-        let template = ErrorsTemplate {errors: vec!["You cannot edit this comment!"]};
-        return HtmlTemplate(template).into_response()
+    if comment.user_id != current.id {
+        return Err(AppError::Forbidden("You cannot edit this comment!"))
     }
 
-    let query_result = sqlx::query("UPDATE comments SET content = $1 WHERE id = $2")
-        .bind(&request.content)
-        .bind(&comment_id)
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
-
-    if let Err(_) = query_result {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    }
+    let content = request.content.unwrap();
+    state.storage.comments.update(comment_id, &content).await?;
     info!("comment succesfully updated.");
     let template = CommentAddResultTemplate {
-        comment: request.content.unwrap(),
-        screen_name: username,
+        comment: content,
+        screen_name: current.user.screen_name,
+        avatar: current.user.avatar_key.as_deref().map(|key| state.media.sized_url(key, 32)),
         id: format!("comment-{}", comment_id)
     };
-    return HtmlTemplate(template).into_response()
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn comments_for_post(
     user: UserData,
     State(state): State<Arc<AppState>>,
     Path(post_id): Path<i32>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("comments requested");
 
     debug!("getting comments from database");
-    let comments = get_comments(&state.db, post_id, 0).await;
-    match comments {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((comments, records)) => {
-            let pages = records_to_count(records);
-            let template = CommentsTemplate {comments, pages, post_id, page: 0, user};
-            return HtmlTemplate(template).into_response()
-        }
-    };
+    let (comments, records) = get_comments(&state.storage, post_id, 0).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let template = CommentsTemplate {comments, pages, post_id, page: 0, user, media: state.media.clone()};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
-async fn get_comments(db: &PgPool, post_id: i32, page: i32) -> Result<(Vec<BlogCommentDetails>, Option<i64>), sqlx::Error> {
+async fn get_comments(storage: &Storage, post_id: i32, page: i32) -> Result<(Vec<BlogCommentDetails>, Option<i64>), AppError> {
     let page_size = 25;
     let offset = page_size * page;
-    let users = sqlx::query_as::<Postgres, BlogCommentDetails>(
-        "SELECT c.*, u.screen_name 
-        FROM comments c 
-        LEFT JOIN users u
-        ON c.user_id = u.id
-        WHERE c.post_id = $1 
-        ORDER BY c.created_at
-        LIMIT $2 OFFSET $3 "
-        )
-        .bind(&post_id)
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(db)
-        .await?;
-
-    let records: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM comments
-        WHERE post_id = $1")
-        .bind(&post_id)
-        .fetch_one(db)
-        .await?;
-    
-    Ok((users, Some(records)))
+    let comments = storage.comments.list_for_post(post_id, page_size, offset).await?;
+    let records = storage.comments.count_for_post(post_id).await?;
+    Ok((comments, Some(records)))
 }
 
 #[derive(Deserialize)]
@@ -255,54 +134,24 @@ pub async fn comments_page(
     Query(query): Query<SearchQuery>,
     State(state): State<Arc<AppState>>,
     Path(post_id): Path<i32>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
 
     debug!("getting comments from database");
-    let comments = get_comments(&state.db, post_id, query.page).await;
-    match comments {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((comments, results)) => {
-            let pages = records_to_count(results);
-            let template = CommentsTemplate {comments, pages, post_id, page: query.page, user };
-            return HtmlTemplate(template).into_response()
-        }
-    };
-}
-
-fn records_to_count(records: Option<i64>) -> i32 {
-    match records {
-        None => 0,
-        Some(count) => {
-            let count = (count as f64)/25.0;
-            let count = count.ceil() as i32;
-            count
-        }
-    }
+    let (comments, results) = get_comments(&state.storage, post_id, query.page).await?;
+    let pages = records_to_count(results, PAGE_SIZE);
+    let template = CommentsTemplate {comments, pages, post_id, page: query.page, user, media: state.media.clone()};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn comment_form(
     State(state): State<Arc<AppState>>,
     Path(comment_id): Path<i32>
-    ) -> impl IntoResponse {
-    let comment_db = sqlx::query_as::<Postgres, BlogCommentModel>(
-        "SELECT * FROM comments WHERE id = $1")
-        .bind(&comment_id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(comment) = comment_db else {
-        debug!("Db error: {:?}", comment_db);
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(comment) = comment else {
-        let template = ErrorsTemplate {errors: vec!["No such comment!"]};
-        return HtmlTemplate(template).into_response()
+    ) -> Result<Response, AppError> {
+    let comment_db = state.storage.comments.find(comment_id).await?;
+    let Some(comment) = comment_db else {
+        return Err(AppError::NotFound("No such comment!"))
     };
 
     let template = CommentFormTemplate {comment, comment_id};
-    return HtmlTemplate(template).into_response()
+    return Ok(HtmlTemplate(template).into_response())
 }
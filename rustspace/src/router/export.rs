@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::HeaderMap, response::{IntoResponse, Response}};
+use tracing::{info, debug};
+
+use crate::{template::{HtmlTemplate, ExportFieldTemplate}, error::AppError, AppState, auth::CurrentUser, export::{latest_export, start_export, archive_path}};
+
+pub async fn request_export(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    info!("data export requested");
+    let latest = latest_export(&state.db, current.id, state.clock.now()).await?;
+    if let Some(export) = latest.filter(|export| export.completed_at.is_none()) {
+        debug!("export already in progress");
+        let template = ExportFieldTemplate {export: Some(export)};
+        return Ok(HtmlTemplate(template).into_response())
+    }
+
+    let export = start_export(&state, current.user, current.id).await?;
+    let template = ExportFieldTemplate {export: Some(export)};
+    Ok(HtmlTemplate(template).into_response())
+}
+
+/// Polled by the account page while the archive is being built.
+pub async fn export_status(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let export = latest_export(&state.db, current.id, state.clock.now()).await?;
+    let template = ExportFieldTemplate {export};
+    Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn download_export(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    info!("data export download requested");
+    let latest = latest_export(&state.db, current.id, state.clock.now()).await?;
+    let Some(export) = latest.filter(|export| export.completed_at.is_some()) else {
+        return Err(AppError::NotFound("No export ready for download!"))
+    };
+    let Ok(archive) = tokio::fs::read(archive_path(&state.config.exports, export.id)).await else {
+        return Err(AppError::NotFound("No export ready for download!"))
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", "application/zip".parse().unwrap());
+    let filename = format!("attachment; filename=\"rustspace-{}.zip\"", export.created_at.format("%Y-%m-%d"));
+    headers.insert("Content-Disposition", filename.parse().unwrap());
+    headers.insert("Cache-Control", "no-store".parse().unwrap());
+    Ok((headers, archive).into_response())
+}
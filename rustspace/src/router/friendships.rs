@@ -1,190 +1,96 @@
 use std::sync::Arc;
 
-use axum::{response::IntoResponse, extract::{State, Path, Query}, Form};
-use sqlx::{Postgres, PgPool};
+use axum::{response::{IntoResponse, Response}, extract::{State, Path, Query}, Form};
 use tracing::{info, debug};
 use chrono::Utc;
 use serde::Deserialize;
 
-use crate::{template::{HtmlTemplate, ErrorsTemplate, UnauthorizedTemplate, FriendRequestsTemplate, FriendsTemplate, FriendRequestsResultsTemplate, InvitedTemplate, RejectedFriendRequestsTemplate, RejectedRequestsResultsTemplate, RequestResultTemplate, FriendsResultTemplate}, UserData, AppState, UserModel, FriendshipModel, FriendshipRequest, FriendshipStateRequest, validation::validate_non_empty, FriendshipDetails};
+use crate::{template::{HtmlTemplate, FriendRequestsTemplate, FriendsTemplate, FriendRequestsResultsTemplate, InvitedTemplate, RejectedFriendRequestsTemplate, RejectedRequestsResultsTemplate, RequestResultTemplate, FriendsResultTemplate, FriendStatus}, error::{AppError, Conflict}, UserData, AppState, auth::CurrentUser, FriendshipRequest, FriendshipStateRequest, validation::validate_non_empty, FriendshipDetails, storage::Storage};
+
+use super::{verification::verified_email_required, records_to_count, PAGE_SIZE};
+
+/// How the visitor stands to the user, with the id of a friendship the visitor cancelled
+/// and may renew. Visitors who aren't logged in are no friends.
+pub async fn friend_status(storage: &Storage, current_id: Option<i32>, user_id: i32) -> (FriendStatus, Option<i32>) {
+    let Some(current_id) = current_id else {
+        return (FriendStatus::NotFriend, None)
+    };
+    if current_id == user_id {
+        return (FriendStatus::User, None)
+    }
+    match storage.friendships.find_between(current_id, user_id).await {
+        Ok(Some(cancelled)) if cancelled.cancelled => (FriendStatus::Cancelled, cancelled.id),
+        Ok(Some(accepted)) if accepted.accepted => (FriendStatus::Friend, None),
+        Ok(Some(rejected)) if rejected.rejected => (FriendStatus::Rejector, None),
+        Ok(Some(_)) => (FriendStatus::Invitee, None),
+        Ok(None) => (FriendStatus::NotFriend, None),
+        Err(_) => (FriendStatus::NotFriend, None)
+    }
+}
 
 pub async fn send_friend_request(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Form(request): Form<FriendshipRequest>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("sending friend request requested");
-    if user.username.is_none() {
-        let template = ErrorsTemplate {errors: vec!["Unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
-    }
+    verified_email_required(&state, &current).await?;
 
     if !validate_non_empty(&request.username) {
-        let template = ErrorsTemplate {errors: vec!["Username cannot be empty!"]};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::Validation(vec!["Username cannot be empty!"]))
     }
 
     let username = request.username.unwrap();
 
-    if let Some(logged) = &user.username {
-       if logged == &username {
-            let template = ErrorsTemplate {errors: vec!["You cannot befriend yourself!"]};
-            return HtmlTemplate(template).into_response()
-        }
+    if current.user.screen_name == username {
+        return Err(AppError::Validation(vec!["You cannot befriend yourself!"]))
     }
 
-    debug!("getting user from database");
-    let user_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&user.username)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(Some(user)) = user_db else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let friend_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&username)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(friend) = friend_db else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(friend) = friend else {
-        let template = ErrorsTemplate {errors: vec!["User not found!"]};
-        return HtmlTemplate(template).into_response()
+    debug!("getting friend from database");
+    let friend = state.storage.users.find_by_name(&username).await?;
+    let Some(friend_id) = friend.and_then(|friend| friend.id) else {
+        return Err(AppError::NotFound("User not found!"))
     };
 
-    let friendship = sqlx::query_as::<Postgres, FriendshipModel>(
-        "SELECT * FROM friendships WHERE (user_id = $1 AND friend_id = $2) OR (user_id = $2 AND friend_id = $1)",
-        )
-        .bind(&friend.id)
-        .bind(&user.id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(friendship) = friendship else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    let friendship = state.storage.friendships.find_between(current.id, friend_id).await?;
 
     match friendship {
-        Some(accepted) if accepted.accepted =>  {
-            let template = ErrorsTemplate {errors: vec!["You're already friends!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Some(rejected) if rejected.rejected => {
-            let template = ErrorsTemplate {errors: vec!["User already have rejected your request!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Some(_) => {
-            let template = ErrorsTemplate {errors: vec!["Request already created!"]};
-            return HtmlTemplate(template).into_response()
-        },
+        Some(accepted) if accepted.accepted => return Err(AppError::Conflict(Conflict::AlreadyFriends)),
+        Some(rejected) if rejected.rejected => return Err(AppError::Conflict(Conflict::FriendRequestRejected)),
+        Some(_) => return Err(AppError::Conflict(Conflict::FriendRequestPending)),
         None => {
-            let query_result =
-                sqlx::query("INSERT INTO friendships (user_id, friend_id) VALUES ($1, $2)")
-                .bind(&user.id)
-                .bind(&friend.id)
-                .execute(&state.db)
-                .await
-                .map_err(|err: sqlx::Error| err.to_string());
-
-            if let Err(_) = query_result {
-                let template = ErrorsTemplate {errors: vec!["Couldn't send request!"]};
-                return HtmlTemplate(template).into_response()
-            }
+            state.storage.friendships.create(current.id, friend_id).await?;
             info!("request succesfully created.");
             let template = InvitedTemplate {};
-            return HtmlTemplate(template).into_response()
+            return Ok(HtmlTemplate(template).into_response())
         }
     }
 }
 
-async fn get_friend_requests(db: &PgPool, user_id: i32, page: i32) -> Result<(Vec<FriendshipDetails>, Option<i64>), sqlx::Error> {
+async fn get_friend_requests(storage: &Storage, user_id: i32, page: i32) -> Result<(Vec<FriendshipDetails>, Option<i64>), AppError> {
     let page_size = 25;
     let offset = page_size * page;
-    let users = sqlx::query_as::<Postgres, FriendshipDetails>(
-        "SELECT f.id, u.screen_name, f.accepted, f.rejected, f.cancelled, f.created_at
-        FROM users u
-        LEFT JOIN friendships f ON u.id = f.user_id
-        WHERE f.friend_id = $3 AND f.accepted = false AND f.rejected = false AND f.cancelled = false
-        ORDER BY f.created_at
-        LIMIT $1 OFFSET $2"
-        )
-        .bind(page_size)
-        .bind(offset)
-        .bind(user_id)
-        .fetch_all(db)
-        .await?;
-
-    let records: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM friendships f
-        WHERE f.friend_id = $1 AND f.accepted = false AND f.rejected = false AND f.cancelled = false")
-        .bind(user_id)
-        .fetch_one(db)
-        .await?;
-    
+    let users = storage.friendships.pending(user_id, page_size, offset).await?;
+    let records = storage.friendships.count_pending(user_id).await?;
     Ok((users, Some(records)))
 }
 
-async fn get_friends(db: &PgPool, user_id: i32, page: i32) -> Result<(Vec<FriendshipDetails>, Option<i64>), sqlx::Error> {
+async fn get_friends(storage: &Storage, user_id: i32, page: i32) -> Result<(Vec<FriendshipDetails>, Option<i64>), AppError> {
     let page_size = 25;
     let offset = page_size * page;
-    let users = sqlx::query_as::<Postgres, FriendshipDetails>(
-        "SELECT f.id, u.screen_name, f.accepted, f.rejected, f.cancelled, f.created_at
-        FROM users u
-        LEFT JOIN friendships f ON u.id = f.friend_id
-        WHERE f.user_id = $3 AND f.accepted = true AND f.cancelled = false
-        UNION
-        SELECT fr.id, us.screen_name, fr.accepted, fr.rejected, fr.cancelled, fr.created_at
-        FROM users us
-        LEFT JOIN friendships fr ON us.id = fr.user_id
-        WHERE fr.friend_id = $3 AND fr.accepted = true AND fr.cancelled = false
-        ORDER BY created_at
-        LIMIT $1 OFFSET $2"
-        )
-        .bind(page_size)
-        .bind(offset)
-        .bind(user_id)
-        .fetch_all(db)
-        .await?;
-
-    let records: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM friendships f
-        WHERE (f.user_id = $1 OR f.friend_id = $1) AND f.accepted = true AND f.cancelled = false")
-        .bind(user_id)
-        .fetch_one(db)
-        .await?;
-    
+    let users = storage.friendships.friends(user_id, page_size, offset).await?;
+    let records = storage.friendships.count_friends(user_id).await?;
     Ok((users, Some(records)))
 }
 
 pub async fn requests(
     user: UserData,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    if user.username.is_none() {
-        let template = UnauthorizedTemplate {message: "You're unauthenticated!", redir: Some(String::from("/friends/requests"))};
-        return HtmlTemplate(template).into_response()
-    }
-
-    let Some(user_id) = get_user_id(&state.db, &user).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let users = get_friend_requests(&state.db, user_id, 0).await;
-    match users {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((friends, records)) => {
-            let pages = records_to_count(records);
-            let template = FriendRequestsTemplate {path: "/friends", friends, pages, user, records };
-            return HtmlTemplate(template).into_response()
-        }
-    };
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (friends, records) = get_friend_requests(&state.storage, current.id, 0).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let template = FriendRequestsTemplate {path: "/friends", friends, pages, user, records };
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 #[derive(Deserialize)]
@@ -193,131 +99,42 @@ pub struct SearchQuery {
 }
 
 pub async fn requests_page(
-    user: UserData,
+    current: CurrentUser,
     Query(query): Query<SearchQuery>,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    if user.username.is_none() {
-        let template = ErrorsTemplate {errors: vec!["You're unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
-    }
-
-    let Some(user_id) = get_user_id(&state.db, &user).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let users = get_friend_requests(&state.db, user_id, query.page).await;
-    match users {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((friends, records)) => {
-            let pages = records_to_count(records);
-            let template = FriendRequestsResultsTemplate {friends, pages, page: query.page};
-            return HtmlTemplate(template).into_response()
-        }
-    };
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (friends, records) = get_friend_requests(&state.storage, current.id, query.page).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let template = FriendRequestsResultsTemplate {friends, pages, page: query.page};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn friends(
     user: UserData,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    if user.username.is_none() {
-        let template = UnauthorizedTemplate {message: "You're unauthenticated!", redir: Some(String::from("/friends"))};
-        return HtmlTemplate(template).into_response()
-    }
-
-    let Some(user_id) = get_user_id(&state.db, &user).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let users = get_friends(&state.db, user_id, 0).await;
-    match users {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((friends, records)) => {
-            let pages = records_to_count(records);
-            let template = FriendsTemplate {path: "/friends", friends, pages, user, records };
-            return HtmlTemplate(template).into_response()
-        }
-    };
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (friends, records) = get_friends(&state.storage, current.id, 0).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let template = FriendsTemplate {path: "/friends", friends, pages, user, records, media: state.media.clone()};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn friends_page(
-    user: UserData,
+    current: CurrentUser,
     Query(query): Query<SearchQuery>,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    if user.username.is_none() {
-        let template = ErrorsTemplate {errors: vec!["You're unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
-    }
-
-    let Some(user_id) = get_user_id(&state.db, &user).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let users = get_friends(&state.db, user_id, query.page).await;
-    match users {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((friends, records)) => {
-            let pages = records_to_count(records);
-            let template = FriendsResultTemplate {friends, page: query.page, pages};
-            return HtmlTemplate(template).into_response()
-        }
-    };
-}
-
-pub async fn get_user_id(db: &PgPool, user: &UserData) -> Option<i32> {
-    let user_db = sqlx::query_as::<Postgres, UserModel>(
-        "SELECT * FROM users WHERE screen_name = $1",
-        )
-        .bind(&user.username)
-        .fetch_optional(db)
-        .await;
-
-    let Ok(Some(user_db)) = user_db else {
-        return None;
-    };
-
-    let Some(user_id) = user_db.id else {
-        return None;
-    };
-    Some(user_id)
-}
-
-fn records_to_count(records: Option<i64>) -> i32 {
-    match records {
-        None => 0,
-        Some(count) => {
-            let count = (count as f64)/25.0;
-            let count = count.ceil() as i32;
-            count
-        }
-    }
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (friends, records) = get_friends(&state.storage, current.id, query.page).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let template = FriendsResultTemplate {friends, page: query.page, pages, media: state.media.clone()};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn change_request_state(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(request_id): Path<i32>,
     Form(request): Form<FriendshipStateRequest>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("sending friend request requested");
-    if user.username.is_none() {
-        let template = ErrorsTemplate {errors: vec!["Unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
-    }
 
     let (accepted, rejected, error) = match request.state {
         None => (false, false, Some("State cannot be empty!")),
@@ -327,38 +144,13 @@ pub async fn change_request_state(
     };
 
     if let Some(error) = error {
-        let template = ErrorsTemplate {errors: vec![error]};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::Validation(vec![error]))
     };
 
-    let user_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&user.username)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(Some(user)) = user_db else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(user_id) = user.id else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let friendship = sqlx::query_as::<Postgres, FriendshipModel>(
-        "SELECT * FROM friendships WHERE id = $1",
-        )
-        .bind(&request_id)
-        .fetch_optional(&state.db)
-        .await;
-
-    let Ok(friendship) = friendship else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    let friendship = state.storage.friendships.find(request_id).await?;
 
     let Some(friendship) = friendship else {
-        let template = ErrorsTemplate {errors: vec!["No such request!"]};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::NotFound("No such request!"))
     };
 
 
@@ -367,134 +159,106 @@ pub async fn change_request_state(
         false => friendship.accepted_at
     };
 
-    if &friendship.friend_id != &user_id {
+    if friendship.friend_id != current.id {
         if (friendship.cancelled && rejected) || (!friendship.cancelled && accepted) {
-            let template = ErrorsTemplate {errors: vec!["State is already set!"]};
-            return HtmlTemplate(template).into_response()
-        }
-        let result = sqlx::query("UPDATE friendships SET cancelled = $1, accepted_at = $2 WHERE id = $3")
-            .bind(&rejected)
-            .bind(&accepted_at)
-            .bind(&request_id)
-            .execute(&state.db)
-            .await
-            .map_err(|err: sqlx::Error| err.to_string());
-        if let Ok(_) = result {
-            let template = RequestResultTemplate {id: request_id, accepted};
-            return HtmlTemplate(template).into_response()
-        } else {
-            let template = ErrorsTemplate {errors: vec!["Database error, please try again later"]};
-            return HtmlTemplate(template).into_response()
+            return Err(AppError::Conflict(Conflict::StateAlreadySet))
         }
+        state.storage.friendships.set_cancelled(request_id, rejected, accepted_at).await?;
+        let template = RequestResultTemplate {id: request_id, accepted};
+        return Ok(HtmlTemplate(template).into_response())
     }
 
-    if &friendship.user_id != &user_id {
-        let template = ErrorsTemplate {errors: vec!["You cannot change state of friendship you aren't part of!"]};
-        return HtmlTemplate(template).into_response()
+    if friendship.user_id != current.id {
+        return Err(AppError::Forbidden("You cannot change state of friendship you aren't part of!"))
     }
     
     if (friendship.rejected && rejected) || (friendship.accepted && accepted) {
-        let template = ErrorsTemplate {errors: vec!["State is already set!"]};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::Conflict(Conflict::StateAlreadySet))
     }
 
-    let result = sqlx::query("UPDATE friendships SET accepted = $1, rejected = $2, accepted_at = $3 WHERE id = $4")
-        .bind(&accepted)
-        .bind(&rejected)
-        .bind(&accepted_at)
-        .bind(&request_id)
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
-    if let Ok(_) = result {
-        let template = RequestResultTemplate {id: request_id, accepted};
-        return HtmlTemplate(template).into_response()
-    } else {
-        let template = ErrorsTemplate {errors: vec!["Database error, please try again later"]};
-        return HtmlTemplate(template).into_response()
-    }
+    state.storage.friendships.set_state(request_id, accepted, rejected, accepted_at).await?;
+    let template = RequestResultTemplate {id: request_id, accepted};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
-async fn get_rejected_requests(db: &PgPool, user_id: i32, page: i32) -> Result<(Vec<FriendshipDetails>, Option<i64>), sqlx::Error> {
+async fn get_rejected_requests(storage: &Storage, user_id: i32, page: i32) -> Result<(Vec<FriendshipDetails>, Option<i64>), AppError> {
     let page_size = 25;
     let offset = page_size * page;
-    let users = sqlx::query_as::<Postgres, FriendshipDetails>(
-        "SELECT f.id, u.screen_name, f.accepted, f.rejected, f.cancelled, f.created_at
-        FROM users u
-        LEFT JOIN friendships f ON u.id = f.user_id
-        WHERE f.friend_id = $3 AND f.rejected = true OR f.cancelled = true
-        ORDER BY f.created_at
-        LIMIT $1 OFFSET $2"
-        )
-        .bind(page_size)
-        .bind(offset)
-        .bind(user_id)
-        .fetch_all(db)
-        .await?;
-
-    let records: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM friendships f
-        WHERE f.friend_id = $1 AND f.rejected = true OR f.cancelled = true")
-        .bind(user_id)
-        .fetch_one(db)
-        .await?;
-    
+    let users = storage.friendships.rejected(user_id, page_size, offset).await?;
+    let records = storage.friendships.count_rejected(user_id).await?;
     Ok((users, Some(records)))
 }
 
 pub async fn rejected_requests(
     user: UserData,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    if user.username.is_none() {
-        let template = UnauthorizedTemplate {message: "You're unauthenticated!", redir: Some(String::from("/friends/requests/rejected"))};
-        return HtmlTemplate(template).into_response()
-    }
-
-    let Some(user_id) = get_user_id(&state.db, &user).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let users = get_rejected_requests(&state.db, user_id, 0).await;
-    match users {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((friends, records)) => {
-            let pages = records_to_count(records);
-            let template = RejectedFriendRequestsTemplate {path: "/friends", friends, pages, user, records };
-            return HtmlTemplate(template).into_response()
-        }
-    };
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (friends, records) = get_rejected_requests(&state.storage, current.id, 0).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let template = RejectedFriendRequestsTemplate {path: "/friends", friends, pages, user, records };
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn rejected_page(
-    user: UserData,
+    current: CurrentUser,
     Query(query): Query<SearchQuery>,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    if user.username.is_none() {
-        let template = ErrorsTemplate {errors: vec!["You're unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (friends, records) = get_rejected_requests(&state.storage, current.id, query.page).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let template = RejectedRequestsResultsTemplate {friends, pages, page: query.page};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::{State, Path}, Form};
+
+    use crate::{FriendshipRequest, FriendshipStateRequest, error::{AppError, Conflict}, test::{memory_state, memory_user}};
+
+    use super::{send_friend_request, change_request_state};
+
+    fn invite(username: &str) -> Form<FriendshipRequest> {
+        Form(FriendshipRequest { username: Some(String::from(username)) })
     }
 
-    let Some(user_id) = get_user_id(&state.db, &user).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    #[tokio::test]
+    async fn test_befriending_yourself() {
+        let state = memory_state();
+        let user = memory_user(&state, "Test").await;
 
-    let users = get_rejected_requests(&state.db, user_id, query.page).await;
-    match users {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((friends, records)) => {
-            let pages = records_to_count(records);
-            let template = RejectedRequestsResultsTemplate {friends, pages, page: query.page};
-            return HtmlTemplate(template).into_response()
-        }
-    };
+        let result = send_friend_request(user, State(state), invite("Test")).await;
+
+        assert!(matches!(result, Err(AppError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sending_request_twice() {
+        let state = memory_state();
+        let user = memory_user(&state, "Test").await;
+        let friend = memory_user(&state, "Friend").await;
+
+        assert!(send_friend_request(user.clone(), State(state.clone()), invite("Friend")).await.is_ok());
+        let result = send_friend_request(user.clone(), State(state.clone()), invite("Friend")).await;
+
+        assert!(matches!(result, Err(AppError::Conflict(Conflict::FriendRequestPending))));
+        assert_eq!(state.storage.friendships.count_pending(friend.id).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelling_request() {
+        let state = memory_state();
+        let user = memory_user(&state, "Test").await;
+        let friend = memory_user(&state, "Friend").await;
+        state.storage.friendships.create(user.id, friend.id).await.unwrap();
+        let request = state.storage.friendships.find_between(user.id, friend.id).await.unwrap().unwrap();
+
+        let cancel = Form(FriendshipStateRequest { state: Some(String::from("rejected")) });
+        assert!(change_request_state(user.clone(), State(state.clone()), Path(request.id.unwrap()), cancel).await.is_ok());
+
+        assert_eq!(state.storage.friendships.count_pending(friend.id).await.unwrap(), 0);
+        let rejected = state.storage.friendships.rejected(friend.id, 25, 0).await.unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].screen_name, "Test");
+        assert!(rejected[0].cancelled);
+    }
 }
@@ -1,13 +1,15 @@
 use std::sync::Arc;
 
-use axum::{Router, routing::{get, post, put, delete}};
+use axum::{Router, routing::{get, post, put, delete}, middleware, extract::DefaultBodyLimit, http::{header, HeaderMap, HeaderValue}, response::{IntoResponse, Response}};
+use tower::ServiceBuilder;
+use tower_http::{services::ServeDir, set_header::SetResponseHeaderLayer};
 
-use crate::AppState;
+use crate::{AppState, config::MediaBackend, media::MEDIA_PATH, session::apply_pending_cookies, rate_limit::limit_by_ip, csrf::csrf_protection, headers::security_headers, error::error_pages};
 
 use self::{
     main::{root, about, help},
-    user::{user_page, register_form, register_user, check_password, check_username, check_email, check_password_repeat, login_form, login, logout, to_login, edit_email, edit_password, update_email, update_password, edit_avatar, upload_avatar, delete_avatar}, 
-    profile::{profile, edit_profile, update_profile}, community::{community, get_users_page, search_users, get_search_users_page}, friendships::{send_friend_request, friends, requests, change_request_state, requests_page, friends_page, rejected_requests, rejected_page}, post::{add_post, delete_post, edit_post, get_post, get_users_posts, posts_page, post_form, new_posts, edit_post_form}, comment::{add_comment, delete_comment, edit_comment, comments_for_post, comments_page, comment_form}
+    user::{user_page, register_form, register_user, check_password, check_username, check_email, check_password_repeat, login_form, login, logout, to_login, edit_username, update_username, edit_email, edit_password, update_email, update_password, edit_avatar, upload_avatar, delete_avatar, edit_banner, upload_banner, delete_banner, edit_delete_account, delete_account}, 
+    profile::{profile, edit_profile, update_profile}, community::{community, get_users_page, search_users, get_search_users_page}, friendships::{send_friend_request, friends, requests, change_request_state, requests_page, friends_page, rejected_requests, rejected_page}, post::{add_post, delete_post, edit_post, get_post, get_users_posts, posts_page, post_form, new_posts, edit_post_form}, comment::{add_comment, delete_comment, edit_comment, comments_for_post, comments_page, comment_form}, session::{sessions, delete_session, delete_all_sessions}, password::{forgot_password_form, forgot_password, reset_password_form, reset_password}, verification::{verify_email, resend_verification}, two_factor::{edit_two_factor, enable_two_factor, edit_disable_two_factor, disable_two_factor, two_factor_login_form, two_factor_login}, export::{request_export, export_status, download_export}, album::{user_albums, create_album, album, album_photos, update_album, delete_album, upload_photos, set_cover, delete_photo}
 };
 mod main;
 mod user;
@@ -16,28 +18,48 @@ mod community;
 mod friendships;
 mod post;
 mod comment;
+mod session;
+mod password;
+mod verification;
+mod two_factor;
+mod export;
+mod album;
 
-pub fn get_router() -> Router<Arc<AppState>> {
-    Router::new()
+pub fn get_router(state: Arc<AppState>) -> Router {
+    let router = Router::new()
+        .merge(limited_routes(state.clone()))
         .route("/", get(root))
         .route("/index", get(root))
         .route("/about", get(about))
         .route("/help", get(help))
         .route("/register", get(register_form))
-        .route("/register", post(register_user))
         .route("/login", get(login_form))
-        .route("/login", post(login))
+        .route("/login/2fa", get(two_factor_login_form))
         .route("/to_login", get(to_login))
         .route("/logout", get(logout))
-        .route("/validation/psw", post(check_password))
-        .route("/validation/username", post(check_username))
-        .route("/validation/email", post(check_email))
-        .route("/validation/psw_repeat", post(check_password_repeat))
+        .route("/password/forgot", get(forgot_password_form))
+        .route("/password/reset", get(reset_password_form))
         .route("/user", get(user_page))
+        .route("/user/sessions", get(sessions))
+        .route("/user/sessions", delete(delete_all_sessions))
+        .route("/user/sessions/:id", delete(delete_session))
+        .route("/forms/username", get(edit_username))
+        .route("/username", put(update_username))
         .route("/forms/email", get(edit_email))
         .route("/email", put(update_email))
+        .route("/email/verify", get(verify_email))
+        .route("/email/verify", post(resend_verification))
         .route("/forms/password", get(edit_password))
         .route("/password", put(update_password))
+        .route("/forms/2fa", get(edit_two_factor))
+        .route("/2fa", post(enable_two_factor))
+        .route("/forms/2fa/disable", get(edit_disable_two_factor))
+        .route("/2fa/disable", post(disable_two_factor))
+        .route("/forms/account/delete", get(edit_delete_account))
+        .route("/user/delete", post(delete_account))
+        .route("/user/export", post(request_export))
+        .route("/user/export", get(download_export))
+        .route("/user/export/status", get(export_status))
         .route("/profile/:username", get(profile))
         .route("/forms/profile", get(edit_profile))
         .route("/profile", put(update_profile))
@@ -46,8 +68,11 @@ pub fn get_router() -> Router<Arc<AppState>> {
         .route("/community/users", get(search_users))
         .route("/community/users/search", get(get_search_users_page))
         .route("/forms/avatar", get(edit_avatar))
-        .route("/avatar", post(upload_avatar))
+        .route("/avatar", post(upload_avatar).layer(upload_limit(&state)))
         .route("/avatar", delete(delete_avatar))
+        .route("/forms/banner", get(edit_banner))
+        .route("/banner", post(upload_banner).layer(upload_limit(&state)))
+        .route("/banner", delete(delete_banner))
         .route("/friendships", post(send_friend_request))
         .route("/friends", get(friends))
         .route("/friends/page", get(friends_page))
@@ -71,4 +96,70 @@ pub fn get_router() -> Router<Arc<AppState>> {
         .route("/blog/comment/:id/edit", get(comment_form))
         .route("/blog/:id/comments", get(comments_for_post))
         .route("/blog/:id/comments/page", get(comments_page))
+        .route("/user/:username/albums", get(user_albums))
+        .route("/albums", post(create_album))
+        .route("/albums/:id", get(album))
+        .route("/albums/:id", put(update_album))
+        .route("/albums/:id", delete(delete_album))
+        .route("/albums/:id/photos", get(album_photos))
+        .route("/albums/:id/photos", post(upload_photos).layer(photos_upload_limit(&state)))
+        .route("/albums/:id/cover", put(set_cover))
+        .route("/albums/photos/:id", delete(delete_photo))
+        .layer(middleware::from_fn(error_pages))
+        .layer(middleware::from_fn(apply_pending_cookies))
+        .layer(middleware::from_fn_with_state(state.clone(), csrf_protection))
+        .nest_service("/assets", ServeDir::new(&state.config.assets.path));
+    let router = match state.config.media.backend {
+        // keys change with the content, so browsers may keep the files for good
+        MediaBackend::Local => router.nest_service(MEDIA_PATH, ServiceBuilder::new()
+            .layer(SetResponseHeaderLayer::overriding(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=31536000, immutable")))
+            .service(ServeDir::new(&state.config.media.dir))),
+        MediaBackend::S3 => router,
+    };
+    router
+        .layer(middleware::from_fn_with_state(state.clone(), security_headers))
+        .with_state(state)
+}
+
+/// Rows on a page of posts, comments, friends or users.
+const PAGE_SIZE: i32 = 25;
+
+/// Number of pages the records fill, none when they weren't counted.
+fn records_to_count(records: Option<i64>, page_size: i32) -> i32 {
+    match records {
+        None => 0,
+        Some(count) => ((count as f64)/(page_size as f64)).ceil() as i32,
+    }
+}
+
+/// Tells htmx to load another page after a successful request.
+fn hx_redirect(location: &str) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-redirect", HeaderValue::from_str(location).unwrap());
+    (headers, "Success").into_response()
+}
+
+/// Body limit of forms with an image, which leaves room for the other fields.
+fn upload_limit(state: &AppState) -> DefaultBodyLimit {
+    DefaultBodyLimit::max(state.config.assets.max_upload_size + 64 * 1024)
+}
+
+/// Body limit of the photo upload form, which takes several images at once.
+fn photos_upload_limit(state: &AppState) -> DefaultBodyLimit {
+    DefaultBodyLimit::max(state.config.assets.max_upload_size * state.config.albums.max_photos_per_upload + 64 * 1024)
+}
+
+/// Endpoints that guess passwords or reveal whether accounts exist, limited per client address.
+fn limited_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/register", post(register_user))
+        .route("/login", post(login))
+        .route("/login/2fa", post(two_factor_login))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
+        .route("/validation/psw", post(check_password))
+        .route("/validation/username", post(check_username))
+        .route("/validation/email", post(check_email))
+        .route("/validation/psw_repeat", post(check_password_repeat))
+        .route_layer(middleware::from_fn_with_state(state, limit_by_ip))
 }
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use axum::{response::{IntoResponse, Response}, extract::{State, Query}, http::HeaderMap, Form};
+use serde::Deserialize;
+use tracing::{info, debug, error};
+
+use crate::{template::{HtmlTemplate, ForgotPasswordTemplate, ResetLinkSentTemplate, ResetPasswordTemplate}, error::AppError, UserData, AppState, ForgotPasswordRequest, ResetPasswordRequest, validation::{validate_email, validate_password, validate_repeated_password}, security::{hash_token, random_token}, mailer::Email, session::{revoke_all_sessions, clear_token_cookie, clear_refresh_cookie}};
+
+use super::user::hash_password;
+
+/// Lifetime of a password reset link in seconds.
+const RESET_TOKEN_MAX_AGE: i64 = 60*60;
+
+#[derive(Deserialize)]
+pub struct ResetQuery {
+    token: Option<String>
+}
+
+pub async fn forgot_password_form(user: UserData) -> impl IntoResponse {
+    info!("forgot password form requested");
+    let template = ForgotPasswordTemplate {path: "login", user};
+    return HtmlTemplate(template)
+}
+
+pub async fn forgot_password(
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ForgotPasswordRequest>) -> Result<Response, AppError> {
+    info!("password reset requested");
+    let errors = validate_email(&request.email);
+    if errors.len() > 0 {
+        debug!("email is invalid");
+        return Err(AppError::Validation(errors))
+    }
+
+    let email = request.email.unwrap_or_default();
+    let user_db = state.storage.users.find_by_email(&email).await?;
+
+    // The response doesn't depend on whether the account exists,
+    // so the form can't be used to find out who is registered.
+    let Some(user_db) = user_db else {
+        debug!("no user with such email");
+        let template = ResetLinkSentTemplate {};
+        return Ok(HtmlTemplate(template).into_response())
+    };
+
+    let token = random_token(32);
+    sqlx::query!("INSERT INTO password_resets (token_hash, user_id, expires_at) VALUES ($1, $2, now() + make_interval(secs => $3))",
+        hash_token(&token), user_db.id, RESET_TOKEN_MAX_AGE as f64)
+        .execute(&state.db)
+        .await?;
+
+    let link = format!("{}/password/reset?token={}", state.config.server.public_url.trim_end_matches('/'), token);
+    let email = Email {
+        to: user_db.email,
+        subject: String::from("Reset your RustSpace password"),
+        body: format!("Hi {},\n\nsomeone asked to reset the password of your RustSpace account. \
+            If it was you, open the link below to choose a new one:\n\n{}\n\n\
+            The link is valid for one hour. If you didn't ask for it, just ignore this message.\n",
+            user_db.screen_name, link),
+    };
+    if let Err(err) = state.mailer.send(email).await {
+        error!("couldn't send password reset mail: {}", err);
+    }
+
+    info!("password reset link sent.");
+    let template = ResetLinkSentTemplate {};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn reset_password_form(
+    user: UserData,
+    State(state): State<Arc<AppState>>,
+    query: Query<ResetQuery>) -> impl IntoResponse {
+    info!("reset password form requested");
+    let mut token = query.token.to_owned();
+    if let Some(value) = &token {
+        let valid = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM password_resets WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()) AS "valid!""#,
+            hash_token(value))
+            .fetch_one(&state.db)
+            .await;
+        if !valid.unwrap_or(false) {
+            debug!("reset token is invalid");
+            token = None;
+        }
+    }
+    let template = ResetPasswordTemplate {path: "login", user, token};
+    return HtmlTemplate(template)
+}
+
+pub async fn reset_password(
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ResetPasswordRequest>) -> Result<Response, AppError> {
+    info!("request to reset password");
+    let mut errors = validate_password(&request.psw);
+    errors.append(&mut validate_repeated_password(&request.psw, &request.psw_repeat));
+    if errors.len() > 0 {
+        debug!("password input is invalid");
+        return Err(AppError::Validation(errors))
+    }
+    let Some(token) = request.token else {
+        return Err(AppError::Validation(vec!["This link is invalid or has expired!"]))
+    };
+
+    debug!("hashing password...");
+    let password = match hash_password(&request.psw.unwrap()) {
+        Ok(password) => password,
+        Err(error) => {
+            error!("there was an error during hashing a password!");
+            return Err(AppError::Internal(error.message))
+        }
+    };
+
+    let result = reset_with_token(&state, &token, &password).await?;
+    match result {
+        false => {
+            debug!("reset token is invalid");
+            return Err(AppError::Validation(vec!["This link is invalid or has expired!"]))
+        },
+        true => {
+            info!("password succesfully reset.");
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-redirect", "/login".parse().unwrap());
+            headers.append("Set-Cookie", clear_token_cookie(&state.config.session).parse().unwrap());
+            headers.append("Set-Cookie", clear_refresh_cookie(&state.config.session).parse().unwrap());
+            return Ok((headers, "Success").into_response())
+        }
+    }
+}
+
+/// Uses up the token, sets the new password and signs the user out everywhere.
+/// Returns `false` if the token is unknown, used or expired.
+async fn reset_with_token(state: &AppState, token: &str, password: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = state.db.begin().await?;
+    let user_id = sqlx::query_scalar!(
+        "UPDATE password_resets SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id",
+        hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(user_id) = user_id else {
+        return Ok(false)
+    };
+
+    sqlx::query!("UPDATE users SET password = $1 WHERE id = $2", password, user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE password_resets SET used_at = now() WHERE user_id = $1 AND used_at IS NULL", user_id)
+        .execute(&mut *tx)
+        .await?;
+    revoke_all_sessions(&mut *tx, user_id).await?;
+    tx.commit().await?;
+    Ok(true)
+}
@@ -1,11 +1,12 @@
 use std::sync::Arc;
 
-use axum::{response::IntoResponse, extract::{State, Path, Query}, Form, http::{HeaderMap, HeaderValue}};
-use sqlx::{Postgres, PgPool};
+use axum::{response::{IntoResponse, Response, Redirect}, extract::{State, Path, Query}, Form};
 use tracing::{info, debug};
 use serde::Deserialize;
 
-use crate::{template::{HtmlTemplate, ErrorsTemplate, UserNotFoundTemplate, PostTemplate, PostsTemplate, PostsResultTemplate, PostFormTemplate, PostNotFoundTemplate, DbErrorTemplate, NewPostsTemplate, UpdatePostFormTemplate}, UserData, AppState, validation::validate_non_empty, PostRequest, BlogPostModel, BlogPostDetails};
+use crate::{template::{HtmlTemplate, PostTemplate, PostsTemplate, PostsResultTemplate, PostFormTemplate, NewPostsTemplate, UpdatePostFormTemplate}, error::AppError, UserData, AppState, auth::{CurrentUser, OptionalUser}, validation::validate_non_empty, PostRequest, BlogPostModel, storage::Storage};
+
+use super::{verification::verified_email_required, records_to_count, hx_redirect, PAGE_SIZE};
 
 fn validate_post(request: &PostRequest) -> Vec<&'static str> {
     let mut errors = vec![];
@@ -18,283 +19,146 @@ fn validate_post(request: &PostRequest) -> Vec<&'static str> {
     return errors;
 }
 
-fn post_action_validate(user: &UserData, request: &PostRequest,) -> Option<ErrorsTemplate> {
-    if user.username.is_none() {
-        return Some(ErrorsTemplate {errors: vec!["Unauthenticated!"]});
-    }
+fn post_action_validate(request: &PostRequest,) -> Result<(), AppError> {
     let errors = validate_post(&request);
     if !errors.is_empty() {
-        return Some(ErrorsTemplate {errors});
+        return Err(AppError::Validation(errors));
     }
-    return None;
+    return Ok(());
 }
 
-pub async fn get_user_by_name(db: &PgPool, username: &String) -> Result<Option<i32>, sqlx::Error> {
+async fn get_user_id(storage: &Storage, username: &str) -> Result<Option<i32>, AppError> {
     debug!("getting user from database");
-    return sqlx::query_scalar("SELECT id FROM users WHERE screen_name = $1")
-        .bind(username)
-        .fetch_optional(db)
-        .await;
+    let user = storage.users.find_by_name(username).await?;
+    Ok(user.and_then(|user| user.id))
 }
 
-pub async fn insert_post(db: &PgPool, user_id: &i32, request: &PostRequest) -> Result<i32, String> {
-    debug!("saving post in database");
-    return sqlx::query_scalar("INSERT INTO posts (user_id, content, title) VALUES ($1, $2, $3) RETURNING id")
-        .bind(user_id)
-        .bind(&request.content)
-        .bind(&request.title)
-        .fetch_one(db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
+/// Sends links to the blog of a user who has since been renamed to the new
+/// address, `path` being the part after the username.
+async fn redirect_renamed(storage: &Storage, username: &str, path: &str, missing: &'static str) -> Result<Response, AppError> {
+    let Some(current_name) = storage.users.find_renamed(username).await? else {
+        return Err(AppError::NotFound(missing))
+    };
+    debug!("user {} is now called {}", username, current_name);
+    Ok(Redirect::permanent(&format!("/user/{}/blog{}", current_name, path)).into_response())
 }
 
 pub async fn add_post(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Form(request): Form<PostRequest>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("adding blogpost requested");
-    let error = post_action_validate(&user, &request);
-    if let Some(template) = error {
-        return HtmlTemplate(template).into_response()
-    }
-
-    let username = user.username.unwrap();
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    post_action_validate(&request)?;
+    verified_email_required(&state, &current).await?;
 
-    let query_result = insert_post(&state.db, &user_id, &request).await;
-    let Ok(id) = query_result else {
-        debug!("Db error: {:?}", query_result);
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    debug!("saving post in database");
+    let title = request.title.unwrap_or_default();
+    let content = request.content.unwrap_or_default();
+    let id = state.storage.posts.create(current.id, &title, &content).await?;
     info!("post succesfully created.");
 
-    let mut headers = HeaderMap::new();
-    headers.insert("HX-redirect", HeaderValue::from_str(&format!("/blog/{}", id)).unwrap());
-    (headers, "Success").into_response()
+    Ok(hx_redirect(&format!("/blog/{}", id)))
 }
 
 pub async fn delete_post(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(post_id): Path<i32>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("deleting blogpost requested");
-    if user.username.is_none() {
-        let template = ErrorsTemplate {errors: vec!["Unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
-    }
-
-    debug!("getting user from database");
-    let username = user.username.unwrap();
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
 
     debug!("getting post from database");
-    let post_db = sqlx::query_as::<Postgres, BlogPostModel>("SELECT * FROM posts WHERE id = $1")
-        .bind(&post_id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(post) = post_db else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
+    let post_db = state.storage.posts.find(post_id).await?;
+    let Some(post) = post_db else {
+        return Err(AppError::NotFound("No such post!"))
     };
-    let Some(post) = post else {
-        let template = ErrorsTemplate {errors: vec!["No such post!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    if &post.user_id != &user_id {
-        let template = ErrorsTemplate {errors: vec!["You cannot delete this post!"]};
-        return HtmlTemplate(template).into_response()
-    }
-
-    let delete_comments_result = sqlx::query("DELETE FROM comments WHERE post_id = $1")
-        .bind(&post.id)
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
 
-    if let Err(_) = delete_comments_result  {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
+    if post.user_id != current.id {
+        return Err(AppError::Forbidden("You cannot delete this post!"))
     }
 
-    let query_result = sqlx::query("DELETE FROM posts WHERE id = $1")
-        .bind(&post.id)
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
-
-    if let Err(_) = query_result {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    }
+    state.storage.posts.delete(post_id).await?;
 
     info!("post succesfully deleted.");
-    let mut headers = HeaderMap::new();
-    headers.insert("HX-redirect", HeaderValue::from_str(&format!("/user/{}/blog", username)).unwrap());
-    (headers, "Success").into_response()
+    Ok(hx_redirect(&format!("/user/{}/blog", current.user.screen_name)))
 }
 
 pub async fn edit_post(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(post_id): Path<i32>,
     Form(request): Form<PostRequest>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("updating blogpost requested");
-    let error = post_action_validate(&user, &request);
-    if let Some(template) = error {
-        return HtmlTemplate(template).into_response()
-    }
-
-    debug!("getting user from database");
-    let username = user.username.unwrap();
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
+    post_action_validate(&request)?;
 
     debug!("getting post from database");
-    let post_db = sqlx::query_as::<Postgres, BlogPostModel>("SELECT * FROM posts WHERE id = $1")
-        .bind(&post_id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(post) = post_db else {
-        debug!("Db error: {:?}", post_db);
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(post) = post else {
-        let template = ErrorsTemplate {errors: vec!["No such post!"]};
-        return HtmlTemplate(template).into_response()
+    let post_db = state.storage.posts.find(post_id).await?;
+    let Some(post) = post_db else {
+        return Err(AppError::NotFound("No such post!"))
     };
 
-    if &post.user_id != &user_id {
-        let template = ErrorsTemplate {errors: vec!["You cannot edit this post!"]};
-        return HtmlTemplate(template).into_response()
+    if post.user_id != current.id {
+        return Err(AppError::Forbidden("You cannot edit this post!"))
     }
 
-    let query_result = sqlx::query("UPDATE posts SET content = $1, title = $2 WHERE id = $3")
-        .bind(&request.content)
-        .bind(&request.title)
-        .bind(&post_id)
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
-
-    if let Err(err) = query_result {
-        debug!("Db error: {:?}", err);
-        let template = ErrorsTemplate {errors: vec!["Db error!"]};
-        return HtmlTemplate(template).into_response()
-    }
+    let title = request.title.unwrap_or_default();
+    let content = request.content.unwrap_or_default();
+    state.storage.posts.update(post_id, &title, &content).await?;
     info!("post succesfully updated.");
 
-    let mut headers = HeaderMap::new();
-    headers.insert("HX-redirect", HeaderValue::from_str(&format!("/blog/{}", post_id)).unwrap());
-    (headers, "Success").into_response()
+    Ok(hx_redirect(&format!("/blog/{}", post_id)))
 }
 
 pub async fn get_post(
     user: UserData,
+    OptionalUser(current): OptionalUser,
     State(state): State<Arc<AppState>>,
     Path(post_id): Path<i32>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("blogpost requested");
 
     debug!("getting post from database");
-    let post_db = sqlx::query_as::<Postgres, BlogPostDetails>(
-        "SELECT p.*, u.screen_name 
-        FROM posts p
-        LEFT JOIN users u ON u.id = p.user_id
-        WHERE p.id = $1"
-        )
-        .bind(&post_id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(post) = post_db else {
-        debug!("Db error: {:?}", post_db);
-        let template = DbErrorTemplate{};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(post) = post else {
-        let template = PostNotFoundTemplate{};
-        return HtmlTemplate(template).into_response()
+    let post_db = state.storage.posts.find_details(post_id).await?;
+    let Some(post) = post_db else {
+        return Err(AppError::NotFound("No such post!"))
     };
 
-    let owner = match &user.username {
-        None => false,
-        Some(username) => match get_user_by_name(&state.db, username).await {
-            Ok(Some(user_id)) if user_id == post.user_id => true,
-            _ => false
-        }
-    };
+    let owner = current.is_some_and(|current| current.id == post.user_id);
 
     let template = PostTemplate {post, user, owner, path: "/post"};
-    return HtmlTemplate(template).into_response()
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn get_users_posts(
     user: UserData,
     State(state): State<Arc<AppState>>,
     Path(username): Path<String>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("blogpost requested");
 
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = UserNotFoundTemplate {};
-        return HtmlTemplate(template).into_response()
+    let Some(user_id) = get_user_id(&state.storage, &username).await? else {
+        return redirect_renamed(&state.storage, &username, "", "There is no such user.").await
     };
 
     debug!("getting posts from database");
-    let posts = get_posts(&state.db, user_id, 0).await;
-    match posts {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = DbErrorTemplate{};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((posts, records)) => {
-            let pages = records_to_count(records);
-            let owner = match &user.username {
-                None => false,
-                Some(u) => u == &username,
-            };
-            let template = PostsTemplate {posts, user, pages, username, path: "/posts", owner};
-            return HtmlTemplate(template).into_response()
-        }
+    let (posts, records) = get_posts(&state.storage, user_id, 0).await?;
+    let pages = records_to_count(records, PAGE_SIZE);
+    let owner = match &user.username {
+        None => false,
+        Some(u) => u == &username,
     };
+    let template = PostsTemplate {posts, user, pages, username, path: "/posts", owner};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
-async fn get_posts(db: &PgPool, user_id: i32, page: i32) -> Result<(Vec<BlogPostModel>, Option<i64>), sqlx::Error> {
+async fn get_posts(storage: &Storage, user_id: i32, page: i32) -> Result<(Vec<BlogPostModel>, Option<i64>), AppError> {
     let page_size = 25;
     let offset = page_size * page;
-    let users = sqlx::query_as::<Postgres, BlogPostModel>(
-        "SELECT * FROM posts WHERE user_id = $1 
-        ORDER BY created_at
-        LIMIT $2 OFFSET $3 "
-        )
-        .bind(&user_id)
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(db)
-        .await?;
-
-    let records: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM posts 
-        WHERE user_id = $1")
-        .bind(&user_id)
-        .fetch_one(db)
-        .await?;
-    
-    Ok((users, Some(records)))
+    let posts = storage.posts.list_by_user(user_id, page_size, offset).await?;
+    let records = storage.posts.count_by_user(user_id).await?;
+    Ok((posts, Some(records)))
 }
 
 #[derive(Deserialize)]
@@ -306,39 +170,19 @@ pub async fn posts_page(
     Query(query): Query<SearchQuery>,
     State(state): State<Arc<AppState>>,
     Path(username): Path<String>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("blogpost requested");
 
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = UserNotFoundTemplate {};
-        return HtmlTemplate(template).into_response()
+    let Some(user_id) = get_user_id(&state.storage, &username).await? else {
+        let path = format!("/page?page={}", query.page);
+        return redirect_renamed(&state.storage, &username, &path, "There is no such user.").await
     };
 
     debug!("getting posts from database");
-    let posts = get_posts(&state.db, user_id, query.page).await;
-    match posts {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = DbErrorTemplate{};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok((posts, results)) => {
-            let pages = records_to_count(results);
-            let template = PostsResultTemplate {posts, pages, username, page: query.page};
-            return HtmlTemplate(template).into_response()
-        }
-    };
-}
-
-fn records_to_count(records: Option<i64>) -> i32 {
-    match records {
-        None => 0,
-        Some(count) => {
-            let count = (count as f64)/25.0;
-            let count = count.ceil() as i32;
-            count
-        }
-    }
+    let (posts, results) = get_posts(&state.storage, user_id, query.page).await?;
+    let pages = records_to_count(results, PAGE_SIZE);
+    let template = PostsResultTemplate {posts, pages, username, page: query.page};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn post_form(user: UserData) -> impl IntoResponse {
@@ -347,67 +191,85 @@ pub async fn post_form(user: UserData) -> impl IntoResponse {
     return HtmlTemplate(template)
 }
 
-async fn get_new_posts(db: &PgPool, user_id: i32) -> Result<Vec<BlogPostModel>, sqlx::Error> {
-    let page_size = 5;
-    let users = sqlx::query_as::<Postgres, BlogPostModel>(
-        "SELECT * FROM posts WHERE user_id = $1 
-        ORDER BY created_at
-        LIMIT $2 "
-        )
-        .bind(&user_id)
-        .bind(page_size)
-        .fetch_all(db)
-        .await?;
-
-    Ok(users)
-}
-
 pub async fn new_posts(
     State(state): State<Arc<AppState>>,
     Path(username): Path<String>
-    ) -> impl IntoResponse {
-    let Ok(Some(user_id)) = get_user_by_name(&state.db, &username).await else {
-        let template = ErrorsTemplate {errors: vec!["No user!"]};
-        return HtmlTemplate(template).into_response()
+    ) -> Result<Response, AppError> {
+    let Some(user_id) = get_user_id(&state.storage, &username).await? else {
+        return redirect_renamed(&state.storage, &username, "/new", "No user!").await
     };
 
     debug!("getting posts from database");
-    let posts = get_new_posts(&state.db, user_id).await;
-    match posts {
-        Err(err) => {
-            debug!("Database error: {}", err);
-            let template = ErrorsTemplate {errors: vec!["Db error!"]};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok(posts) => {
-            let template = NewPostsTemplate {posts, username};
-            return HtmlTemplate(template).into_response()
-        }
-    };
+    let posts = state.storage.posts.list_by_user(user_id, 5, 0).await?;
+    let template = NewPostsTemplate {posts, username};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn edit_post_form(
     user: UserData,
     State(state): State<Arc<AppState>>,
     Path(post_id): Path<i32>
-    ) -> impl IntoResponse {
+    ) -> Result<Response, AppError> {
     info!("register form requested");
 
-    let post_db = sqlx::query_as::<Postgres, BlogPostModel>(
-        "SELECT * FROM posts WHERE id = $1")
-        .bind(&post_id)
-        .fetch_optional(&state.db)
-        .await;
-    let Ok(post) = post_db else {
-        debug!("Db error: {:?}", post_db);
-        let template = DbErrorTemplate{};
-        return HtmlTemplate(template).into_response()
-    };
-    let Some(post) = post else {
-        let template = PostNotFoundTemplate{};
-        return HtmlTemplate(template).into_response()
+    let post_db = state.storage.posts.find(post_id).await?;
+    let Some(post) = post_db else {
+        return Err(AppError::NotFound("No such post!"))
     };
 
     let template = UpdatePostFormTemplate {path: "register", user, post, post_id};
-    return HtmlTemplate(template).into_response()
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::{State, Path}, Form, http::StatusCode};
+
+    use crate::{PostRequest, error::AppError, test::{memory_state, memory_user}};
+
+    use super::{add_post, delete_post};
+
+    fn post_request(title: &str, content: &str) -> Form<PostRequest> {
+        Form(PostRequest { title: Some(String::from(title)), content: Some(String::from(content)) })
+    }
+
+    #[tokio::test]
+    async fn test_adding_post_redirects_to_it() {
+        let state = memory_state();
+        let user = memory_user(&state, "Test").await;
+
+        let response = add_post(user.clone(), State(state.clone()), post_request("Title", "Content")).await.unwrap();
+
+        let posts = state.storage.posts.list_by_user(user.id, 25, 0).await.unwrap();
+        assert_eq!(posts.len(), 1);
+        let location = format!("/blog/{}", posts[0].id.unwrap());
+        assert_eq!(response.headers().get("HX-redirect").unwrap(), location.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_deleting_post_of_other_user() {
+        let state = memory_state();
+        let author = memory_user(&state, "Test").await;
+        let other = memory_user(&state, "Other").await;
+        let post_id = state.storage.posts.create(author.id, "Title", "Content").await.unwrap();
+
+        let result = delete_post(other, State(state.clone()), Path(post_id)).await;
+
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+        assert!(state.storage.posts.find(post_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_deleting_post_removes_comments() {
+        let state = memory_state();
+        let author = memory_user(&state, "Test").await;
+        let post_id = state.storage.posts.create(author.id, "Title", "Content").await.unwrap();
+        state.storage.comments.create(author.id, post_id, "Comment").await.unwrap();
+
+        let response = delete_post(author, State(state.clone()), Path(post_id)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.storage.posts.find(post_id).await.unwrap().is_none());
+        assert_eq!(state.storage.comments.count_for_post(post_id).await.unwrap(), 0);
+    }
 }
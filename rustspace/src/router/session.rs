@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use axum::{response::{IntoResponse, Response}, extract::{State, Path}, http::HeaderMap};
+use tracing::info;
+
+use crate::{template::{HtmlTemplate, SessionsTemplate, DeletedSessionTemplate}, error::AppError, UserData, AppState, auth::CurrentUser, session::{get_active_sessions, revoke_user_session, revoke_all_sessions, clear_token_cookie, clear_refresh_cookie}, config::SessionConfig};
+
+pub async fn sessions(
+    user: UserData,
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    info!("active sessions requested");
+    let sessions = get_active_sessions(&state.db, current.id).await?;
+    let current_session = user.session.clone().unwrap_or_default();
+    let template = SessionsTemplate {path: "user", user, sessions, current: current_session};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn delete_session(
+    user: UserData,
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<i32>) -> Result<Response, AppError> {
+    info!("signing out of session requested");
+    let Some(token_id) = revoke_user_session(&state.db, current.id, session_id).await? else {
+        return Err(AppError::NotFound("No such session!"))
+    };
+
+    info!("session succesfully revoked.");
+    if user.session.as_ref() == Some(&token_id) {
+        return Ok(signed_out(&state.config.session).into_response())
+    }
+    let template = DeletedSessionTemplate {id: session_id};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn delete_all_sessions(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    info!("signing out everywhere requested");
+    revoke_all_sessions(&state.db, current.id).await?;
+
+    info!("all sessions succesfully revoked.");
+    Ok(signed_out(&state.config.session).into_response())
+}
+
+fn signed_out(config: &SessionConfig) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-redirect", "/login".parse().unwrap());
+    headers.append("Set-Cookie", clear_token_cookie(config).parse().unwrap());
+    headers.append("Set-Cookie", clear_refresh_cookie(config).parse().unwrap());
+    (headers, "Success")
+}
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use axum::{response::{IntoResponse, Response}, extract::{State, Query}, http::HeaderMap, Form};
+use axum_extra::extract::CookieJar;
+use qrcode::{QrCode, render::svg};
+use sqlx::PgPool;
+use time::Duration;
+use tracing::{info, debug};
+
+use crate::{template::{HtmlTemplate, TwoFactorFormTemplate, TwoFactorDisableFormTemplate, TwoFactorFieldTemplate, RecoveryCodesTemplate, TwoFactorLoginTemplate}, error::{AppError, Conflict}, UserData, AppState, TwoFactorRequest, totp, security::{hash_token, random_token}, session::{self, ClientInfo}, config::SessionConfig, auth::CurrentUser};
+
+use super::user::{FriendlyRedirect, start_session};
+
+const ISSUER: &str = "RustSpace";
+const RECOVERY_CODES: usize = 10;
+/// Time the user has to enter the code after the password, in seconds.
+const CHALLENGE_MAX_AGE: i64 = 5*60;
+const CHALLENGE_MAX_ATTEMPTS: i32 = 5;
+
+struct TwoFactorUser {
+    id: i32,
+    totp_secret: Option<String>,
+    totp_enabled: bool,
+    totp_last_step: Option<i64>,
+}
+
+async fn get_two_factor_user_by_id(db: &PgPool, user_id: i32) -> Result<Option<TwoFactorUser>, sqlx::Error> {
+    sqlx::query_as!(TwoFactorUser,
+        r#"SELECT id, totp_secret, totp_enabled_at IS NOT NULL AS "totp_enabled!", totp_last_step
+        FROM users WHERE id = $1"#,
+        user_id)
+        .fetch_optional(db)
+        .await
+}
+
+/// Accepts a current TOTP code or an unused recovery code.
+async fn check_code(state: &AppState, user: &TwoFactorUser, code: &str) -> Result<bool, sqlx::Error> {
+    let Some(secret) = &user.totp_secret else {
+        return Ok(false)
+    };
+    let now = state.clock.now().timestamp();
+    if let Some(step) = totp::verify(secret, code, now, user.totp_last_step) {
+        let result = sqlx::query!("UPDATE users SET totp_last_step = $1 WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+            step, user.id)
+            .execute(&state.db)
+            .await?;
+        return Ok(result.rows_affected() > 0)
+    }
+
+    let result = sqlx::query!("UPDATE recovery_codes SET used_at = now() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        user.id, hash_token(&normalize_recovery_code(code)))
+        .execute(&state.db)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn new_recovery_code() -> String {
+    let code = random_token(5);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+pub async fn edit_two_factor(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    info!("two-factor form requested");
+
+    // the secret stays pending until the user confirms it with a code
+    let secret = totp::generate_secret();
+    let result = sqlx::query!("UPDATE users SET totp_secret = $1, totp_last_step = NULL WHERE id = $2 AND totp_enabled_at IS NULL",
+        secret, current.id)
+        .execute(&state.db)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::Conflict(Conflict::TwoFactorEnabled))
+    }
+
+    let uri = totp::provisioning_uri(&secret, &current.user.screen_name, ISSUER);
+    let qr = QrCode::new(uri.as_bytes())
+        .map(|code| code.render::<svg::Color>().min_dimensions(200, 200).build())
+        .unwrap_or_default();
+    let template = TwoFactorFormTemplate {secret, uri, qr};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+pub async fn enable_two_factor(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<TwoFactorRequest>) -> Result<Response, AppError> {
+    info!("enabling two-factor authentication requested");
+    let Some(user_db) = get_two_factor_user_by_id(&state.db, current.id).await? else {
+        return Err(AppError::Unauthenticated)
+    };
+    if user_db.totp_enabled {
+        return Err(AppError::Conflict(Conflict::TwoFactorEnabled))
+    }
+    let Some(secret) = &user_db.totp_secret else {
+        return Err(AppError::Validation(vec!["Scan the QR code first!"]))
+    };
+    let now = state.clock.now().timestamp();
+    let Some(step) = totp::verify(secret, request.code.as_deref().unwrap_or(""), now, None) else {
+        return Err(AppError::Validation(vec!["Wrong code!"]))
+    };
+
+    let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| new_recovery_code()).collect();
+    store_two_factor(&state.db, user_db.id, step, &codes).await?;
+    info!("two-factor authentication succesfully enabled.");
+    let template = RecoveryCodesTemplate {codes};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+async fn store_two_factor(db: &PgPool, user_id: i32, step: i64, codes: &Vec<String>) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    sqlx::query!("UPDATE users SET totp_enabled_at = now(), totp_last_step = $1 WHERE id = $2", step, user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    for code in codes {
+        sqlx::query!("INSERT INTO recovery_codes (code_hash, user_id) VALUES ($1, $2)",
+            hash_token(&normalize_recovery_code(code)), user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn edit_disable_two_factor() -> impl IntoResponse {
+    info!("disabling two-factor form requested");
+    let template = TwoFactorDisableFormTemplate {};
+    return HtmlTemplate(template)
+}
+
+pub async fn disable_two_factor(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<TwoFactorRequest>) -> Result<Response, AppError> {
+    info!("disabling two-factor authentication requested");
+    let Some(user_db) = get_two_factor_user_by_id(&state.db, current.id).await? else {
+        return Err(AppError::Unauthenticated)
+    };
+    if !user_db.totp_enabled {
+        return Err(AppError::Conflict(Conflict::TwoFactorDisabled))
+    }
+    if !check_code(&state, &user_db, request.code.as_deref().unwrap_or("")).await? {
+        return Err(AppError::Validation(vec!["Wrong code!"]))
+    }
+
+    sqlx::query!(
+        "WITH deleted AS (DELETE FROM recovery_codes WHERE user_id = $1)
+        UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = $1",
+        user_db.id)
+        .execute(&state.db)
+        .await?;
+    info!("two-factor authentication succesfully disabled.");
+    let template = TwoFactorFieldTemplate {enabled: false};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+/// Remembers that the password was correct until the second factor is checked.
+pub async fn create_challenge(db: &PgPool, user_id: i32, remember: bool) -> Result<String, sqlx::Error> {
+    let token = random_token(32);
+    sqlx::query!("INSERT INTO login_challenges (token_hash, user_id, remember, expires_at) VALUES ($1, $2, $3, now() + make_interval(secs => $4))",
+        hash_token(&token), user_id, remember, CHALLENGE_MAX_AGE as f64)
+        .execute(db)
+        .await?;
+    Ok(token)
+}
+
+pub fn challenge_cookie(token: &str, config: &SessionConfig) -> String {
+    let mut cookie = session::cookie("Challenge", token, config);
+    cookie.set_path("/login");
+    cookie.set_max_age(Duration::seconds(CHALLENGE_MAX_AGE));
+    cookie.to_string()
+}
+
+pub async fn two_factor_login_form(user: UserData, query: Query<FriendlyRedirect>) -> impl IntoResponse {
+    info!("two-factor login form requested");
+    let template = TwoFactorLoginTemplate {path: "login", user, redir: query.path.to_owned()};
+    return HtmlTemplate(template)
+}
+
+pub async fn two_factor_login(
+    State(state): State<Arc<AppState>>,
+    jar: CookieJar,
+    client: ClientInfo,
+    Form(request): Form<TwoFactorRequest>) -> Result<Response, AppError> {
+    info!("request to finish login with second factor");
+    let Some(challenge) = jar.get("Challenge").map(|cookie| cookie.value().to_string()) else {
+        return Err(AppError::Validation(vec!["Login expired, please log in again!"]))
+    };
+
+    let pending = sqlx::query!(
+        "UPDATE login_challenges SET attempts = attempts + 1
+        WHERE token_hash = $1 AND expires_at > now() AND attempts < $2
+        RETURNING user_id, remember",
+        hash_token(&challenge), CHALLENGE_MAX_ATTEMPTS)
+        .fetch_optional(&state.db)
+        .await?;
+    let Some((user_id, remember)) = pending.map(|row| (row.user_id, row.remember)) else {
+        return Err(AppError::Validation(vec!["Login expired, please log in again!"]))
+    };
+
+    let Some(user_db) = get_two_factor_user_by_id(&state.db, user_id).await? else {
+        return Err(AppError::Unauthenticated)
+    };
+    if !check_code(&state, &user_db, request.code.as_deref().unwrap_or("")).await? {
+        debug!("wrong second factor");
+        return Err(AppError::Validation(vec!["Wrong code!"]))
+    }
+
+    _ = sqlx::query!("DELETE FROM login_challenges WHERE token_hash = $1", hash_token(&challenge))
+        .execute(&state.db)
+        .await;
+    let (token, refresh_token) = start_session(&state, user_db.id, &client, remember).await?;
+
+    info!("second factor accepted.");
+    let path = request.redir.unwrap_or_default();
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-redirect", path.as_str().parse().unwrap());
+    headers.append("Set-Cookie", session::token_cookie(&token, &state.config.session).parse().unwrap());
+    let cookie = session::refresh_cookie(&refresh_token, remember, &state.config.session);
+    headers.append("Set-Cookie", cookie.parse().unwrap());
+    headers.append("Set-Cookie", session::removal_cookie("Challenge", "/login", &state.config.session).parse().unwrap());
+    Ok((headers, "Success").into_response())
+}
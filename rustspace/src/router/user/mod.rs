@@ -1,81 +1,60 @@
 use core::fmt;
-use std::sync::Arc;
-use chrono::Utc;
+use std::{collections::HashMap, future::Future, sync::{Arc, OnceLock}};
 use serde::Deserialize;
 
 use argon2::{password_hash::SaltString, Argon2, PasswordHasher, PasswordHash, PasswordVerifier};
-use axum::{extract::{State, Query}, Form, http::HeaderMap, response::IntoResponse, body::Bytes};
+use axum::{extract::{State, Query}, Form, http::HeaderMap, response::{IntoResponse, Response}};
+use askama::Template;
 use axum_extra::extract::Multipart;
 use rand_core::OsRng;
-use sqlx::Postgres;
-use tracing::{info, debug, error};
+use tracing::{info, debug, warn, error};
 
-use crate::{AppState, template::{ErrorsTemplate, RegisterTemplate, UserTemplate, HtmlTemplate, FieldTemplate, UnauthorizedTemplate, LoginTemplate, EmailFormTemplate, PasswordFormTemplate, EmailFieldTemplate, PasswordFieldTemplate, AvatarFormTemplate, AvatarResultTemplate}, UserRequest, validation::{validate_user, validate_password, validate_username, validate_email, validate_repeated_password, validate_login}, UserData, security::get_token, LoginRequest, UserModel, EmailRequest, PasswordRequest};
+use super::{verification::send_verification, two_factor::{create_challenge, challenge_cookie}, hx_redirect};
+
+use crate::{AppState, error::AppError, template::{RegisterTemplate, UserTemplate, HtmlTemplate, FieldTemplate, LoginTemplate, UsernameFormTemplate, EmailFormTemplate, PasswordFormTemplate, EmailFieldTemplate, PasswordFieldTemplate, AvatarFormTemplate, AvatarResultTemplate, BannerFormTemplate, BannerResultTemplate, DeleteAccountFormTemplate}, UserRequest, validation::{validate_user, validate_password, validate_username, validate_email, validate_repeated_password, validate_login}, UserData, security::{get_token, random_token}, rate_limit::too_many_requests, redirect::RedirectTarget, session::{self, ClientInfo}, LoginRequest, UsernameRequest, EmailRequest, PasswordRequest, DeleteAccountRequest, auth::CurrentUser, export::latest_export, sized_image::{SizedImage, AVATARS, BANNERS}, storage::StorageResult, images::{ImageLimits, CropBox, read_upload}};
 
 #[derive(Deserialize)]
 pub struct FriendlyRedirect {
-    path: Option<String>
+    pub path: Option<RedirectTarget>
 }
 
 pub async fn register_user(
     State(state): State<Arc<AppState>>,
-    Form(user): Form<UserRequest>) -> impl IntoResponse {
+    client: ClientInfo,
+    Form(user): Form<UserRequest>) -> Result<Response, AppError> {
     info!("register form sent");
     debug!("validating...");
 
-    let mut errors = validate_user(&user);
+    let errors = validate_user(&user);
     if errors.len() > 0 {
         debug!("user input is invalid");
-        let template = ErrorsTemplate {errors};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::Validation(errors))
     }
 
     debug!("hashing password...");
-    let password = hash_password(&user.psw.unwrap());
-    if let Err(error) = password {
+    let password = hash_password(&user.psw.unwrap()).map_err(|error| {
         error!("there was an error during hashing a password!");
-        let template = ErrorsTemplate { errors: vec![error.message]};
-        return HtmlTemplate(template).into_response()
-    }
+        AppError::Internal(error.message)
+    })?;
 
     debug!("trying to add user to db...");
-    let query_result =
-        sqlx::query("INSERT INTO users (screen_name, email, password) VALUES ($1, $2, $3)")
-        .bind(&user.username)
-        .bind(&user.email)
-        .bind(&password.unwrap())
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
-
-    if let Err(err) = query_result {
-        debug!("cannot add user to db!");
-        if err.contains("duplicate key") && err.contains("screen_name") {
-            errors.push("Username must be unique!");
-        }
-        if err.contains("duplicate key") && err.contains("email") {
-            errors.push("Email must be unique!");
-        }
-        if !err.contains("duplicate key") {
-            errors.push("Couldn't add to db!");
-        }
-        debug!(err);
-        let template = ErrorsTemplate {errors};
-        return HtmlTemplate(template).into_response()
-    }
+    let username = user.username.clone().unwrap_or_default();
+    let email = user.email.clone().unwrap_or_default();
+    let user_id = state.storage.users.create(&username, &email, &password).await?;
     info!("user succesfully created.");
-
-    let mut path = String::from("/user");
-    if let Some(friendly_path) = user.redir {
-        path = friendly_path
+    if let Err(err) = send_verification(&state, user_id, &username, &email).await {
+        debug!("couldn't send verification: {}", err);
     }
 
+    let path = user.redir.unwrap_or_default();
+
+    let (token, refresh_token) = start_session(&state, user_id, &client, false).await?;
+
     let mut headers = HeaderMap::new();
-    headers.insert("HX-redirect", path.parse().unwrap());
-    let (token, _) = get_token(&user.username);
-    let cookie = format!("Token={}; SameSite=Lax", token);
-    headers.insert("Set-Cookie", cookie.parse().unwrap());
-    (headers, "Success").into_response()
+    headers.insert("HX-redirect", path.as_str().parse().unwrap());
+    headers.append("Set-Cookie", session::token_cookie(&token, &state.config.session).parse().unwrap());
+    headers.append("Set-Cookie", session::refresh_cookie(&refresh_token, false, &state.config.session).parse().unwrap());
+    Ok((headers, "Success").into_response())
 }
 
 pub async fn register_form(user: UserData, query: Query<FriendlyRedirect>) -> impl IntoResponse {
@@ -85,38 +64,16 @@ pub async fn register_form(user: UserData, query: Query<FriendlyRedirect>) -> im
 }
 
 pub async fn user_page(user: UserData,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
     info!("user index requested");
-    if user.username.is_none() {
-        let template = UnauthorizedTemplate {message: "You're unauthorized!", redir: Some(String::from("/user"))};
-        return HtmlTemplate(template).into_response()
-    }
-
-    let user_db = sqlx::query_as::<Postgres, UserModel>(
-        "SELECT * FROM users WHERE screen_name = $1",
-        )
-        .bind(&user.username)
-        .fetch_optional(&state.db)
-        .await;
-
-    let Ok(user_db) = user_db else {
-        debug!("db error");
-        let template = ErrorsTemplate {errors: vec!["Database error, please try again later"]};
-        return HtmlTemplate(template).into_response()
-    };
+    let export = latest_export(&state.db, current.id, state.clock.now()).await?;
+    let user_db = current.user;
 
-    let Some(user_db) = user_db  else {
-        debug!("no such user");
-        let template = ErrorsTemplate {errors: vec!["No such user!"]};
-        return HtmlTemplate(template).into_response()
-    };
-
-    let timestamp: i64 = match &user_db.updated_at {
-        Some(time) => time.timestamp(),
-        None => 0
-    };
-    let template = UserTemplate {path: "user", user, user_db, timestamp};
-    return HtmlTemplate(template).into_response()
+    let avatar = user_db.avatar_key.as_deref().map(|key| state.media.sized_url(key, AVATARS.largest()));
+    let banner = user_db.banner_key.as_deref().map(|key| state.media.sized_url(key, BANNERS.largest()));
+    let template = UserTemplate {path: "user", user, user_db, avatar, banner, export};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn check_password(Form(user): Form<UserRequest>) -> impl IntoResponse {
@@ -180,7 +137,7 @@ pub async fn check_password_repeat(Form(user): Form<UserRequest>) -> impl IntoRe
     return HtmlTemplate(template).into_response()
 }
 
-fn hash_password(password: &String) -> Result<String, HashError> {
+pub fn hash_password(password: &String) -> Result<String, HashError> {
     let salt = SaltString::generate(&mut OsRng);
     let hash_password = Argon2::default()
         .hash_password(password.as_bytes(), &salt)
@@ -192,8 +149,8 @@ fn hash_password(password: &String) -> Result<String, HashError> {
 }
 
 #[derive(Debug, Clone)]
-struct HashError {
-    message: &'static str
+pub struct HashError {
+    pub message: &'static str
 }
 
 impl fmt::Display for HashError {
@@ -204,65 +161,81 @@ impl fmt::Display for HashError {
 
 pub async fn login(
     State(state): State<Arc<AppState>>,
-    Form(user): Form<LoginRequest>) -> impl IntoResponse {
+    client: ClientInfo,
+    Form(user): Form<LoginRequest>) -> Result<Response, AppError> {
     info!("request to login");
     let errors = validate_login(&user.username, &user.psw);
     if errors.len() > 0 {
         debug!("login input is invalid");
-        let template = ErrorsTemplate {errors};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::Validation(errors))
     }
 
-    let user_db = sqlx::query_as::<Postgres, UserModel>(
-        "SELECT * FROM users WHERE screen_name = $1",
-        )
-        .bind(&user.username)
-        .fetch_optional(&state.db)
-        .await;
-
-    if let Ok(user_db) = user_db {
-        if let Some(user_db) = user_db {
-            let is_valid = match PasswordHash::new(&user_db.password) {
-                Ok(parsed_hash) => Argon2::default()
-                    .verify_password(user.psw.unwrap().as_bytes(), &parsed_hash)
-                    .map_or(false, |_| true),
-                Err(_) => false,
-            };
-            if !is_valid {
-                debug!("login unsuccessful due to wrong password");
-                let template = ErrorsTemplate {errors: vec!["Wrong password!"]};
-                return HtmlTemplate(template).into_response()
-            }
-            let mut path = String::from("/user");
-            if let Some(friendly_path) = user.redir {
-                path = friendly_path
-            }
-            let mut headers = HeaderMap::new();
-            headers.insert("HX-redirect", path.parse().unwrap());
-            let remember = match user.remember_me {
-                None | Some(false) => false,
-                Some(true) => true
-            };
-            debug!("remember_me={}", remember);
-            let (token, date) = get_token(&user.username);
-
-            let cookie = match remember {
-                false => format!("Token={}; SameSite=Lax", token),
-                true => format!("Token={}; Max-Age={}; SameSite=Lax", token, date),
-            };
-            debug!("cookie: {}", cookie);
-            headers.insert("Set-Cookie", cookie.parse().unwrap());
-            (headers, "Success").into_response()
-        } else {
-            debug!("no such user");
-            let template = ErrorsTemplate {errors: vec!["No such user!"]};
-            return HtmlTemplate(template).into_response()
-        }
-    } else {
-        debug!("login unsuccessful due to db error");
-        let template = ErrorsTemplate {errors: vec!["Database error, please try again later"]};
-        return HtmlTemplate(template).into_response()
+    let username = user.username.clone().unwrap_or_default();
+    if let Err(limited) = state.limiter.check_account(&username, state.clock.now()) {
+        debug!("too many login attempts for {}", username);
+        return Ok(too_many_requests(limited))
     }
+
+    let user_db = state.storage.users.find_by_name(&username).await?;
+
+    // unknown users are checked against a dummy hash, so that the response
+    // takes as long as for a wrong password
+    let password_hash = match &user_db {
+        Some(user_db) => user_db.password.as_str(),
+        None => dummy_hash(),
+    };
+    let is_valid = match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => Argon2::default()
+            .verify_password(user.psw.unwrap().as_bytes(), &parsed_hash)
+            .map_or(false, |_| true),
+        Err(_) => false,
+    };
+    let Some(user_db) = user_db.filter(|_| is_valid) else {
+        debug!("login unsuccessful due to wrong username or password");
+        state.limiter.login_failed(&username, state.clock.now());
+        return Err(AppError::Validation(vec!["Invalid username or password!"]))
+    };
+    state.limiter.login_succeeded(&username);
+
+    let Some(user_id) = user_db.id else {
+        debug!("user has no id");
+        return Err(AppError::Internal("Database error, please try again later"))
+    };
+    let remember = match user.remember_me {
+        None | Some(false) => false,
+        Some(true) => true
+    };
+    debug!("remember_me={}", remember);
+    if user_db.totp_enabled_at.is_some() {
+        debug!("second factor required");
+        return second_factor_required(&state, user_id, remember, user.redir).await
+    }
+    let (token, refresh_token) = start_session(&state, user_id, &client, remember).await?;
+    let path = user.redir.unwrap_or_default();
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-redirect", path.as_str().parse().unwrap());
+    headers.append("Set-Cookie", session::token_cookie(&token, &state.config.session).parse().unwrap());
+    let cookie = session::refresh_cookie(&refresh_token, remember, &state.config.session);
+    headers.append("Set-Cookie", cookie.parse().unwrap());
+    Ok((headers, "Success").into_response())
+}
+
+/// Hash of a random password, verified against when the user doesn't exist.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password(&random_token(16)).unwrap_or_default())
+}
+
+async fn second_factor_required(state: &AppState, user_id: i32, remember: bool, redir: Option<RedirectTarget>) -> Result<Response, AppError> {
+    let challenge = create_challenge(&state.db, user_id, remember).await?;
+    let path = match redir {
+        Some(redir) => format!("/login/2fa?path={}", redir.encoded()),
+        None => String::from("/login/2fa"),
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-redirect", path.parse().unwrap());
+    headers.insert("Set-Cookie", challenge_cookie(&challenge, &state.config.session).parse().unwrap());
+    Ok((headers, "Success").into_response())
 }
 
 pub async fn login_form(user: UserData, query: Query<FriendlyRedirect>) -> impl IntoResponse {
@@ -271,21 +244,41 @@ pub async fn login_form(user: UserData, query: Query<FriendlyRedirect>) -> impl
     return HtmlTemplate(template)
 }
 
-pub async fn logout() -> impl IntoResponse {
+/// Returns the access token and the refresh token of a new session.
+/// Logging in cancels a pending deletion of the account.
+pub async fn start_session(state: &AppState, user_id: i32, client: &ClientInfo, remember: bool) -> Result<(String, String), AppError> {
+    if state.storage.users.cancel_deletion(user_id).await? {
+        info!("account deletion cancelled by login");
+    }
+    let config = &state.config.session;
+    let (token_id, refresh_token) = session::create_session(&state.db, user_id, client, remember, config).await?;
+    let (token, _) = get_token(user_id, &token_id, &state.keys, config.access_token_ttl);
+    Ok((token, refresh_token))
+}
+
+pub async fn logout(user: UserData,
+    State(state): State<Arc<AppState>>) -> impl IntoResponse {
     info!("request to logout");
+    if let Some(token_id) = &user.session {
+        if let Err(err) = session::revoke_session(&state.db, token_id).await {
+            debug!("couldn't revoke session: {}", err);
+        }
+    }
     let mut headers = HeaderMap::new();
     headers.insert("HX-redirect", "/".parse().unwrap());
-    let cookie = String::from("Token=");
-    headers.insert("Set-Cookie", cookie.parse().unwrap());
+    headers.append("Set-Cookie", session::clear_token_cookie(&state.config.session).parse().unwrap());
+    headers.append("Set-Cookie", session::clear_refresh_cookie(&state.config.session).parse().unwrap());
     (headers, "Success").into_response()
 }
 
 pub async fn to_login(query: Query<FriendlyRedirect>) -> impl IntoResponse {
     info!("redir to login requested");
-    let mut headers = HeaderMap::new();
-    let path = format!("/login?path={}", query.path.to_owned().unwrap());
-    headers.insert("HX-redirect", path.parse().unwrap());
-    (headers, "Success").into_response()
+    hx_redirect(&format!("/login?path={}", query.path.clone().unwrap_or_default().encoded()))
+}
+
+pub async fn edit_username() -> impl IntoResponse {
+    info!("username form requested");
+    HtmlTemplate(UsernameFormTemplate {})
 }
 
 pub async fn edit_email() -> impl IntoResponse {
@@ -300,240 +293,234 @@ pub async fn edit_password() -> impl IntoResponse {
     return HtmlTemplate(template)
 }
 
+/// Renames the user. The old name keeps pointing to them, so links to
+/// their profile and blog still work.
+pub async fn update_username(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<UsernameRequest>) -> Result<Response, AppError> {
+    info!("request to update username");
+    let errors = validate_username(&request.username);
+    if !errors.is_empty() {
+        debug!("username is invalid");
+        return Err(AppError::Validation(errors))
+    }
+
+    let username = request.username.unwrap();
+    state.storage.users.rename(current.id, &username).await?;
+    info!("user {} renamed to {}", current.user.screen_name, username);
+
+    // the name is shown all over the page
+    Ok(hx_redirect("/user"))
+}
+
 pub async fn update_email(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
-    Form(request): Form<EmailRequest>) -> impl IntoResponse {
+    Form(request): Form<EmailRequest>) -> Result<Response, AppError> {
     info!("request to update email");
-    let mut errors = validate_email(&request.email);
+    let errors = validate_email(&request.email);
     if errors.len() > 0 {
         debug!("email is invalid");
-        let template = ErrorsTemplate {errors};
-        return HtmlTemplate(template).into_response()
-    }
-    if user.username.is_none() {
-        errors.push("Unauthenticated!");
-        let template = ErrorsTemplate {errors};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::Validation(errors))
     }
 
-    let result = sqlx::query("UPDATE users SET email= $1 WHERE screen_name = $2")
-        .bind(&request.email)
-        .bind(&user.username)
-        .execute(&state.db)
-        .await
-        .map_err(|err: sqlx::Error| err.to_string());
-
-    match result {
-        Err(err) => {
-            debug!("changing email unsuccessful due to db error");
-            if err.contains("duplicate key") && err.contains("email") {
-                errors.push("Email must be unique!");
-            } else {
-                errors.push("Couldn't update due to database error!");
-            }
-            debug!(err);
-            let template = ErrorsTemplate {errors};
-            return HtmlTemplate(template).into_response()
-        },
-        Ok(_) => {
-            let template = EmailFieldTemplate {email: request.email.unwrap()};
-            return HtmlTemplate(template).into_response()
+    // changing the address requires confirming it again
+    let email = request.email.unwrap();
+    let verified = state.storage.users.update_email(current.id, &email).await?;
+    if !verified {
+        if let Err(err) = send_verification(&state, current.id, &current.user.screen_name, &email).await {
+            debug!("couldn't send verification: {}", err);
         }
     }
+    let template = EmailFieldTemplate {email, verified};
+    return Ok(HtmlTemplate(template).into_response())
 }
 
 pub async fn update_password(
-    user: UserData,
+    current: CurrentUser,
     State(state): State<Arc<AppState>>,
-    Form(request): Form<PasswordRequest>) -> impl IntoResponse {
+    Form(request): Form<PasswordRequest>) -> Result<Response, AppError> {
     info!("request to update password");
     let mut errors = validate_password(&request.new_psw);
     errors.append(&mut validate_repeated_password(&request.new_psw, &request.psw_repeat));
     if errors.len() > 0 {
         debug!("password input is invalid");
-        let template = ErrorsTemplate {errors};
-        return HtmlTemplate(template).into_response()
+        return Err(AppError::Validation(errors))
     }
-    if user.username.is_none() {
-        errors.push("Unauthenticated!");
-        let template = ErrorsTemplate {errors};
-        return HtmlTemplate(template).into_response()
+
+    if !verify_password(&request.psw.unwrap_or_default(), &current.user.password) {
+        debug!("password change unsuccessful due to wrong password");
+        return Err(AppError::Validation(vec!["Old is wrong password!"]))
     }
 
-    let user_db = sqlx::query_as::<Postgres, UserModel>(
-        "SELECT * FROM users WHERE screen_name = $1",
-        )
-        .bind(&user.username)
-        .fetch_optional(&state.db)
-        .await;
-
-    if let Ok(user_db) = user_db {
-        if let Some(user_db) = user_db {
-            let is_valid = match PasswordHash::new(&user_db.password) {
-                Ok(parsed_hash) => Argon2::default()
-                    .verify_password(request.psw.unwrap().as_bytes(), &parsed_hash)
-                    .map_or(false, |_| true),
-                Err(_) => false,
-            };
-            if !is_valid {
-                debug!("password change unsuccessful due to wrong password");
-                let template = ErrorsTemplate {errors: vec!["Old is wrong password!"]};
-                return HtmlTemplate(template).into_response()
-            }
-
-            debug!("hashing password...");
-            let password = hash_password(&request.new_psw.unwrap());
-            if let Err(error) = password {
-                error!("there was an error during hashing a password!");
-                let template = ErrorsTemplate { errors: vec![error.message]};
-                return HtmlTemplate(template).into_response()
-            }
-
-            let result = sqlx::query("UPDATE users SET password = $1 WHERE screen_name = $2")
-                .bind(password.unwrap())
-                .bind(&user.username)
-                .execute(&state.db)
-                .await
-                .map_err(|err: sqlx::Error| err.to_string());
-
-            if let Ok(_) = result {
-                let template = PasswordFieldTemplate{};
-                return HtmlTemplate(template).into_response()
-            } else {
-                debug!("password change unsuccessful due to db error");
-                let template = ErrorsTemplate {errors: vec!["Database error, please try again later"]};
-                return HtmlTemplate(template).into_response()
-            }
-        } else {
-            debug!("no such user");
-            let template = ErrorsTemplate {errors: vec!["No such user!"]};
-            return HtmlTemplate(template).into_response()
-        }
-    } else {
-        debug!("password change unsuccessful due to db error");
-        let template = ErrorsTemplate {errors: vec!["Database error, please try again later"]};
-        return HtmlTemplate(template).into_response()
+    debug!("hashing password...");
+    let password = hash_password(&request.new_psw.unwrap()).map_err(|error| {
+        error!("there was an error during hashing a password!");
+        AppError::Internal(error.message)
+    })?;
+
+    state.storage.users.update_password(current.id, &password).await?;
+    let template = PasswordFieldTemplate{};
+    return Ok(HtmlTemplate(template).into_response())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed_hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok(),
+        Err(_) => false,
     }
 }
 
+pub async fn edit_delete_account() -> impl IntoResponse {
+    info!("account deletion form requested");
+    let template = DeleteAccountFormTemplate {};
+    return HtmlTemplate(template)
+}
+
+/// Logs the user out everywhere. The account is removed once the grace period
+/// is over, unless they log in again before that.
+pub async fn delete_account(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<DeleteAccountRequest>) -> Result<Response, AppError> {
+    info!("request to delete account");
+    if !verify_password(&request.psw.unwrap_or_default(), &current.user.password) {
+        debug!("account deletion unsuccessful due to wrong password");
+        return Err(AppError::Validation(vec!["Wrong password!"]))
+    }
+
+    let due_at = state.clock.now() + chrono::Duration::seconds(state.config.accounts.deletion_grace_period);
+    state.storage.users.schedule_deletion(current.id, due_at).await?;
+    session::revoke_all_sessions(&state.db, current.id).await?;
+    info!("account deletion scheduled for {}.", due_at);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-redirect", "/".parse().unwrap());
+    headers.append("Set-Cookie", session::clear_token_cookie(&state.config.session).parse().unwrap());
+    headers.append("Set-Cookie", session::clear_refresh_cookie(&state.config.session).parse().unwrap());
+    Ok((headers, "Success").into_response())
+}
+
 pub async fn edit_avatar() -> impl IntoResponse {
     info!("avatar form requested");
     let template = AvatarFormTemplate {};
     return HtmlTemplate(template)
 }
 
-pub async fn upload_avatar(user: UserData,
-    State(state): State<Arc<AppState>>,
-    mut multipart: Multipart) -> impl IntoResponse {
-    let Some(username) = user.username else {
-        let template = ErrorsTemplate {errors: vec!["You're unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
-    };
-    let Ok(Some(field)) = multipart.next_field().await else {
-        println!("No fields in form!");
-        let template = ErrorsTemplate {errors: vec!["Form is empty!"]};
-        return HtmlTemplate(template).into_response()
+/// Reads the image of an upload form, together with the crop box given next to it.
+async fn read_image_form(multipart: &mut Multipart, max_size: usize) -> Result<(Vec<u8>, Option<CropBox>), AppError> {
+    let mut data = None;
+    let mut fields = HashMap::new();
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "image" {
+            data = Some(read_upload(field, max_size).await?);
+        } else if let Ok(text) = field.text().await {
+            fields.insert(name, text);
+        }
+    }
+    let Some(data) = data else {
+        debug!("No image in form!");
+        return Err(AppError::Validation(vec!["Form is empty!"]))
     };
+    let field = |name: &str| fields.get(name).map(String::as_str);
+    let crop = CropBox::from_fields(field("x"), field("y"), field("width"), field("height"))?;
+    Ok((data, crop))
+}
 
-    let Ok(mut data) = field.bytes().await else {
-        println!("No avatar data!");
-        let template = ErrorsTemplate {errors: vec!["No avatar data!"]};
-        return HtmlTemplate(template).into_response()
+/// Makes every size of the uploaded image and stores them, then hands their key to `set_key`.
+/// The image the user had before is removed, unless the same one was uploaded again.
+async fn upload_sized_image<F, Fut, T: Template>(image: &'static SizedImage,
+    state: &AppState,
+    user_id: i32,
+    old_key: Option<String>,
+    multipart: &mut Multipart,
+    set_key: F,
+    template: fn(Option<String>) -> T) -> Result<Response, AppError>
+    where F: FnOnce(Option<String>) -> Fut, Fut: Future<Output = StorageResult<()>> {
+    let (data, crop) = read_image_form(multipart, state.config.assets.max_upload_size).await?;
+
+    debug!("Length of {} for user {} is {} bytes", image.name, user_id, data.len());
+    let limits = ImageLimits::from_config(&state.config.assets);
+    let images = tokio::task::spawn_blocking(move || image.make(&data, crop, &limits))
+        .await
+        .map_err(|_| AppError::Internal("Couldn't convert the image!"))??;
+    let key = match image.save(state.media.as_ref(), user_id, &images).await {
+        Ok(key) => key,
+        Err(err) => {
+            error!("couldn't save {} of user {}: {}", image.name, user_id, err);
+            return Err(AppError::Internal("Couldn't save file!"))
+        }
     };
-    if !validate_filetype(&data) {
-        println!("Wrong format!");
-        let template = ErrorsTemplate {errors: vec!["Avatar must be either JPG or PNG!"]};
-        return HtmlTemplate(template).into_response()
-    }
-
-    if is_jpg(&data) {
-        let png = convert_jpg_to_png(&data);
-        if let Ok(png) = png {
-            data = png;
-        } else {
-            println!("Couldn't convert to png!");
-            let template = ErrorsTemplate {errors: vec!["There was an error while converting JPG to PNG!"]};
-            return HtmlTemplate(template).into_response()
+    set_key(Some(key.clone())).await?;
+    // uploading the same image again gives the same key
+    if let Some(old_key) = old_key.filter(|old_key| *old_key != key) {
+        if let Err(err) = image.remove(state.media.as_ref(), &old_key).await {
+            warn!("couldn't delete old {} of user {}: {}", image.name, user_id, err);
         }
     }
 
-    debug!("Length of avatar for user {} is {} bytes", username, data.len());
-    let filename = format!("assets/avatars/{}.png", username);
-    if let Ok(_) = std::fs::write(filename, data) {
-        let result = sqlx::query("UPDATE users SET avatar=true WHERE screen_name = $1")
-            .bind(&username)
-            .execute(&state.db)
-            .await
-            .map_err(|err: sqlx::Error| err.to_string());
-
-        match result {
-            Err(_) => {
-                let template = ErrorsTemplate {errors: vec!["Db error! Please try again later."]};
-                return HtmlTemplate(template).into_response()
-            },
-            Ok(_) => {
-                let dt = Utc::now();
-                let timestamp: i64 = dt.timestamp();
-                let template = AvatarResultTemplate {avatar: true, username, timestamp};
-                return HtmlTemplate(template).into_response()
-            }
-        };
-    };
-    let template = ErrorsTemplate {errors: vec!["Couldn't save file!"]};
-    return HtmlTemplate(template).into_response()
+    Ok(HtmlTemplate(template(Some(state.media.sized_url(&key, image.largest())))).into_response())
 }
 
-fn validate_filetype(file: &Bytes) -> bool {
-    match imghdr::from_bytes(file) {
-        Some(imghdr::Type::Png) => true,
-        Some(imghdr::Type::Jpeg) => true,
-        _ => false,
+/// Removes every size of the image stored under `key`, then clears it with `set_key`.
+async fn delete_sized_image<F, Fut, T: Template>(image: &SizedImage,
+    state: &AppState,
+    user_id: i32,
+    key: &str,
+    set_key: F,
+    template: fn(Option<String>) -> T) -> Result<Response, AppError>
+    where F: FnOnce(Option<String>) -> Fut, Fut: Future<Output = StorageResult<()>> {
+    if let Err(err) = image.remove(state.media.as_ref(), key).await {
+        error!("couldn't delete {} of user {}: {}", image.name, user_id, err);
+        return Err(AppError::Internal("Couldn't delete image!"))
     }
-}
+    set_key(None).await?;
 
-fn is_jpg(file: &Bytes) -> bool {
-    match imghdr::from_bytes(file) {
-        Some(imghdr::Type::Jpeg) => true,
-        _ => false,
-    }
+    Ok(HtmlTemplate(template(None)).into_response())
 }
 
-fn convert_jpg_to_png(image: &Bytes) -> Result<Bytes, image::ImageError> {
-    let img = image::load_from_memory(image)?;
-    let rgba_img = img.to_rgba8();
-    let mut bytes = Vec::new();
-    rgba_img.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)?;
-    let bytes = Bytes::from(bytes);
-    Ok(bytes)
+pub async fn upload_avatar(current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart) -> Result<Response, AppError> {
+    let users = &state.storage.users;
+    let set_key = |key: Option<String>| async move { users.set_avatar(current.id, key.as_deref()).await };
+    upload_sized_image(&AVATARS, &state, current.id, current.user.avatar_key, &mut multipart, set_key, |avatar| AvatarResultTemplate {avatar}).await
 }
 
-pub async fn delete_avatar(user: UserData,
-    State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let Some(username) = user.username else {
-        let template = ErrorsTemplate {errors: vec!["You're unauthenticated!"]};
-        return HtmlTemplate(template).into_response()
+pub async fn delete_avatar(current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let Some(key) = current.user.avatar_key else {
+        return Err(AppError::NotFound("Couldn't delete avatar!"))
     };
+    let users = &state.storage.users;
+    let set_key = |key: Option<String>| async move { users.set_avatar(current.id, key.as_deref()).await };
+    delete_sized_image(&AVATARS, &state, current.id, &key, set_key, |avatar| AvatarResultTemplate {avatar}).await
+}
+
+pub async fn edit_banner() -> impl IntoResponse {
+    info!("banner form requested");
+    let template = BannerFormTemplate {};
+    return HtmlTemplate(template)
+}
+
+pub async fn upload_banner(current: CurrentUser,
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart) -> Result<Response, AppError> {
+    let users = &state.storage.users;
+    let set_key = |key: Option<String>| async move { users.set_banner(current.id, key.as_deref()).await };
+    upload_sized_image(&BANNERS, &state, current.id, current.user.banner_key, &mut multipart, set_key, |banner| BannerResultTemplate {banner}).await
+}
 
-    let filename = format!("assets/avatars/{}.png", username);
-    if let Ok(_) = std::fs::remove_file(filename) {
-        let result = sqlx::query("UPDATE users SET avatar=false WHERE screen_name = $1")
-            .bind(&username)
-            .execute(&state.db)
-            .await
-            .map_err(|err: sqlx::Error| err.to_string());
-
-        match result {
-            Err(_) => {
-                let template = ErrorsTemplate {errors: vec!["Db error! Please try again later."]};
-                return HtmlTemplate(template).into_response()
-            },
-            Ok(_) => {
-                let template = AvatarResultTemplate {avatar: false, username, timestamp: 0};
-                return HtmlTemplate(template).into_response()
-            }
-        };
+pub async fn delete_banner(current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let Some(key) = current.user.banner_key else {
+        return Err(AppError::NotFound("Couldn't delete banner!"))
     };
-    let template = ErrorsTemplate {errors: vec!["Couldn't delete avatar!"]};
-    return HtmlTemplate(template).into_response()
+    let users = &state.storage.users;
+    let set_key = |key: Option<String>| async move { users.set_banner(current.id, key.as_deref()).await };
+    delete_sized_image(&BANNERS, &state, current.id, &key, set_key, |banner| BannerResultTemplate {banner}).await
 }
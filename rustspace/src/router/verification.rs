@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use axum::{response::{IntoResponse, Response}, extract::{State, Query}};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::{info, debug, error};
+
+use crate::{template::{HtmlTemplate, EmailVerifiedTemplate, VerificationSentTemplate}, error::AppError, UserData, AppState, auth::CurrentUser, security::{hash_token, random_token}, mailer::Email};
+
+/// Lifetime of an email verification link in seconds.
+const VERIFICATION_TOKEN_MAX_AGE: i64 = 24*60*60;
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    token: Option<String>
+}
+
+/// Sends a link confirming that `email` belongs to the user.
+pub async fn send_verification(state: &AppState, user_id: i32, username: &str, email: &str) -> Result<(), sqlx::Error> {
+    let token = random_token(32);
+    sqlx::query!("INSERT INTO email_verifications (token_hash, email, user_id, expires_at) VALUES ($1, $2, $3, now() + make_interval(secs => $4))",
+        hash_token(&token), email, user_id, VERIFICATION_TOKEN_MAX_AGE as f64)
+        .execute(&state.db)
+        .await?;
+
+    let link = format!("{}/email/verify?token={}", state.config.server.public_url.trim_end_matches('/'), token);
+    let message = Email {
+        to: String::from(email),
+        subject: String::from("Confirm your RustSpace email address"),
+        body: format!("Hi {},\n\nplease confirm your email address by opening the link below:\n\n{}\n\n\
+            The link is valid for 24 hours.\n",
+            username, link),
+    };
+    if let Err(err) = state.mailer.send(message).await {
+        error!("couldn't send verification mail: {}", err);
+    }
+    Ok(())
+}
+
+/// Returns an error if the site requires verified addresses and the user hasn't confirmed theirs.
+pub async fn verified_email_required(state: &AppState, current: &CurrentUser) -> Result<(), AppError> {
+    if !state.config.accounts.require_verified_email || current.user.email_verified_at.is_some() {
+        return Ok(());
+    }
+    Err(AppError::Forbidden("Please verify your email address first!"))
+}
+
+pub async fn verify_email(
+    user: UserData,
+    State(state): State<Arc<AppState>>,
+    query: Query<VerifyQuery>) -> impl IntoResponse {
+    info!("email verification requested");
+    let verified = match &query.token {
+        Some(token) => verify_with_token(&state.db, token).await,
+        None => Ok(false),
+    };
+    let verified = match verified {
+        Ok(verified) => verified,
+        Err(err) => {
+            debug!("Database error: {}", err);
+            false
+        }
+    };
+    if verified {
+        info!("email succesfully verified.");
+    }
+    let template = EmailVerifiedTemplate {path: "user", user, verified};
+    return HtmlTemplate(template)
+}
+
+/// Marks the address as verified, unless the token is used, expired,
+/// or the user has changed their email since the link was sent.
+async fn verify_with_token(db: &PgPool, token: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let verification = sqlx::query!(
+        "UPDATE email_verifications SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id, email",
+        hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(verification) = verification else {
+        return Ok(false)
+    };
+
+    let result = sqlx::query!("UPDATE users SET email_verified_at = now() WHERE id = $1 AND email = $2",
+        verification.user_id, verification.email)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn resend_verification(
+    current: CurrentUser,
+    State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    info!("resending verification email requested");
+    if current.user.email_verified_at.is_some() {
+        return Err(AppError::Validation(vec!["Email is already verified!"]))
+    }
+
+    send_verification(&state, current.id, &current.user.screen_name, &current.user.email).await?;
+    let template = VerificationSentTemplate {};
+    return Ok(HtmlTemplate(template).into_response())
+}
@@ -1,36 +1,304 @@
-use jsonwebtoken::{encode, Header, EncodingKey};
+use std::path::{Path, PathBuf};
+
+use jsonwebtoken::{encode, decode, decode_header, Header, EncodingKey, DecodingKey, Algorithm, Validation, errors::ErrorKind};
+use rand_core::{OsRng, RngCore};
 use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
 
-pub fn get_token(username: &Option<String>) -> (String, i64) {
-    let username = match username {
-        Some(username) => username,
-        None => ""
-    };
-    let max_age = 60;
+use crate::config::{JwtConfig, JwtAlgorithm};
+
+/// Creates an access token valid for `max_age` seconds. The subject is the id of
+/// the user, which unlike the name never changes.
+pub fn get_token(user_id: i32, token_id: &str, keys: &JwtKeys, max_age: i64) -> (String, i64) {
     let now = chrono::Utc::now();
     let iat = now.timestamp() as usize;
-    let exp = (now + chrono::Duration::minutes(max_age)).timestamp() as usize;
+    let exp = (now + chrono::Duration::seconds(max_age)).timestamp() as usize;
     let claims: TokenClaims = TokenClaims {
-        sub: String::from(username),
+        sub: user_id.to_string(),
+        jti: String::from(token_id),
         exp,
         iat,
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret("secret".as_ref()),
-    );
+    let token = keys.encode(&claims);
 
     match token {
-        Ok(token) => (token, max_age*60),
-        Err(_) => (String::from(""), max_age*60)
+        Ok(token) => (token, max_age),
+        Err(_) => (String::from(""), max_age)
     }
 }
 
+/// Hex encoded random token of `bytes` bytes.
+pub fn random_token(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    buffer.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hex encoded SHA-256 of an opaque token, so that tokens are never stored in plain text.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: String,
+    pub jti: String,
     pub iat: usize,
     pub exp: usize,
 }
+
+/// Key used to sign new tokens together with every key tokens are still verified against.
+pub struct JwtKeys {
+    header: Header,
+    encoding: EncodingKey,
+    verification: Vec<VerificationKey>,
+}
+
+struct VerificationKey {
+    key_id: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+#[derive(Debug)]
+pub enum KeyError {
+    Io(PathBuf, std::io::Error),
+    Format(PathBuf, jsonwebtoken::errors::Error),
+}
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KeyError::Io(path, err) => write!(f, "couldn't read key {}: {}", path.display(), err),
+            KeyError::Format(path, err) => write!(f, "invalid key {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+impl JwtKeys {
+    pub fn from_config(config: &JwtConfig) -> Result<JwtKeys, KeyError> {
+        let algorithm = to_algorithm(config.algorithm);
+        let mut header = Header::new(algorithm);
+        header.kid = config.key_id.clone();
+
+        let (encoding, decoding) = match config.algorithm {
+            JwtAlgorithm::HS256 => (
+                EncodingKey::from_secret(config.secret.as_ref()),
+                DecodingKey::from_secret(config.secret.as_ref()),
+            ),
+            _ => (
+                load_encoding_key(config.algorithm, config.private_key.as_deref().unwrap_or(Path::new("")))?,
+                load_decoding_key(config.algorithm, config.public_key.as_deref().unwrap_or(Path::new("")))?,
+            ),
+        };
+
+        let mut verification = vec![VerificationKey {key_id: config.key_id.clone(), algorithm, key: decoding}];
+        for previous in &config.previous {
+            let key = match previous.algorithm {
+                JwtAlgorithm::HS256 => DecodingKey::from_secret(previous.secret.as_deref().unwrap_or("").as_ref()),
+                _ => load_decoding_key(previous.algorithm, previous.public_key.as_deref().unwrap_or(Path::new("")))?,
+            };
+            verification.push(VerificationKey {
+                key_id: previous.key_id.clone(),
+                algorithm: to_algorithm(previous.algorithm),
+                key,
+            });
+        }
+
+        Ok(JwtKeys { header, encoding, verification })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        encode(&self.header, claims, &self.encoding)
+    }
+
+    /// Verifies the token against the key named in its `kid` header. Tokens without
+    /// `kid` are tried against every key using the same algorithm.
+    pub fn decode(&self, token: &str) -> Result<TokenClaims, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+        let candidates = self.verification
+            .iter()
+            .filter(|key| key.algorithm == header.alg)
+            .filter(|key| header.kid.is_none() || key.key_id == header.kid);
+
+        let mut result = Err(ErrorKind::InvalidSignature.into());
+        for key in candidates {
+            result = decode::<TokenClaims>(token, &key.key, &Validation::new(key.algorithm))
+                .map(|data| data.claims);
+            match &result {
+                Err(err) if matches!(err.kind(), ErrorKind::InvalidSignature) => continue,
+                _ => break,
+            }
+        }
+        result
+    }
+}
+
+fn to_algorithm(algorithm: JwtAlgorithm) -> Algorithm {
+    match algorithm {
+        JwtAlgorithm::HS256 => Algorithm::HS256,
+        JwtAlgorithm::RS256 => Algorithm::RS256,
+        JwtAlgorithm::EdDSA => Algorithm::EdDSA,
+    }
+}
+
+fn load_encoding_key(algorithm: JwtAlgorithm, path: &Path) -> Result<EncodingKey, KeyError> {
+    let pem = std::fs::read(path).map_err(|err| KeyError::Io(path.to_path_buf(), err))?;
+    let key = match algorithm {
+        JwtAlgorithm::HS256 => Ok(EncodingKey::from_secret(&pem)),
+        JwtAlgorithm::RS256 => EncodingKey::from_rsa_pem(&pem),
+        JwtAlgorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+    };
+    key.map_err(|err| KeyError::Format(path.to_path_buf(), err))
+}
+
+fn load_decoding_key(algorithm: JwtAlgorithm, path: &Path) -> Result<DecodingKey, KeyError> {
+    let pem = std::fs::read(path).map_err(|err| KeyError::Io(path.to_path_buf(), err))?;
+    let key = match algorithm {
+        JwtAlgorithm::HS256 => Ok(DecodingKey::from_secret(&pem)),
+        JwtAlgorithm::RS256 => DecodingKey::from_rsa_pem(&pem),
+        JwtAlgorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+    };
+    key.map_err(|err| KeyError::Format(path.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use jsonwebtoken::decode_header;
+
+    use crate::{config::{JwtConfig, JwtKeyConfig, JwtAlgorithm}, security::{JwtKeys, get_token}};
+
+    fn key_path(name: &str) -> Option<PathBuf> {
+        Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/test/keys").join(name))
+    }
+
+    fn hmac_config(key_id: &str, secret: &str) -> JwtConfig {
+        JwtConfig {
+            key_id: Some(String::from(key_id)),
+            secret: String::from(secret),
+            ..Default::default()
+        }
+    }
+
+    fn rsa_config(key_id: &str) -> JwtConfig {
+        JwtConfig {
+            algorithm: JwtAlgorithm::RS256,
+            key_id: Some(String::from(key_id)),
+            private_key: key_path("rsa.pem"),
+            public_key: key_path("rsa.pub.pem"),
+            ..Default::default()
+        }
+    }
+
+    fn ed_config(key_id: &str) -> JwtConfig {
+        JwtConfig {
+            algorithm: JwtAlgorithm::EdDSA,
+            key_id: Some(String::from(key_id)),
+            private_key: key_path("ed25519.pem"),
+            public_key: key_path("ed25519.pub.pem"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hmac_token_roundtrip() {
+        let keys = JwtKeys::from_config(&hmac_config("current", "secret")).unwrap();
+        let (token, _) = get_token(1, "session", &keys, 3600);
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.kid, Some(String::from("current")));
+        let claims = keys.decode(&token).unwrap();
+        assert_eq!(claims.sub, "1");
+    }
+
+    #[test]
+    fn test_rsa_token_roundtrip() {
+        let keys = JwtKeys::from_config(&rsa_config("rsa")).unwrap();
+        let (token, _) = get_token(1, "session", &keys, 3600);
+        let claims = keys.decode(&token).unwrap();
+        assert_eq!(claims.sub, "1");
+    }
+
+    #[test]
+    fn test_eddsa_token_roundtrip() {
+        let keys = JwtKeys::from_config(&ed_config("ed")).unwrap();
+        let (token, _) = get_token(1, "session", &keys, 3600);
+        let claims = keys.decode(&token).unwrap();
+        assert_eq!(claims.sub, "1");
+    }
+
+    #[test]
+    fn test_token_signed_with_other_secret_is_rejected() {
+        let keys = JwtKeys::from_config(&hmac_config("current", "secret")).unwrap();
+        let other = JwtKeys::from_config(&hmac_config("current", "other")).unwrap();
+        let (token, _) = get_token(1, "session", &other, 3600);
+        assert!(keys.decode(&token).is_err());
+    }
+
+    #[test]
+    fn test_token_signed_with_previous_key_is_accepted() {
+        let old = JwtKeys::from_config(&hmac_config("old", "old_secret")).unwrap();
+        let (token, _) = get_token(1, "session", &old, 3600);
+
+        let mut config = ed_config("new");
+        config.previous.push(JwtKeyConfig {
+            key_id: Some(String::from("old")),
+            algorithm: JwtAlgorithm::HS256,
+            secret: Some(String::from("old_secret")),
+            public_key: None,
+        });
+        let keys = JwtKeys::from_config(&config).unwrap();
+        let claims = keys.decode(&token).unwrap();
+        assert_eq!(claims.sub, "1");
+
+        let (token, _) = get_token(1, "session", &keys, 3600);
+        assert_eq!(decode_header(&token).unwrap().kid, Some(String::from("new")));
+    }
+
+    #[test]
+    fn test_token_without_key_id_is_checked_against_all_keys() {
+        let mut legacy = hmac_config("old", "old_secret");
+        legacy.key_id = None;
+        let old = JwtKeys::from_config(&legacy).unwrap();
+        let (token, _) = get_token(1, "session", &old, 3600);
+
+        let mut config = hmac_config("new", "new_secret");
+        config.previous.push(JwtKeyConfig {
+            key_id: Some(String::from("old")),
+            algorithm: JwtAlgorithm::HS256,
+            secret: Some(String::from("old_secret")),
+            public_key: None,
+        });
+        let keys = JwtKeys::from_config(&config).unwrap();
+        assert!(keys.decode(&token).is_ok());
+    }
+
+    #[test]
+    fn test_token_with_retired_key_id_is_rejected() {
+        let old = JwtKeys::from_config(&hmac_config("old", "old_secret")).unwrap();
+        let (token, _) = get_token(1, "session", &old, 3600);
+        let keys = JwtKeys::from_config(&hmac_config("new", "old_secret")).unwrap();
+        assert!(keys.decode(&token).is_err());
+    }
+
+    #[test]
+    fn test_expired_token_is_rejected() {
+        let keys = JwtKeys::from_config(&hmac_config("current", "secret")).unwrap();
+        let (token, _) = get_token(1, "session", &keys, -120);
+        assert!(keys.decode(&token).is_err());
+    }
+
+    #[test]
+    fn test_loading_missing_key_file() {
+        let mut config = rsa_config("rsa");
+        config.private_key = key_path("missing.pem");
+        assert!(JwtKeys::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use std::{net::SocketAddr, convert::Infallible, sync::{Arc, Mutex}};
+
+use axum::{extract::{FromRequestParts, ConnectInfo, Request}, http::{request::Parts, HeaderValue, header::SET_COOKIE}, middleware::Next, response::Response, async_trait};
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use sqlx::{PgPool, PgExecutor, Postgres};
+use time::Duration;
+
+use crate::{SessionModel, config::SessionConfig, security::{hash_token, random_token}};
+
+/// Reusing a refresh token within this many seconds of its rotation is treated
+/// as a race between parallel requests rather than as a stolen token.
+const REUSE_GRACE_PERIOD: f64 = 10.0;
+
+/// Device information stored with a session.
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientInfo::from_parts(parts))
+    }
+}
+
+impl ClientInfo {
+    pub fn from_parts(parts: &Parts) -> ClientInfo {
+        let ip = parts.extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip().to_string());
+        let user_agent = parts.headers
+            .get("User-Agent")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        ClientInfo { ip, user_agent }
+    }
+}
+
+pub fn new_token_id() -> String {
+    random_token(16)
+}
+
+/// Starts a new session and returns its token id together with the first refresh token.
+pub async fn create_session(db: &PgPool, user_id: i32, client: &ClientInfo, remember: bool, config: &SessionConfig) -> Result<(String, String), sqlx::Error> {
+    let token_id = new_token_id();
+    let max_age = config.refresh_ttl(remember);
+    let mut tx = db.begin().await?;
+    let session_id = sqlx::query_scalar!(
+        "INSERT INTO sessions (token_id, user_id, user_agent, ip, remember, expires_at)
+        VALUES ($1, $2, $3, $4, $5, now() + make_interval(secs => $6))
+        RETURNING id",
+        token_id, user_id, client.user_agent, client.ip, remember, max_age as f64)
+        .fetch_one(&mut *tx)
+        .await?;
+    let refresh_token = insert_refresh_token(&mut tx, session_id, max_age).await?;
+    tx.commit().await?;
+    Ok((token_id, refresh_token))
+}
+
+async fn insert_refresh_token(tx: &mut sqlx::Transaction<'_, Postgres>, session_id: i32, max_age: i64) -> Result<String, sqlx::Error> {
+    let refresh_token = random_token(32);
+    sqlx::query!("INSERT INTO refresh_tokens (token_hash, session_id, expires_at) VALUES ($1, $2, now() + make_interval(secs => $3))",
+        hash_token(&refresh_token), session_id, max_age as f64)
+        .execute(&mut **tx)
+        .await?;
+    Ok(refresh_token)
+}
+
+pub struct RenewedSession {
+    pub token_id: String,
+    pub user_id: i32,
+    pub username: String,
+    pub refresh_token: String,
+    pub remember: bool,
+}
+
+pub enum Refresh {
+    Renewed(RenewedSession),
+    /// The token was rotated by a parallel request a moment ago.
+    Raced,
+    /// An already rotated token was presented again, the whole session was revoked.
+    Reused,
+    Invalid,
+}
+
+/// Rotates the refresh token and extends the session it belongs to.
+pub async fn refresh_session(db: &PgPool, refresh_token: &str, ip: &Option<String>, config: &SessionConfig) -> Result<Refresh, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let token = sqlx::query!(
+        r#"SELECT session_id, extract(epoch FROM now() - used_at)::float8 AS used_ago, expires_at <= now() AS "expired!"
+        FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE"#,
+        hash_token(refresh_token))
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(token) = token else {
+        return Ok(Refresh::Invalid)
+    };
+
+    if let Some(used_ago) = token.used_ago {
+        if used_ago < REUSE_GRACE_PERIOD {
+            return Ok(Refresh::Raced)
+        }
+        sqlx::query!("UPDATE sessions SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL", token.session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(Refresh::Reused)
+    }
+    if token.expired {
+        return Ok(Refresh::Invalid)
+    }
+
+    let session = sqlx::query!(
+        "UPDATE sessions s SET last_seen_at = now(), ip = COALESCE($2, s.ip),
+            expires_at = now() + make_interval(secs => CASE WHEN s.remember THEN $3::float8 ELSE $4::float8 END)
+        FROM users u
+        WHERE s.id = $1 AND u.id = s.user_id AND s.revoked_at IS NULL AND s.expires_at > now()
+        RETURNING s.token_id, u.id AS user_id, u.screen_name, s.remember",
+        token.session_id, ip.as_deref(), config.refresh_ttl(true) as f64, config.refresh_ttl(false) as f64)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(session) = session else {
+        return Ok(Refresh::Invalid)
+    };
+
+    sqlx::query!("UPDATE refresh_tokens SET used_at = now() WHERE token_hash = $1", hash_token(refresh_token))
+        .execute(&mut *tx)
+        .await?;
+    let refresh_token = insert_refresh_token(&mut tx, token.session_id, config.refresh_ttl(session.remember)).await?;
+    tx.commit().await?;
+
+    Ok(Refresh::Renewed(RenewedSession {
+        token_id: session.token_id,
+        user_id: session.user_id,
+        username: session.screen_name,
+        refresh_token,
+        remember: session.remember,
+    }))
+}
+
+pub struct SessionOwner {
+    pub user_id: i32,
+    pub username: String,
+}
+
+/// Marks the session as used and returns its owner, or `None` if the session
+/// was revoked, expired or never existed.
+pub async fn touch_session(db: &PgPool, token_id: &str, ip: &Option<String>) -> Result<Option<SessionOwner>, sqlx::Error> {
+    sqlx::query_as!(SessionOwner,
+        "UPDATE sessions s SET last_seen_at = now(), ip = COALESCE($2, s.ip)
+        FROM users u
+        WHERE s.token_id = $1 AND u.id = s.user_id AND s.revoked_at IS NULL AND s.expires_at > now()
+        RETURNING u.id AS user_id, u.screen_name AS username",
+        token_id, ip.as_deref())
+        .fetch_optional(db)
+        .await
+}
+
+pub async fn revoke_session(db: &PgPool, token_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("UPDATE sessions SET revoked_at = now() WHERE token_id = $1 AND revoked_at IS NULL", token_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn revoke_user_session(db: &PgPool, user_id: i32, session_id: i32) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!("UPDATE sessions SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL RETURNING token_id",
+        session_id, user_id)
+        .fetch_optional(db)
+        .await
+}
+
+pub async fn revoke_all_sessions<'e, E: PgExecutor<'e>>(db: E, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!("UPDATE sessions SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL", user_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_active_sessions(db: &PgPool, user_id: i32) -> Result<Vec<SessionModel>, sqlx::Error> {
+    sqlx::query_as!(SessionModel,
+        "SELECT id, token_id, user_id, user_agent, ip, created_at, last_seen_at, expires_at, revoked_at
+        FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > now()
+        ORDER BY last_seen_at DESC",
+        user_id)
+        .fetch_all(db)
+        .await
+}
+
+/// Base for every cookie the site sets, so that they all get the same attributes.
+pub fn cookie(name: &'static str, value: &str, config: &SessionConfig) -> Cookie<'static> {
+    Cookie::build((name, String::from(value)))
+        .path("/")
+        .http_only(true)
+        .secure(config.secure_cookies)
+        .same_site(SameSite::Lax)
+        .build()
+}
+
+pub fn removal_cookie(name: &'static str, path: &'static str, config: &SessionConfig) -> String {
+    let mut cookie = cookie(name, "", config);
+    cookie.set_path(path);
+    cookie.set_max_age(Duration::ZERO);
+    cookie.to_string()
+}
+
+pub fn token_cookie(token: &str, config: &SessionConfig) -> String {
+    cookie("Token", token, config).to_string()
+}
+
+/// Refresh cookies of "remember me" sessions survive closing the browser.
+pub fn refresh_cookie(token: &str, remember: bool, config: &SessionConfig) -> String {
+    let mut cookie = cookie("Refresh", token, config);
+    if remember {
+        cookie.set_max_age(Duration::seconds(config.refresh_token_ttl));
+    }
+    cookie.to_string()
+}
+
+pub fn clear_token_cookie(config: &SessionConfig) -> String {
+    removal_cookie("Token", "/", config)
+}
+
+pub fn clear_refresh_cookie(config: &SessionConfig) -> String {
+    removal_cookie("Refresh", "/", config)
+}
+
+/// Cookies the `UserData` extractor wants to set after renewing a session.
+#[derive(Clone, Default)]
+pub struct PendingCookies(Arc<Mutex<Vec<String>>>);
+
+impl PendingCookies {
+    pub fn push(&self, cookie: String) {
+        if let Ok(mut cookies) = self.0.lock() {
+            cookies.push(cookie);
+        }
+    }
+
+    fn take(&self) -> Vec<String> {
+        match self.0.lock() {
+            Ok(mut cookies) => std::mem::take(&mut *cookies),
+            Err(_) => vec![],
+        }
+    }
+}
+
+/// Appends cookies set during extraction to the response, unless the handler
+/// already set a cookie of the same name (e.g. on logout).
+pub async fn apply_pending_cookies(mut request: Request, next: Next) -> Response {
+    let pending = PendingCookies::default();
+    request.extensions_mut().insert(pending.clone());
+    let mut response = next.run(request).await;
+
+    let set_by_handler: Vec<String> = response.headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split('=').next())
+        .map(String::from)
+        .collect();
+    for cookie in pending.take() {
+        let name = cookie.split('=').next().unwrap_or("");
+        if set_by_handler.iter().any(|set| set == name) {
+            continue;
+        }
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+    response
+}
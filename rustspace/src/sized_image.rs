@@ -0,0 +1,149 @@
+use crate::{error::AppError, media::{MediaStore, MediaError}, images::{self, ImageLimits, CropBox, Variants, store_variants, remove_variants}};
+
+/// Picture of a user kept in a few widths, each page showing the one it needs.
+pub struct SizedImage {
+    /// What the picture is called in logs.
+    pub name: &'static str,
+    /// Start of the media keys, followed by the id of the user.
+    prefix: &'static str,
+    /// Widths in pixels from the smallest to the largest.
+    pub widths: &'static [u32],
+    /// How many times wider than high the images are.
+    ratio: u32,
+}
+
+/// Square avatars for list entries, comments and the profile page.
+pub const AVATARS: SizedImage = SizedImage { name: "avatar", prefix: "avatars", widths: &[32, 64, 256], ratio: 1 };
+/// Profile banners, the smaller width for narrow screens.
+pub const BANNERS: SizedImage = SizedImage { name: "banner", prefix: "banners", widths: &[750, 1500], ratio: 3 };
+
+impl SizedImage {
+    /// Width shown on the profile, and the one handed out in data exports.
+    pub const fn largest(&self) -> u32 {
+        self.widths[self.widths.len() - 1]
+    }
+
+    /// Decodes the uploaded image and returns it in each of the widths, as WebP,
+    /// or as GIF when the upload is animated. Without a crop box the middle of the image is used.
+    pub fn make(&self, data: &[u8], crop: Option<CropBox>, limits: &ImageLimits) -> Result<Variants, AppError> {
+        let picture = images::decode(data, limits)?.cropped(crop)?;
+
+        let images = self.widths.iter()
+            .map(|&width| Ok((width, picture.resize_to_fill(width, width / self.ratio).encode()?)))
+            .collect::<Result<_, AppError>>()?;
+        Ok(Variants { format: picture.format(), images })
+    }
+
+    /// Stores every width of the image and returns the key they share, which is kept with the user.
+    pub async fn save(&self, media: &dyn MediaStore, user_id: i32, images: &Variants) -> Result<String, MediaError> {
+        store_variants(media, &format!("{}/{}", self.prefix, user_id), images).await
+    }
+
+    pub async fn remove(&self, media: &dyn MediaStore, key: &str) -> Result<(), MediaError> {
+        remove_variants(media, key, self.widths).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{RgbaImage, Rgba, Frame, Delay, codecs::gif::GifEncoder};
+
+    use crate::{sized_image::{AVATARS, BANNERS}, images::{ImageLimits, OutputFormat, CropBox}, test::fixtures::png};
+
+    const LIMITS: ImageLimits = ImageLimits { max_dimension: 1000, max_frames: 10 };
+
+    fn gif(frames: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for i in 0..frames {
+                let image = RgbaImage::from_pixel(40, 40, Rgba([(i * 50) as u8, 0, 0, 255]));
+                encoder.encode_frame(Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(100, 1))).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_making_avatars_in_every_size() {
+        let avatars = AVATARS.make(&png(300, 200), None, &LIMITS).unwrap();
+
+        assert_eq!(avatars.format, OutputFormat::WebP);
+        let sizes: Vec<u32> = avatars.images.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, AVATARS.widths);
+        for (size, data) in avatars.images {
+            assert_eq!(image::guess_format(&data).unwrap(), image::ImageFormat::WebP);
+            let image = image::load_from_memory(&data).unwrap();
+            assert_eq!((image.width(), image.height()), (size, size));
+        }
+    }
+
+    #[test]
+    fn test_making_banners_in_every_width() {
+        let banners = BANNERS.make(&png(800, 800), None, &LIMITS).unwrap();
+
+        let widths: Vec<u32> = banners.images.iter().map(|(width, _)| *width).collect();
+        assert_eq!(widths, BANNERS.widths);
+        for (width, data) in banners.images {
+            let image = image::load_from_memory(&data).unwrap();
+            assert_eq!((image.width(), image.height()), (width, width / 3));
+        }
+    }
+
+    #[test]
+    fn test_largest_width() {
+        assert_eq!(AVATARS.largest(), 256);
+        assert_eq!(BANNERS.largest(), 1500);
+    }
+
+    #[test]
+    fn test_making_animated_avatars() {
+        let avatars = AVATARS.make(&gif(3), None, &LIMITS).unwrap();
+
+        assert_eq!(avatars.format, OutputFormat::Gif);
+        let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(&avatars.images[0].1)).unwrap();
+        let frames = image::AnimationDecoder::into_frames(decoder).collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].buffer().dimensions(), (32, 32));
+    }
+
+    #[test]
+    fn test_too_many_frames() {
+        let limits = ImageLimits { max_frames: 2, ..LIMITS };
+        assert!(AVATARS.make(&gif(3), None, &limits).is_err());
+    }
+
+    #[test]
+    fn test_cropping_avatar() {
+        let crop = CropBox { x: 0, y: 0, width: 100, height: 100 };
+        let avatars = AVATARS.make(&png(300, 200), Some(crop), &LIMITS).unwrap();
+
+        let image = image::load_from_memory(&avatars.images[0].1).unwrap().to_rgba8();
+        assert!(image.get_pixel(31, 31)[0] > 200);
+    }
+
+    #[test]
+    fn test_cropping_banner() {
+        let crop = CropBox { x: 0, y: 0, width: 390, height: 130 };
+        let banners = BANNERS.make(&png(800, 800), Some(crop), &LIMITS).unwrap();
+
+        let image = image::load_from_memory(&banners.images[0].1).unwrap().to_rgba8();
+        assert!(image.pixels().all(|pixel| pixel[0] > 150 && pixel[2] < 50));
+    }
+
+    #[test]
+    fn test_crop_outside_of_image() {
+        let crop = CropBox { x: 250, y: 0, width: 100, height: 100 };
+        assert!(AVATARS.make(&png(300, 200), Some(crop), &LIMITS).is_err());
+        let crop = CropBox { x: 0, y: 0, width: 0, height: 100 };
+        assert!(AVATARS.make(&png(300, 200), Some(crop), &LIMITS).is_err());
+        let crop = CropBox { x: 300, y: 0, width: 600, height: 200 };
+        assert!(BANNERS.make(&png(800, 800), Some(crop), &LIMITS).is_err());
+    }
+
+    #[test]
+    fn test_too_large_image() {
+        let limits = ImageLimits { max_dimension: 250, ..LIMITS };
+        assert!(AVATARS.make(&png(300, 200), None, &limits).is_err());
+    }
+}
@@ -0,0 +1,682 @@
+use std::sync::{Mutex, MutexGuard};
+
+use axum::async_trait;
+use chrono::Utc;
+
+use crate::{error::{AppError, Conflict}, UserModel, UserDetails, ProfileModel, FriendshipModel, FriendshipDetails, FriendshipRecord, BlogPostModel, BlogPostDetails, BlogCommentModel, BlogCommentDetails, AlbumModel, AlbumDetails, PhotoModel, albums::Visibility};
+
+use super::{StorageResult, UserSearch, ProfileFields, UserRepo, ProfileRepo, FriendshipRepo, PostRepo, CommentRepo, AlbumRepo};
+
+/// Keeps everything in vectors, for testing handler logic without a database.
+/// Unique keys, the comment to post reference and deleting comments with their
+/// post, photos with their album or everything with its user behave like the
+/// constraints of the schema.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+    last_id: i32,
+    users: Vec<UserModel>,
+    /// Old names with the id of the user who had them.
+    previous_usernames: Vec<(String, i32)>,
+    profiles: Vec<ProfileModel>,
+    friendships: Vec<FriendshipModel>,
+    posts: Vec<BlogPostModel>,
+    comments: Vec<BlogCommentModel>,
+    albums: Vec<AlbumModel>,
+    photos: Vec<PhotoModel>,
+}
+
+/// Friendships are unique regardless of who sent the request.
+fn pair(user_id: i32, other_id: i32) -> (i32, i32) {
+    (user_id.min(other_id), user_id.max(other_id))
+}
+
+impl Tables {
+    fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn screen_name(&self, user_id: i32) -> String {
+        self.users.iter()
+            .find(|user| user.id == Some(user_id))
+            .map(|user| user.screen_name.clone())
+            .unwrap_or_default()
+    }
+
+    fn avatar_key(&self, user_id: i32) -> Option<String> {
+        self.users.iter()
+            .find(|user| user.id == Some(user_id))
+            .and_then(|user| user.avatar_key.clone())
+    }
+
+    fn album_ids(&self, user_id: i32) -> Vec<i32> {
+        self.albums.iter()
+            .filter(|album| album.user_id == user_id)
+            .map(|album| album.id)
+            .collect()
+    }
+
+    fn friendship_details(&self, friendship: &FriendshipModel, other_id: i32) -> FriendshipDetails {
+        FriendshipDetails {
+            id: friendship.id,
+            user_id: other_id,
+            screen_name: self.screen_name(other_id),
+            avatar_key: self.avatar_key(other_id),
+            accepted: friendship.accepted,
+            rejected: friendship.rejected,
+            cancelled: friendship.cancelled,
+            created_at: friendship.created_at,
+        }
+    }
+}
+
+impl MemoryStore {
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap()
+    }
+}
+
+fn page<T>(rows: Vec<T>, limit: i32, offset: i32) -> Vec<T> {
+    rows.into_iter()
+        .skip(offset.max(0) as usize)
+        .take(limit.max(0) as usize)
+        .collect()
+}
+
+fn matches(search: &UserSearch, username: &str) -> bool {
+    let username = username.to_lowercase();
+    match search {
+        UserSearch::Prefix(prefix) => username.starts_with(&prefix.to_lowercase()),
+        UserSearch::Contains(text) => username.contains(&text.to_lowercase()),
+    }
+}
+
+#[async_trait]
+impl UserRepo for MemoryStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<UserModel>> {
+        Ok(self.tables().users.iter().find(|user| user.id == Some(id)).cloned())
+    }
+
+    async fn find_by_name(&self, username: &str) -> StorageResult<Option<UserModel>> {
+        Ok(self.tables().users.iter().find(|user| user.screen_name == username).cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> StorageResult<Option<UserModel>> {
+        Ok(self.tables().users.iter().find(|user| user.email == email).cloned())
+    }
+
+    async fn find_renamed(&self, username: &str) -> StorageResult<Option<String>> {
+        let tables = self.tables();
+        let current = tables.previous_usernames.iter()
+            .find(|(name, _)| name == username)
+            .map(|(_, user_id)| tables.screen_name(*user_id));
+        Ok(current)
+    }
+
+    async fn create(&self, username: &str, email: &str, password: &str) -> StorageResult<i32> {
+        let mut tables = self.tables();
+        if tables.users.iter().any(|user| user.screen_name == username)
+            || tables.previous_usernames.iter().any(|(name, _)| name == username) {
+            return Err(AppError::Conflict(Conflict::UsernameTaken))
+        }
+        if tables.users.iter().any(|user| user.email == email) {
+            return Err(AppError::Conflict(Conflict::EmailTaken))
+        }
+        let id = tables.next_id();
+        let now = Some(Utc::now());
+        tables.users.push(UserModel {
+            id: Some(id),
+            screen_name: String::from(username),
+            email: String::from(email),
+            password: String::from(password),
+            avatar_key: None,
+            banner_key: None,
+            created_at: now,
+            updated_at: now,
+            email_verified_at: None,
+            totp_enabled_at: None,
+            deletion_due_at: None,
+        });
+        Ok(id)
+    }
+
+    async fn update_email(&self, user_id: i32, email: &str) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        if tables.users.iter().any(|user| user.email == email && user.id != Some(user_id)) {
+            return Err(AppError::Conflict(Conflict::EmailTaken))
+        }
+        let Some(user) = tables.users.iter_mut().find(|user| user.id == Some(user_id)) else {
+            return Err(AppError::Database(sqlx::Error::RowNotFound))
+        };
+        if user.email != email {
+            user.email = String::from(email);
+            user.email_verified_at = None;
+        }
+        Ok(user.email_verified_at.is_some())
+    }
+
+    async fn update_password(&self, user_id: i32, password: &str) -> StorageResult<()> {
+        if let Some(user) = self.tables().users.iter_mut().find(|user| user.id == Some(user_id)) {
+            user.password = String::from(password);
+        }
+        Ok(())
+    }
+
+    async fn rename(&self, user_id: i32, username: &str) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if tables.users.iter().any(|user| user.screen_name == username && user.id != Some(user_id))
+            || tables.previous_usernames.iter().any(|(name, id)| name == username && *id != user_id) {
+            return Err(AppError::Conflict(Conflict::UsernameTaken))
+        }
+        let Some(user) = tables.users.iter_mut().find(|user| user.id == Some(user_id)) else {
+            return Err(AppError::Database(sqlx::Error::RowNotFound))
+        };
+        let old = std::mem::replace(&mut user.screen_name, String::from(username));
+        tables.previous_usernames.retain(|(name, _)| name != username);
+        if old != username {
+            tables.previous_usernames.push((old, user_id));
+        }
+        Ok(())
+    }
+
+    async fn set_avatar(&self, user_id: i32, key: Option<&str>) -> StorageResult<()> {
+        if let Some(user) = self.tables().users.iter_mut().find(|user| user.id == Some(user_id)) {
+            user.avatar_key = key.map(String::from);
+        }
+        Ok(())
+    }
+
+    async fn set_banner(&self, user_id: i32, key: Option<&str>) -> StorageResult<()> {
+        if let Some(user) = self.tables().users.iter_mut().find(|user| user.id == Some(user_id)) {
+            user.banner_key = key.map(String::from);
+        }
+        Ok(())
+    }
+
+    async fn search(&self, search: &UserSearch, limit: i32, offset: i32) -> StorageResult<Vec<UserDetails>> {
+        let tables = self.tables();
+        let mut users: Vec<UserDetails> = tables.users.iter()
+            .filter(|user| matches(search, &user.screen_name))
+            .map(|user| {
+                let profile = tables.profiles.iter().find(|profile| Some(profile.user_id) == user.id);
+                UserDetails {
+                    id: user.id,
+                    screen_name: user.screen_name.clone(),
+                    avatar_key: user.avatar_key.clone(),
+                    real_name: profile.and_then(|profile| profile.real_name.clone()),
+                    gender: profile.and_then(|profile| profile.gender.clone()),
+                    city: profile.and_then(|profile| profile.city.clone()),
+                }
+            })
+            .collect();
+        users.sort_by(|a, b| a.screen_name.cmp(&b.screen_name));
+        Ok(page(users, limit, offset))
+    }
+
+    async fn count(&self, search: &UserSearch) -> StorageResult<i64> {
+        Ok(self.tables().users.iter().filter(|user| matches(search, &user.screen_name)).count() as i64)
+    }
+
+    async fn schedule_deletion(&self, user_id: i32, due_at: chrono::DateTime<chrono::Utc>) -> StorageResult<()> {
+        if let Some(user) = self.tables().users.iter_mut().find(|user| user.id == Some(user_id)) {
+            user.deletion_due_at = Some(due_at);
+        }
+        Ok(())
+    }
+
+    async fn cancel_deletion(&self, user_id: i32) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        let Some(user) = tables.users.iter_mut().find(|user| user.id == Some(user_id)) else {
+            return Ok(false)
+        };
+        Ok(user.deletion_due_at.take().is_some())
+    }
+
+    async fn due_for_deletion(&self, now: chrono::DateTime<chrono::Utc>) -> StorageResult<Vec<UserModel>> {
+        let users = self.tables().users.iter()
+            .filter(|user| user.deletion_due_at.is_some_and(|due_at| due_at <= now))
+            .cloned()
+            .collect();
+        Ok(users)
+    }
+
+    async fn delete_due(&self, user_id: i32, now: chrono::DateTime<chrono::Utc>) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        let due = tables.users.iter()
+            .any(|user| user.id == Some(user_id) && user.deletion_due_at.is_some_and(|due_at| due_at <= now));
+        if !due {
+            return Ok(false)
+        }
+        let posts: Vec<i32> = tables.posts.iter()
+            .filter(|post| post.user_id == user_id)
+            .filter_map(|post| post.id)
+            .collect();
+        tables.comments.retain(|comment| comment.user_id != user_id && !posts.contains(&comment.post_id));
+        tables.posts.retain(|post| post.user_id != user_id);
+        tables.friendships.retain(|friendship| friendship.user_id != user_id && friendship.friend_id != user_id);
+        tables.profiles.retain(|profile| profile.user_id != user_id);
+        let albums = tables.album_ids(user_id);
+        tables.photos.retain(|photo| !albums.contains(&photo.album_id));
+        tables.albums.retain(|album| album.user_id != user_id);
+        tables.previous_usernames.retain(|(_, id)| *id != user_id);
+        tables.users.retain(|user| user.id != Some(user_id));
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl ProfileRepo for MemoryStore {
+    async fn find_by_user(&self, user_id: i32) -> StorageResult<Option<ProfileModel>> {
+        Ok(self.tables().profiles.iter().find(|profile| profile.user_id == user_id).cloned())
+    }
+
+    async fn save(&self, user_id: i32, fields: &ProfileFields) -> StorageResult<()> {
+        let mut tables = self.tables();
+        let now = Some(Utc::now());
+        if let Some(profile) = tables.profiles.iter_mut().find(|profile| profile.user_id == user_id) {
+            profile.gender = fields.gender.clone();
+            profile.city = fields.city.clone();
+            profile.description = fields.description.clone();
+            profile.real_name = fields.real_name.clone();
+            profile.updated_at = now;
+            return Ok(())
+        }
+        let id = tables.next_id();
+        tables.profiles.push(ProfileModel {
+            id: Some(id),
+            user_id,
+            gender: fields.gender.clone(),
+            city: fields.city.clone(),
+            description: fields.description.clone(),
+            real_name: fields.real_name.clone(),
+            created_at: now,
+            updated_at: now,
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FriendshipRepo for MemoryStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<FriendshipModel>> {
+        Ok(self.tables().friendships.iter().find(|friendship| friendship.id == Some(id)).cloned())
+    }
+
+    async fn find_between(&self, user_id: i32, other_id: i32) -> StorageResult<Option<FriendshipModel>> {
+        Ok(self.tables().friendships.iter()
+            .find(|friendship| (friendship.user_id == user_id && friendship.friend_id == other_id)
+                || (friendship.user_id == other_id && friendship.friend_id == user_id))
+            .cloned())
+    }
+
+    async fn create(&self, user_id: i32, friend_id: i32) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if tables.friendships.iter().any(|friendship| pair(friendship.user_id, friendship.friend_id) == pair(user_id, friend_id)) {
+            return Err(AppError::Conflict(Conflict::FriendRequestPending))
+        }
+        let id = tables.next_id();
+        tables.friendships.push(FriendshipModel {
+            id: Some(id),
+            user_id,
+            friend_id,
+            accepted: false,
+            rejected: false,
+            cancelled: false,
+            created_at: Some(Utc::now()),
+            accepted_at: None,
+        });
+        Ok(())
+    }
+
+    async fn set_state(&self, id: i32, accepted: bool, rejected: bool, accepted_at: Option<chrono::DateTime<chrono::Utc>>) -> StorageResult<()> {
+        if let Some(friendship) = self.tables().friendships.iter_mut().find(|friendship| friendship.id == Some(id)) {
+            friendship.accepted = accepted;
+            friendship.rejected = rejected;
+            friendship.accepted_at = accepted_at;
+        }
+        Ok(())
+    }
+
+    async fn set_cancelled(&self, id: i32, cancelled: bool, accepted_at: Option<chrono::DateTime<chrono::Utc>>) -> StorageResult<()> {
+        if let Some(friendship) = self.tables().friendships.iter_mut().find(|friendship| friendship.id == Some(id)) {
+            friendship.cancelled = cancelled;
+            friendship.accepted_at = accepted_at;
+        }
+        Ok(())
+    }
+
+    async fn pending(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>> {
+        let tables = self.tables();
+        let requests = tables.friendships.iter()
+            .filter(|f| f.friend_id == user_id && !f.accepted && !f.rejected && !f.cancelled)
+            .map(|f| tables.friendship_details(f, f.user_id))
+            .collect();
+        Ok(page(requests, limit, offset))
+    }
+
+    async fn count_pending(&self, user_id: i32) -> StorageResult<i64> {
+        Ok(self.tables().friendships.iter()
+            .filter(|f| f.friend_id == user_id && !f.accepted && !f.rejected && !f.cancelled)
+            .count() as i64)
+    }
+
+    async fn friends(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>> {
+        let tables = self.tables();
+        let friends = tables.friendships.iter()
+            .filter(|f| (f.user_id == user_id || f.friend_id == user_id) && f.accepted && !f.cancelled)
+            .map(|f| {
+                let other_id = if f.user_id == user_id { f.friend_id } else { f.user_id };
+                tables.friendship_details(f, other_id)
+            })
+            .collect();
+        Ok(page(friends, limit, offset))
+    }
+
+    async fn count_friends(&self, user_id: i32) -> StorageResult<i64> {
+        Ok(self.tables().friendships.iter()
+            .filter(|f| (f.user_id == user_id || f.friend_id == user_id) && f.accepted && !f.cancelled)
+            .count() as i64)
+    }
+
+    async fn rejected(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>> {
+        let tables = self.tables();
+        let requests = tables.friendships.iter()
+            .filter(|f| f.friend_id == user_id && (f.rejected || f.cancelled))
+            .map(|f| tables.friendship_details(f, f.user_id))
+            .collect();
+        Ok(page(requests, limit, offset))
+    }
+
+    async fn count_rejected(&self, user_id: i32) -> StorageResult<i64> {
+        Ok(self.tables().friendships.iter()
+            .filter(|f| f.friend_id == user_id && (f.rejected || f.cancelled))
+            .count() as i64)
+    }
+
+    async fn all_for_user(&self, user_id: i32) -> StorageResult<Vec<FriendshipRecord>> {
+        let tables = self.tables();
+        let friendships = tables.friendships.iter()
+            .filter(|f| f.user_id == user_id || f.friend_id == user_id)
+            .map(|f| FriendshipRecord {
+                id: f.id,
+                user: tables.screen_name(f.user_id),
+                friend: tables.screen_name(f.friend_id),
+                accepted: f.accepted,
+                rejected: f.rejected,
+                cancelled: f.cancelled,
+                created_at: f.created_at,
+                accepted_at: f.accepted_at,
+            })
+            .collect();
+        Ok(friendships)
+    }
+}
+
+#[async_trait]
+impl PostRepo for MemoryStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<BlogPostModel>> {
+        Ok(self.tables().posts.iter().find(|post| post.id == Some(id)).cloned())
+    }
+
+    async fn find_details(&self, id: i32) -> StorageResult<Option<BlogPostDetails>> {
+        let tables = self.tables();
+        Ok(tables.posts.iter()
+            .find(|post| post.id == Some(id))
+            .map(|post| BlogPostDetails {
+                id: post.id,
+                user_id: post.user_id,
+                screen_name: tables.screen_name(post.user_id),
+                title: post.title.clone(),
+                content: post.content.clone(),
+                created_at: post.created_at,
+                updated_at: post.updated_at,
+            }))
+    }
+
+    async fn create(&self, user_id: i32, title: &str, content: &str) -> StorageResult<i32> {
+        let mut tables = self.tables();
+        let id = tables.next_id();
+        let now = Some(Utc::now());
+        tables.posts.push(BlogPostModel {
+            id: Some(id),
+            user_id,
+            title: String::from(title),
+            content: String::from(content),
+            created_at: now,
+            updated_at: now,
+        });
+        Ok(id)
+    }
+
+    async fn update(&self, id: i32, title: &str, content: &str) -> StorageResult<()> {
+        if let Some(post) = self.tables().posts.iter_mut().find(|post| post.id == Some(id)) {
+            post.title = String::from(title);
+            post.content = String::from(content);
+            post.updated_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: i32) -> StorageResult<()> {
+        let mut tables = self.tables();
+        tables.comments.retain(|comment| comment.post_id != id);
+        tables.posts.retain(|post| post.id != Some(id));
+        Ok(())
+    }
+
+    async fn list_by_user(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<BlogPostModel>> {
+        let posts = self.tables().posts.iter()
+            .filter(|post| post.user_id == user_id)
+            .cloned()
+            .collect();
+        Ok(page(posts, limit, offset))
+    }
+
+    async fn count_by_user(&self, user_id: i32) -> StorageResult<i64> {
+        Ok(self.tables().posts.iter().filter(|post| post.user_id == user_id).count() as i64)
+    }
+}
+
+#[async_trait]
+impl CommentRepo for MemoryStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<BlogCommentModel>> {
+        Ok(self.tables().comments.iter().find(|comment| comment.id == Some(id)).cloned())
+    }
+
+    async fn create(&self, user_id: i32, post_id: i32, content: &str) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if !tables.posts.iter().any(|post| post.id == Some(post_id)) {
+            return Err(AppError::NotFound("No such post!"))
+        }
+        let id = tables.next_id();
+        let now = Some(Utc::now());
+        tables.comments.push(BlogCommentModel {
+            id: Some(id),
+            user_id,
+            post_id,
+            content: Some(String::from(content)),
+            created_at: now,
+            updated_at: now,
+        });
+        Ok(())
+    }
+
+    async fn update(&self, id: i32, content: &str) -> StorageResult<()> {
+        if let Some(comment) = self.tables().comments.iter_mut().find(|comment| comment.id == Some(id)) {
+            comment.content = Some(String::from(content));
+            comment.updated_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: i32) -> StorageResult<()> {
+        self.tables().comments.retain(|comment| comment.id != Some(id));
+        Ok(())
+    }
+
+    async fn list_for_post(&self, post_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<BlogCommentDetails>> {
+        let tables = self.tables();
+        let comments = tables.comments.iter()
+            .filter(|comment| comment.post_id == post_id)
+            .map(|comment| BlogCommentDetails {
+                id: comment.id,
+                user_id: comment.user_id,
+                post_id: comment.post_id,
+                screen_name: tables.screen_name(comment.user_id),
+                avatar_key: tables.avatar_key(comment.user_id),
+                content: comment.content.clone(),
+                created_at: comment.created_at,
+                updated_at: comment.updated_at,
+            })
+            .collect();
+        Ok(page(comments, limit, offset))
+    }
+
+    async fn count_for_post(&self, post_id: i32) -> StorageResult<i64> {
+        Ok(self.tables().comments.iter().filter(|comment| comment.post_id == post_id).count() as i64)
+    }
+
+    async fn all_by_user(&self, user_id: i32) -> StorageResult<Vec<BlogCommentModel>> {
+        Ok(self.tables().comments.iter().filter(|comment| comment.user_id == user_id).cloned().collect())
+    }
+}
+
+#[async_trait]
+impl AlbumRepo for MemoryStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<AlbumModel>> {
+        Ok(self.tables().albums.iter().find(|album| album.id == id).cloned())
+    }
+
+    async fn create(&self, user_id: i32, name: &str, visibility: Visibility) -> StorageResult<i32> {
+        let mut tables = self.tables();
+        let id = tables.next_id();
+        let now = Utc::now();
+        tables.albums.push(AlbumModel {
+            id,
+            user_id,
+            name: String::from(name),
+            visibility,
+            cover_id: None,
+            created_at: now,
+            updated_at: now,
+        });
+        Ok(id)
+    }
+
+    async fn update(&self, id: i32, name: &str, visibility: Visibility) -> StorageResult<()> {
+        if let Some(album) = self.tables().albums.iter_mut().find(|album| album.id == id) {
+            album.name = String::from(name);
+            album.visibility = visibility;
+            album.updated_at = Utc::now();
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: i32) -> StorageResult<()> {
+        let mut tables = self.tables();
+        tables.photos.retain(|photo| photo.album_id != id);
+        tables.albums.retain(|album| album.id != id);
+        Ok(())
+    }
+
+    async fn set_cover(&self, id: i32, photo_id: Option<i32>) -> StorageResult<()> {
+        if let Some(album) = self.tables().albums.iter_mut().find(|album| album.id == id) {
+            album.cover_id = photo_id;
+        }
+        Ok(())
+    }
+
+    async fn list_by_user(&self, user_id: i32, visibilities: &[Visibility], limit: i32, offset: i32) -> StorageResult<Vec<AlbumDetails>> {
+        let tables = self.tables();
+        let albums = tables.albums.iter()
+            .rev()
+            .filter(|album| album.user_id == user_id && visibilities.contains(&album.visibility))
+            .map(|album| {
+                let photos: Vec<&PhotoModel> = tables.photos.iter().filter(|photo| photo.album_id == album.id).collect();
+                let cover = photos.iter()
+                    .find(|photo| Some(photo.id) == album.cover_id)
+                    .or(photos.first());
+                AlbumDetails {
+                    id: album.id,
+                    user_id: album.user_id,
+                    name: album.name.clone(),
+                    visibility: album.visibility,
+                    cover_key: cover.map(|photo| photo.key.clone()),
+                    photos: photos.len() as i64,
+                    created_at: album.created_at,
+                }
+            })
+            .collect();
+        Ok(page(albums, limit, offset))
+    }
+
+    async fn count_by_user(&self, user_id: i32, visibilities: &[Visibility]) -> StorageResult<i64> {
+        Ok(self.tables().albums.iter()
+            .filter(|album| album.user_id == user_id && visibilities.contains(&album.visibility))
+            .count() as i64)
+    }
+
+    async fn all_by_user(&self, user_id: i32) -> StorageResult<Vec<AlbumModel>> {
+        Ok(self.tables().albums.iter().filter(|album| album.user_id == user_id).cloned().collect())
+    }
+
+    async fn add_photos(&self, album_id: i32, photos: &[(String, Option<String>)]) -> StorageResult<Vec<i32>> {
+        let mut tables = self.tables();
+        if !tables.albums.iter().any(|album| album.id == album_id) {
+            return Err(AppError::Database(sqlx::Error::RowNotFound))
+        }
+        let mut ids = Vec::with_capacity(photos.len());
+        for (key, caption) in photos {
+            let id = tables.next_id();
+            tables.photos.push(PhotoModel {
+                id,
+                album_id,
+                key: key.clone(),
+                caption: caption.clone(),
+                created_at: Utc::now(),
+            });
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    async fn find_photo(&self, id: i32) -> StorageResult<Option<PhotoModel>> {
+        Ok(self.tables().photos.iter().find(|photo| photo.id == id).cloned())
+    }
+
+    async fn delete_photo(&self, id: i32) -> StorageResult<()> {
+        let mut tables = self.tables();
+        tables.photos.retain(|photo| photo.id != id);
+        for album in tables.albums.iter_mut().filter(|album| album.cover_id == Some(id)) {
+            album.cover_id = None;
+        }
+        Ok(())
+    }
+
+    async fn photos(&self, album_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<PhotoModel>> {
+        let photos = self.tables().photos.iter()
+            .filter(|photo| photo.album_id == album_id)
+            .cloned()
+            .collect();
+        Ok(page(photos, limit, offset))
+    }
+
+    async fn count_photos(&self, album_id: i32) -> StorageResult<i64> {
+        Ok(self.tables().photos.iter().filter(|photo| photo.album_id == album_id).count() as i64)
+    }
+
+    async fn photos_by_user(&self, user_id: i32) -> StorageResult<Vec<PhotoModel>> {
+        let tables = self.tables();
+        let albums = tables.album_ids(user_id);
+        Ok(tables.photos.iter().filter(|photo| albums.contains(&photo.album_id)).cloned().collect())
+    }
+
+    async fn photo_key_in_use(&self, key: &str) -> StorageResult<bool> {
+        Ok(self.tables().photos.iter().any(|photo| photo.key == key))
+    }
+}
@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use sqlx::PgPool;
+
+use crate::{error::AppError, UserModel, UserDetails, ProfileModel, FriendshipModel, FriendshipDetails, FriendshipRecord, BlogPostModel, BlogPostDetails, BlogCommentModel, BlogCommentDetails, AlbumModel, AlbumDetails, PhotoModel, albums::Visibility};
+
+mod postgres;
+#[cfg(test)]
+mod memory;
+
+pub use postgres::PgStore;
+#[cfg(test)]
+pub use memory::MemoryStore;
+
+/// Errors are already converted to `AppError`, so that every implementation reports
+/// duplicates and missing rows the same way the database constraints do.
+pub type StorageResult<T> = Result<T, AppError>;
+
+/// Which users to list on the community pages.
+pub enum UserSearch {
+    /// Names starting with the letter.
+    Prefix(String),
+    /// Names containing the text anywhere.
+    Contains(String),
+}
+
+/// Editable profile fields, empty ones are `None`.
+pub struct ProfileFields {
+    pub gender: Option<String>,
+    pub city: Option<String>,
+    pub description: Option<String>,
+    pub real_name: Option<String>,
+}
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    async fn find(&self, id: i32) -> StorageResult<Option<UserModel>>;
+    async fn find_by_name(&self, username: &str) -> StorageResult<Option<UserModel>>;
+    async fn find_by_email(&self, email: &str) -> StorageResult<Option<UserModel>>;
+    /// Current name of the user who used to be called `username`.
+    async fn find_renamed(&self, username: &str) -> StorageResult<Option<String>>;
+    /// Returns the id of the new user. Names other users had before are taken.
+    async fn create(&self, username: &str, email: &str, password: &str) -> StorageResult<i32>;
+    /// Keeps the old name, so that links to it still lead to the user and nobody else can take it.
+    async fn rename(&self, user_id: i32, username: &str) -> StorageResult<()>;
+    /// Clears the verification if the address changes. Returns whether the new address is verified.
+    async fn update_email(&self, user_id: i32, email: &str) -> StorageResult<bool>;
+    async fn update_password(&self, user_id: i32, password: &str) -> StorageResult<()>;
+    /// Key of the user's avatar in the media store, `None` when they have none.
+    async fn set_avatar(&self, user_id: i32, key: Option<&str>) -> StorageResult<()>;
+    /// Key of the banner shown on the user's profile, `None` when they have none.
+    async fn set_banner(&self, user_id: i32, key: Option<&str>) -> StorageResult<()>;
+    async fn search(&self, search: &UserSearch, limit: i32, offset: i32) -> StorageResult<Vec<UserDetails>>;
+    async fn count(&self, search: &UserSearch) -> StorageResult<i64>;
+    /// Marks the account for removal once `due_at` has passed.
+    async fn schedule_deletion(&self, user_id: i32, due_at: chrono::DateTime<chrono::Utc>) -> StorageResult<()>;
+    /// Returns whether a deletion was pending.
+    async fn cancel_deletion(&self, user_id: i32) -> StorageResult<bool>;
+    /// Accounts whose grace period ended at `now`.
+    async fn due_for_deletion(&self, now: chrono::DateTime<chrono::Utc>) -> StorageResult<Vec<UserModel>>;
+    /// Deletes the user with their profile, friendships, posts and comments, unless
+    /// the deletion was cancelled in the meantime. Returns whether the user is gone.
+    async fn delete_due(&self, user_id: i32, now: chrono::DateTime<chrono::Utc>) -> StorageResult<bool>;
+}
+
+#[async_trait]
+pub trait ProfileRepo: Send + Sync {
+    async fn find_by_user(&self, user_id: i32) -> StorageResult<Option<ProfileModel>>;
+    /// Creates the profile or overwrites all of its fields.
+    async fn save(&self, user_id: i32, fields: &ProfileFields) -> StorageResult<()>;
+}
+
+#[async_trait]
+pub trait FriendshipRepo: Send + Sync {
+    async fn find(&self, id: i32) -> StorageResult<Option<FriendshipModel>>;
+    /// Friendship in either direction.
+    async fn find_between(&self, user_id: i32, other_id: i32) -> StorageResult<Option<FriendshipModel>>;
+    async fn create(&self, user_id: i32, friend_id: i32) -> StorageResult<()>;
+    /// Answer of the invited user.
+    async fn set_state(&self, id: i32, accepted: bool, rejected: bool, accepted_at: Option<chrono::DateTime<chrono::Utc>>) -> StorageResult<()>;
+    /// Answer of the inviting user.
+    async fn set_cancelled(&self, id: i32, cancelled: bool, accepted_at: Option<chrono::DateTime<chrono::Utc>>) -> StorageResult<()>;
+    /// Requests the user hasn't answered yet, with the names of the senders.
+    async fn pending(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>>;
+    async fn count_pending(&self, user_id: i32) -> StorageResult<i64>;
+    async fn friends(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>>;
+    async fn count_friends(&self, user_id: i32) -> StorageResult<i64>;
+    async fn rejected(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>>;
+    async fn count_rejected(&self, user_id: i32) -> StorageResult<i64>;
+    /// Every friendship and request the user sent or received.
+    async fn all_for_user(&self, user_id: i32) -> StorageResult<Vec<FriendshipRecord>>;
+}
+
+#[async_trait]
+pub trait PostRepo: Send + Sync {
+    async fn find(&self, id: i32) -> StorageResult<Option<BlogPostModel>>;
+    /// Post with the name of its author.
+    async fn find_details(&self, id: i32) -> StorageResult<Option<BlogPostDetails>>;
+    /// Returns the id of the new post.
+    async fn create(&self, user_id: i32, title: &str, content: &str) -> StorageResult<i32>;
+    async fn update(&self, id: i32, title: &str, content: &str) -> StorageResult<()>;
+    /// Deletes the post together with its comments.
+    async fn delete(&self, id: i32) -> StorageResult<()>;
+    async fn list_by_user(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<BlogPostModel>>;
+    async fn count_by_user(&self, user_id: i32) -> StorageResult<i64>;
+}
+
+#[async_trait]
+pub trait CommentRepo: Send + Sync {
+    async fn find(&self, id: i32) -> StorageResult<Option<BlogCommentModel>>;
+    async fn create(&self, user_id: i32, post_id: i32, content: &str) -> StorageResult<()>;
+    async fn update(&self, id: i32, content: &str) -> StorageResult<()>;
+    async fn delete(&self, id: i32) -> StorageResult<()>;
+    /// Comments with the names of their authors.
+    async fn list_for_post(&self, post_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<BlogCommentDetails>>;
+    async fn count_for_post(&self, post_id: i32) -> StorageResult<i64>;
+    async fn all_by_user(&self, user_id: i32) -> StorageResult<Vec<BlogCommentModel>>;
+}
+
+#[async_trait]
+pub trait AlbumRepo: Send + Sync {
+    async fn find(&self, id: i32) -> StorageResult<Option<AlbumModel>>;
+    /// Returns the id of the new album.
+    async fn create(&self, user_id: i32, name: &str, visibility: Visibility) -> StorageResult<i32>;
+    async fn update(&self, id: i32, name: &str, visibility: Visibility) -> StorageResult<()>;
+    /// Deletes the album together with its photos.
+    async fn delete(&self, id: i32) -> StorageResult<()>;
+    /// `None` goes back to showing the first photo.
+    async fn set_cover(&self, id: i32, photo_id: Option<i32>) -> StorageResult<()>;
+    /// Albums of the user with one of the visibilities, newest first.
+    async fn list_by_user(&self, user_id: i32, visibilities: &[Visibility], limit: i32, offset: i32) -> StorageResult<Vec<AlbumDetails>>;
+    async fn count_by_user(&self, user_id: i32, visibilities: &[Visibility]) -> StorageResult<i64>;
+    async fn all_by_user(&self, user_id: i32) -> StorageResult<Vec<AlbumModel>>;
+    /// Adds all photos or none of them. Returns their ids in the same order.
+    async fn add_photos(&self, album_id: i32, photos: &[(String, Option<String>)]) -> StorageResult<Vec<i32>>;
+    async fn find_photo(&self, id: i32) -> StorageResult<Option<PhotoModel>>;
+    /// An album showing the photo as its cover goes back to its first photo.
+    async fn delete_photo(&self, id: i32) -> StorageResult<()>;
+    /// Photos in the order they were added.
+    async fn photos(&self, album_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<PhotoModel>>;
+    async fn count_photos(&self, album_id: i32) -> StorageResult<i64>;
+    /// Photos of all albums of the user.
+    async fn photos_by_user(&self, user_id: i32) -> StorageResult<Vec<PhotoModel>>;
+    /// Whether a photo is stored under the key.
+    async fn photo_key_in_use(&self, key: &str) -> StorageResult<bool>;
+}
+
+/// Repositories the handlers read and write through. All of them share one store,
+/// so the in-memory one sees the same users as the posts refer to.
+#[derive(Clone)]
+pub struct Storage {
+    pub users: Arc<dyn UserRepo>,
+    pub profiles: Arc<dyn ProfileRepo>,
+    pub friendships: Arc<dyn FriendshipRepo>,
+    pub posts: Arc<dyn PostRepo>,
+    pub comments: Arc<dyn CommentRepo>,
+    pub albums: Arc<dyn AlbumRepo>,
+}
+
+impl Storage {
+    pub fn postgres(db: PgPool) -> Storage {
+        Storage::from_store(Arc::new(PgStore::new(db)))
+    }
+
+    #[cfg(test)]
+    pub fn memory() -> Storage {
+        Storage::from_store(Arc::new(MemoryStore::default()))
+    }
+
+    fn from_store<S>(store: Arc<S>) -> Storage
+    where S: UserRepo + ProfileRepo + FriendshipRepo + PostRepo + CommentRepo + AlbumRepo + 'static {
+        Storage {
+            users: store.clone(),
+            profiles: store.clone(),
+            friendships: store.clone(),
+            posts: store.clone(),
+            comments: store.clone(),
+            albums: store,
+        }
+    }
+}
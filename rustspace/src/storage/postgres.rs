@@ -0,0 +1,631 @@
+use axum::async_trait;
+use sqlx::PgPool;
+
+use crate::{error::{AppError, Conflict}, UserModel, UserDetails, ProfileModel, FriendshipModel, FriendshipDetails, FriendshipRecord, BlogPostModel, BlogPostDetails, BlogCommentModel, BlogCommentDetails, AlbumModel, AlbumDetails, PhotoModel, albums::Visibility};
+
+use super::{StorageResult, UserSearch, ProfileFields, UserRepo, ProfileRepo, FriendshipRepo, PostRepo, CommentRepo, AlbumRepo};
+
+pub struct PgStore {
+    db: PgPool,
+}
+
+impl PgStore {
+    pub fn new(db: PgPool) -> PgStore {
+        PgStore { db }
+    }
+}
+
+fn visibility_names(visibilities: &[Visibility]) -> Vec<String> {
+    visibilities.iter().map(|visibility| String::from(visibility.as_str())).collect()
+}
+
+fn search_pattern(search: &UserSearch) -> String {
+    match search {
+        UserSearch::Prefix(prefix) => format!("{}%", prefix),
+        UserSearch::Contains(text) => format!("%{}%", text),
+    }
+}
+
+#[async_trait]
+impl UserRepo for PgStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<UserModel>> {
+        let user = sqlx::query_as!(UserModel,
+            r#"SELECT id AS "id?", screen_name, email, password, avatar_key, banner_key, created_at AS "created_at?", updated_at AS "updated_at?", email_verified_at, totp_enabled_at, deletion_due_at
+            FROM users WHERE id = $1"#,
+            id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(user)
+    }
+
+    async fn find_by_name(&self, username: &str) -> StorageResult<Option<UserModel>> {
+        let user = sqlx::query_as!(UserModel,
+            r#"SELECT id AS "id?", screen_name, email, password, avatar_key, banner_key, created_at AS "created_at?", updated_at AS "updated_at?", email_verified_at, totp_enabled_at, deletion_due_at
+            FROM users WHERE screen_name = $1"#,
+            username)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> StorageResult<Option<UserModel>> {
+        let user = sqlx::query_as!(UserModel,
+            r#"SELECT id AS "id?", screen_name, email, password, avatar_key, banner_key, created_at AS "created_at?", updated_at AS "updated_at?", email_verified_at, totp_enabled_at, deletion_due_at
+            FROM users WHERE email = $1"#,
+            email)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(user)
+    }
+
+    async fn find_renamed(&self, username: &str) -> StorageResult<Option<String>> {
+        let current = sqlx::query_scalar!(
+            "SELECT u.screen_name FROM previous_usernames p
+            JOIN users u ON u.id = p.user_id
+            WHERE p.screen_name = $1",
+            username)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(current)
+    }
+
+    async fn create(&self, username: &str, email: &str, password: &str) -> StorageResult<i32> {
+        let user_id = sqlx::query_scalar!(
+            "INSERT INTO users (screen_name, email, password)
+            SELECT $1, $2, $3 WHERE NOT EXISTS (SELECT 1 FROM previous_usernames WHERE screen_name = $1)
+            RETURNING id",
+            username, email, password)
+            .fetch_optional(&self.db)
+            .await?;
+        user_id.ok_or(AppError::Conflict(Conflict::UsernameTaken))
+    }
+
+    async fn rename(&self, user_id: i32, username: &str) -> StorageResult<()> {
+        let mut tx = self.db.begin().await?;
+        let taken = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM previous_usernames WHERE screen_name = $1 AND user_id <> $2) AS "taken!""#,
+            username, user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if taken {
+            return Err(AppError::Conflict(Conflict::UsernameTaken))
+        }
+        // users may go back to a name they had before
+        sqlx::query!("DELETE FROM previous_usernames WHERE screen_name = $1 AND user_id = $2", username, user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("INSERT INTO previous_usernames (screen_name, user_id) SELECT screen_name, id FROM users WHERE id = $1 AND screen_name <> $2",
+            user_id, username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("UPDATE users SET screen_name = $1 WHERE id = $2", username, user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_email(&self, user_id: i32, email: &str) -> StorageResult<bool> {
+        let verified = sqlx::query_scalar!(
+            r#"UPDATE users SET email = $1,
+                email_verified_at = CASE WHEN email = $1 THEN email_verified_at ELSE NULL END
+            WHERE id = $2
+            RETURNING email_verified_at IS NOT NULL AS "verified!""#,
+            email, user_id)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(verified)
+    }
+
+    async fn update_password(&self, user_id: i32, password: &str) -> StorageResult<()> {
+        sqlx::query!("UPDATE users SET password = $1 WHERE id = $2", password, user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_avatar(&self, user_id: i32, key: Option<&str>) -> StorageResult<()> {
+        sqlx::query!("UPDATE users SET avatar_key = $1, avatar = $1::text IS NOT NULL WHERE id = $2", key, user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_banner(&self, user_id: i32, key: Option<&str>) -> StorageResult<()> {
+        sqlx::query!("UPDATE users SET banner_key = $1 WHERE id = $2", key, user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn search(&self, search: &UserSearch, limit: i32, offset: i32) -> StorageResult<Vec<UserDetails>> {
+        let users = sqlx::query_as!(UserDetails,
+            r#"SELECT u.id AS "id?", u.screen_name, u.avatar_key, p.real_name AS "real_name?", p.gender AS "gender?", p.city AS "city?"
+            FROM users u
+            LEFT JOIN profiles p ON u.id = p.user_id
+            WHERE u.screen_name ILIKE $3
+            ORDER BY screen_name
+            LIMIT $1 OFFSET $2"#,
+            limit as i64, offset as i64, search_pattern(search))
+            .fetch_all(&self.db)
+            .await?;
+        Ok(users)
+    }
+
+    async fn count(&self, search: &UserSearch) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM users u WHERE u.screen_name ILIKE $1"#,
+            search_pattern(search))
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+
+    async fn schedule_deletion(&self, user_id: i32, due_at: chrono::DateTime<chrono::Utc>) -> StorageResult<()> {
+        sqlx::query!("UPDATE users SET deletion_due_at = $1 WHERE id = $2", due_at, user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn cancel_deletion(&self, user_id: i32) -> StorageResult<bool> {
+        let result = sqlx::query!("UPDATE users SET deletion_due_at = NULL WHERE id = $1 AND deletion_due_at IS NOT NULL", user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn due_for_deletion(&self, now: chrono::DateTime<chrono::Utc>) -> StorageResult<Vec<UserModel>> {
+        let users = sqlx::query_as!(UserModel,
+            r#"SELECT id AS "id?", screen_name, email, password, avatar_key, banner_key, created_at AS "created_at?", updated_at AS "updated_at?", email_verified_at, totp_enabled_at, deletion_due_at
+            FROM users WHERE deletion_due_at <= $1"#,
+            now)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(users)
+    }
+
+    async fn delete_due(&self, user_id: i32, now: chrono::DateTime<chrono::Utc>) -> StorageResult<bool> {
+        // everything else refers to the user with a cascading foreign key
+        let result = sqlx::query!("DELETE FROM users WHERE id = $1 AND deletion_due_at <= $2", user_id, now)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl ProfileRepo for PgStore {
+    async fn find_by_user(&self, user_id: i32) -> StorageResult<Option<ProfileModel>> {
+        let profile = sqlx::query_as!(ProfileModel,
+            r#"SELECT id AS "id?", user_id, gender, city, description, real_name, created_at AS "created_at?", updated_at AS "updated_at?"
+            FROM profiles WHERE user_id = $1"#,
+            user_id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(profile)
+    }
+
+    async fn save(&self, user_id: i32, fields: &ProfileFields) -> StorageResult<()> {
+        sqlx::query!("INSERT INTO profiles (gender, city, description, real_name, user_id) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id) DO UPDATE
+            SET gender = excluded.gender, city = excluded.city, description = excluded.description, real_name = excluded.real_name",
+            fields.gender, fields.city, fields.description, fields.real_name, user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FriendshipRepo for PgStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<FriendshipModel>> {
+        let friendship = sqlx::query_as!(FriendshipModel,
+            r#"SELECT id AS "id?", user_id, friend_id, accepted, rejected, cancelled AS "cancelled!", created_at AS "created_at?", accepted_at
+            FROM friendships WHERE id = $1"#,
+            id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(friendship)
+    }
+
+    async fn find_between(&self, user_id: i32, other_id: i32) -> StorageResult<Option<FriendshipModel>> {
+        let friendship = sqlx::query_as!(FriendshipModel,
+            r#"SELECT id AS "id?", user_id, friend_id, accepted, rejected, cancelled AS "cancelled!", created_at AS "created_at?", accepted_at
+            FROM friendships WHERE (user_id = $1 AND friend_id = $2) OR (user_id = $2 AND friend_id = $1)"#,
+            user_id, other_id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(friendship)
+    }
+
+    async fn create(&self, user_id: i32, friend_id: i32) -> StorageResult<()> {
+        sqlx::query!("INSERT INTO friendships (user_id, friend_id) VALUES ($1, $2)", user_id, friend_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_state(&self, id: i32, accepted: bool, rejected: bool, accepted_at: Option<chrono::DateTime<chrono::Utc>>) -> StorageResult<()> {
+        sqlx::query!("UPDATE friendships SET accepted = $1, rejected = $2, accepted_at = $3 WHERE id = $4",
+            accepted, rejected, accepted_at, id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_cancelled(&self, id: i32, cancelled: bool, accepted_at: Option<chrono::DateTime<chrono::Utc>>) -> StorageResult<()> {
+        sqlx::query!("UPDATE friendships SET cancelled = $1, accepted_at = $2 WHERE id = $3",
+            cancelled, accepted_at, id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn pending(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>> {
+        let requests = sqlx::query_as!(FriendshipDetails,
+            r#"SELECT f.id AS "id?", u.id AS "user_id!", u.screen_name AS "screen_name!", u.avatar_key, f.accepted AS "accepted!", f.rejected AS "rejected!", f.cancelled AS "cancelled!", f.created_at AS "created_at?"
+            FROM users u
+            LEFT JOIN friendships f ON u.id = f.user_id
+            WHERE f.friend_id = $3 AND f.accepted = false AND f.rejected = false AND f.cancelled = false
+            ORDER BY f.created_at
+            LIMIT $1 OFFSET $2"#,
+            limit as i64, offset as i64, user_id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(requests)
+    }
+
+    async fn count_pending(&self, user_id: i32) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM friendships f
+            WHERE f.friend_id = $1 AND f.accepted = false AND f.rejected = false AND f.cancelled = false"#,
+            user_id)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+
+    async fn friends(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>> {
+        let friends = sqlx::query_as!(FriendshipDetails,
+            r#"SELECT f.id AS "id?", u.id AS "user_id!", u.screen_name AS "screen_name!", u.avatar_key, f.accepted AS "accepted!", f.rejected AS "rejected!", f.cancelled AS "cancelled!", f.created_at AS "created_at?"
+            FROM users u
+            LEFT JOIN friendships f ON u.id = f.friend_id
+            WHERE f.user_id = $3 AND f.accepted = true AND f.cancelled = false
+            UNION
+            SELECT fr.id, us.id, us.screen_name, us.avatar_key, fr.accepted, fr.rejected, fr.cancelled, fr.created_at
+            FROM users us
+            LEFT JOIN friendships fr ON us.id = fr.user_id
+            WHERE fr.friend_id = $3 AND fr.accepted = true AND fr.cancelled = false
+            ORDER BY 8
+            LIMIT $1 OFFSET $2"#,
+            limit as i64, offset as i64, user_id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(friends)
+    }
+
+    async fn count_friends(&self, user_id: i32) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM friendships f
+            WHERE (f.user_id = $1 OR f.friend_id = $1) AND f.accepted = true AND f.cancelled = false"#,
+            user_id)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+
+    async fn rejected(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<FriendshipDetails>> {
+        let requests = sqlx::query_as!(FriendshipDetails,
+            r#"SELECT f.id AS "id?", u.id AS "user_id!", u.screen_name AS "screen_name!", u.avatar_key, f.accepted AS "accepted!", f.rejected AS "rejected!", f.cancelled AS "cancelled!", f.created_at AS "created_at?"
+            FROM users u
+            LEFT JOIN friendships f ON u.id = f.user_id
+            WHERE f.friend_id = $3 AND (f.rejected = true OR f.cancelled = true)
+            ORDER BY f.created_at
+            LIMIT $1 OFFSET $2"#,
+            limit as i64, offset as i64, user_id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(requests)
+    }
+
+    async fn count_rejected(&self, user_id: i32) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM friendships f
+            WHERE f.friend_id = $1 AND (f.rejected = true OR f.cancelled = true)"#,
+            user_id)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+
+    async fn all_for_user(&self, user_id: i32) -> StorageResult<Vec<FriendshipRecord>> {
+        let friendships = sqlx::query_as!(FriendshipRecord,
+            r#"SELECT f.id AS "id?", u.screen_name AS user, fr.screen_name AS friend, f.accepted, f.rejected, f.cancelled AS "cancelled!", f.created_at AS "created_at?", f.accepted_at
+            FROM friendships f
+            JOIN users u ON u.id = f.user_id
+            JOIN users fr ON fr.id = f.friend_id
+            WHERE f.user_id = $1 OR f.friend_id = $1
+            ORDER BY f.created_at"#,
+            user_id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(friendships)
+    }
+}
+
+#[async_trait]
+impl PostRepo for PgStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<BlogPostModel>> {
+        let post = sqlx::query_as!(BlogPostModel,
+            r#"SELECT id AS "id?", user_id, title, content, created_at AS "created_at?", updated_at AS "updated_at?" FROM posts WHERE id = $1"#,
+            id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(post)
+    }
+
+    async fn find_details(&self, id: i32) -> StorageResult<Option<BlogPostDetails>> {
+        let post = sqlx::query_as!(BlogPostDetails,
+            r#"SELECT p.id AS "id?", p.user_id, u.screen_name AS "screen_name!", p.title, p.content, p.created_at AS "created_at?", p.updated_at AS "updated_at?"
+            FROM posts p
+            LEFT JOIN users u ON u.id = p.user_id
+            WHERE p.id = $1"#,
+            id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(post)
+    }
+
+    async fn create(&self, user_id: i32, title: &str, content: &str) -> StorageResult<i32> {
+        let id = sqlx::query_scalar!("INSERT INTO posts (user_id, content, title) VALUES ($1, $2, $3) RETURNING id",
+            user_id, content, title)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(id)
+    }
+
+    async fn update(&self, id: i32, title: &str, content: &str) -> StorageResult<()> {
+        sqlx::query!("UPDATE posts SET content = $1, title = $2 WHERE id = $3", content, title, id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: i32) -> StorageResult<()> {
+        // comments go with the post
+        sqlx::query!("DELETE FROM posts WHERE id = $1", id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_by_user(&self, user_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<BlogPostModel>> {
+        let posts = sqlx::query_as!(BlogPostModel,
+            r#"SELECT id AS "id?", user_id, title, content, created_at AS "created_at?", updated_at AS "updated_at?" FROM posts WHERE user_id = $1
+            ORDER BY created_at
+            LIMIT $2 OFFSET $3"#,
+            user_id, limit as i64, offset as i64)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(posts)
+    }
+
+    async fn count_by_user(&self, user_id: i32) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM posts WHERE user_id = $1"#, user_id)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+}
+
+#[async_trait]
+impl CommentRepo for PgStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<BlogCommentModel>> {
+        let comment = sqlx::query_as!(BlogCommentModel,
+            r#"SELECT id AS "id?", user_id, post_id, content, created_at AS "created_at?", updated_at AS "updated_at?"
+            FROM comments WHERE id = $1"#,
+            id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(comment)
+    }
+
+    async fn create(&self, user_id: i32, post_id: i32, content: &str) -> StorageResult<()> {
+        sqlx::query!("INSERT INTO comments (user_id, post_id, content) VALUES ($1, $2, $3)", user_id, post_id, content)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn update(&self, id: i32, content: &str) -> StorageResult<()> {
+        sqlx::query!("UPDATE comments SET content = $1 WHERE id = $2", content, id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: i32) -> StorageResult<()> {
+        sqlx::query!("DELETE FROM comments WHERE id = $1", id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_for_post(&self, post_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<BlogCommentDetails>> {
+        let comments = sqlx::query_as!(BlogCommentDetails,
+            r#"SELECT c.id AS "id?", c.user_id, c.post_id, u.screen_name AS "screen_name!", u.avatar_key, c.content, c.created_at AS "created_at?", c.updated_at AS "updated_at?"
+            FROM comments c
+            LEFT JOIN users u ON c.user_id = u.id
+            WHERE c.post_id = $1
+            ORDER BY c.created_at
+            LIMIT $2 OFFSET $3"#,
+            post_id, limit as i64, offset as i64)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(comments)
+    }
+
+    async fn count_for_post(&self, post_id: i32) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM comments WHERE post_id = $1"#, post_id)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+
+    async fn all_by_user(&self, user_id: i32) -> StorageResult<Vec<BlogCommentModel>> {
+        let comments = sqlx::query_as!(BlogCommentModel,
+            r#"SELECT id AS "id?", user_id, post_id, content, created_at AS "created_at?", updated_at AS "updated_at?"
+            FROM comments WHERE user_id = $1
+            ORDER BY created_at"#,
+            user_id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(comments)
+    }
+}
+
+#[async_trait]
+impl AlbumRepo for PgStore {
+    async fn find(&self, id: i32) -> StorageResult<Option<AlbumModel>> {
+        let album = sqlx::query_as!(AlbumModel,
+            r#"SELECT id, user_id, name, visibility AS "visibility: Visibility", cover_id, created_at, updated_at
+            FROM albums WHERE id = $1"#,
+            id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(album)
+    }
+
+    async fn create(&self, user_id: i32, name: &str, visibility: Visibility) -> StorageResult<i32> {
+        let id = sqlx::query_scalar!("INSERT INTO albums (user_id, name, visibility) VALUES ($1, $2, $3) RETURNING id",
+            user_id, name, visibility.as_str())
+            .fetch_one(&self.db)
+            .await?;
+        Ok(id)
+    }
+
+    async fn update(&self, id: i32, name: &str, visibility: Visibility) -> StorageResult<()> {
+        sqlx::query!("UPDATE albums SET name = $1, visibility = $2 WHERE id = $3", name, visibility.as_str(), id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: i32) -> StorageResult<()> {
+        // photos go with the album
+        sqlx::query!("DELETE FROM albums WHERE id = $1", id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_cover(&self, id: i32, photo_id: Option<i32>) -> StorageResult<()> {
+        sqlx::query!("UPDATE albums SET cover_id = $1 WHERE id = $2", photo_id, id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_by_user(&self, user_id: i32, visibilities: &[Visibility], limit: i32, offset: i32) -> StorageResult<Vec<AlbumDetails>> {
+        let albums = sqlx::query_as!(AlbumDetails,
+            r#"SELECT a.id, a.user_id, a.name, a.visibility AS "visibility: Visibility", a.created_at,
+                COALESCE(c.key, (SELECT p.key FROM photos p WHERE p.album_id = a.id ORDER BY p.id LIMIT 1)) AS cover_key,
+                (SELECT COUNT(*) FROM photos p WHERE p.album_id = a.id) AS "photos!"
+            FROM albums a
+            LEFT JOIN photos c ON c.id = a.cover_id
+            WHERE a.user_id = $1 AND a.visibility = ANY($2)
+            ORDER BY a.created_at DESC, a.id DESC
+            LIMIT $3 OFFSET $4"#,
+            user_id, &visibility_names(visibilities), limit as i64, offset as i64)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(albums)
+    }
+
+    async fn count_by_user(&self, user_id: i32, visibilities: &[Visibility]) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM albums WHERE user_id = $1 AND visibility = ANY($2)"#,
+            user_id, &visibility_names(visibilities))
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+
+    async fn all_by_user(&self, user_id: i32) -> StorageResult<Vec<AlbumModel>> {
+        let albums = sqlx::query_as!(AlbumModel,
+            r#"SELECT id, user_id, name, visibility AS "visibility: Visibility", cover_id, created_at, updated_at
+            FROM albums WHERE user_id = $1
+            ORDER BY created_at"#,
+            user_id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(albums)
+    }
+
+    async fn add_photos(&self, album_id: i32, photos: &[(String, Option<String>)]) -> StorageResult<Vec<i32>> {
+        let mut tx = self.db.begin().await?;
+        let mut ids = Vec::with_capacity(photos.len());
+        for (key, caption) in photos {
+            let id = sqlx::query_scalar!("INSERT INTO photos (album_id, key, caption) VALUES ($1, $2, $3) RETURNING id",
+                album_id, key, caption.as_deref())
+                .fetch_one(&mut *tx)
+                .await?;
+            ids.push(id);
+        }
+        tx.commit().await?;
+        Ok(ids)
+    }
+
+    async fn find_photo(&self, id: i32) -> StorageResult<Option<PhotoModel>> {
+        let photo = sqlx::query_as!(PhotoModel,
+            "SELECT id, album_id, key, caption, created_at FROM photos WHERE id = $1",
+            id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(photo)
+    }
+
+    async fn delete_photo(&self, id: i32) -> StorageResult<()> {
+        // the cover reference is cleared by the foreign key
+        sqlx::query!("DELETE FROM photos WHERE id = $1", id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn photos(&self, album_id: i32, limit: i32, offset: i32) -> StorageResult<Vec<PhotoModel>> {
+        let photos = sqlx::query_as!(PhotoModel,
+            "SELECT id, album_id, key, caption, created_at FROM photos WHERE album_id = $1
+            ORDER BY id
+            LIMIT $2 OFFSET $3",
+            album_id, limit as i64, offset as i64)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(photos)
+    }
+
+    async fn count_photos(&self, album_id: i32) -> StorageResult<i64> {
+        let records = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM photos WHERE album_id = $1"#, album_id)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(records)
+    }
+
+    async fn photos_by_user(&self, user_id: i32) -> StorageResult<Vec<PhotoModel>> {
+        let photos = sqlx::query_as!(PhotoModel,
+            "SELECT p.id, p.album_id, p.key, p.caption, p.created_at FROM photos p
+            JOIN albums a ON a.id = p.album_id
+            WHERE a.user_id = $1
+            ORDER BY p.id",
+            user_id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(photos)
+    }
+
+    async fn photo_key_in_use(&self, key: &str) -> StorageResult<bool> {
+        let used = sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM photos WHERE key = $1) AS "used!""#, key)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(used)
+    }
+}
@@ -1,8 +1,10 @@
+use std::sync::Arc;
+
 use askama::Template;
 use axum::response::{Html, IntoResponse, Response};
 use axum::http::StatusCode;
 
-use crate::{UserData, UserModel, ProfileModel, UserDetails, FriendshipDetails, BlogPostModel, BlogPostDetails, BlogCommentModel, BlogCommentDetails};
+use crate::{UserData, UserModel, ProfileModel, UserDetails, FriendshipDetails, BlogPostModel, BlogPostDetails, BlogCommentModel, BlogCommentDetails, SessionModel, DataExportModel, AlbumModel, AlbumDetails, PhotoModel, redirect::RedirectTarget, media::MediaStore, albums::{Visibility, THUMBNAIL_SIZE, PHOTO_SIZE}};
 
 #[derive(Template)]
 #[template(path = "index.html")]
@@ -30,7 +32,7 @@ pub struct HelpTemplate {
 pub struct RegisterTemplate {
     pub path: &'static str,
     pub user: UserData,
-    pub redir: Option<String>,
+    pub redir: Option<RedirectTarget>,
 }
 
 #[derive(Template)]
@@ -38,7 +40,27 @@ pub struct RegisterTemplate {
 pub struct LoginTemplate {
     pub path: &'static str,
     pub user: UserData,
-    pub redir: Option<String>,
+    pub redir: Option<RedirectTarget>,
+}
+
+#[derive(Template)]
+#[template(path = "forgot-password.html")]
+pub struct ForgotPasswordTemplate {
+    pub path: &'static str,
+    pub user: UserData,
+}
+
+#[derive(Template)]
+#[template(path = "reset-link-sent.html")]
+pub struct ResetLinkSentTemplate {
+}
+
+#[derive(Template)]
+#[template(path = "reset-password.html")]
+pub struct ResetPasswordTemplate {
+    pub path: &'static str,
+    pub user: UserData,
+    pub token: Option<String>,
 }
 
 #[derive(Template)]
@@ -47,7 +69,9 @@ pub struct UserTemplate {
     pub path: &'static str,
     pub user: UserData,
     pub user_db: UserModel,
-    pub timestamp: i64,
+    pub avatar: Option<String>,
+    pub banner: Option<String>,
+    pub export: Option<DataExportModel>,
 }
 
 #[derive(Template)]
@@ -70,8 +94,19 @@ pub struct FieldTemplate {
 #[derive(Template)]
 #[template(path = "unauthorized.html")]
 pub struct UnauthorizedTemplate {
-    pub message: &'static str,
-    pub redir: Option<String>,
+    pub redir: RedirectTarget,
+}
+
+#[derive(Template)]
+#[template(path = "error-page.html")]
+pub struct ErrorPageTemplate {
+    pub title: &'static str,
+    pub messages: Vec<&'static str>,
+}
+
+#[derive(Template)]
+#[template(path = "username-form.html")]
+pub struct UsernameFormTemplate {
 }
 
 #[derive(Template)]
@@ -83,6 +118,53 @@ pub struct EmailFormTemplate {
 #[template(path = "email-field.html")]
 pub struct EmailFieldTemplate {
     pub email: String,
+    pub verified: bool,
+}
+
+#[derive(Template)]
+#[template(path = "two-factor-form.html")]
+pub struct TwoFactorFormTemplate {
+    pub secret: String,
+    pub uri: String,
+    pub qr: String,
+}
+
+#[derive(Template)]
+#[template(path = "two-factor-disable-form.html")]
+pub struct TwoFactorDisableFormTemplate {
+}
+
+#[derive(Template)]
+#[template(path = "two-factor-field.html")]
+pub struct TwoFactorFieldTemplate {
+    pub enabled: bool,
+}
+
+#[derive(Template)]
+#[template(path = "recovery-codes.html")]
+pub struct RecoveryCodesTemplate {
+    pub codes: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "login-2fa.html")]
+pub struct TwoFactorLoginTemplate {
+    pub path: &'static str,
+    pub user: UserData,
+    pub redir: Option<RedirectTarget>,
+}
+
+#[derive(Template)]
+#[template(path = "verification-sent.html")]
+pub struct VerificationSentTemplate {
+}
+
+#[derive(Template)]
+#[template(path = "email-verified.html")]
+pub struct EmailVerifiedTemplate {
+    pub path: &'static str,
+    pub user: UserData,
+    pub verified: bool,
 }
 
 #[derive(Template)]
@@ -90,6 +172,17 @@ pub struct EmailFieldTemplate {
 pub struct PasswordFormTemplate {
 }
 
+#[derive(Template)]
+#[template(path = "delete-account-form.html")]
+pub struct DeleteAccountFormTemplate {
+}
+
+#[derive(Template)]
+#[template(path = "export-field.html")]
+pub struct ExportFieldTemplate {
+    pub export: Option<DataExportModel>,
+}
+
 #[derive(Template)]
 #[template(path = "password-field.html")]
 pub struct PasswordFieldTemplate {
@@ -103,8 +196,10 @@ pub struct ProfileTemplate {
     pub username: String,
     pub profile: Option<ProfileModel>,
     pub owner: bool,
-    pub avatar: bool,
-    pub timestamp: i64,
+    /// URL of the largest size of the avatar.
+    pub avatar: Option<String>,
+    /// URL of the widest banner.
+    pub banner: Option<String>,
     pub friend: FriendStatus,
     pub friend_id: Option<i32>,
 }
@@ -119,11 +214,6 @@ pub enum FriendStatus {
     Cancelled
 }
 
-#[derive(Template)]
-#[template(path = "profile_error.html")]
-pub struct UserNotFoundTemplate {
-}
-
 #[derive(Template)]
 #[template(path = "profile-form.html")]
 pub struct ProfileFormTemplate {
@@ -151,6 +241,7 @@ pub struct CommunityTemplate {
     pub users: Vec<UserDetails>,
     pub records: Option<i64>,
     pub pages: i32,
+    pub media: Arc<dyn MediaStore>,
 }
 
 #[derive(Template)]
@@ -162,6 +253,7 @@ pub struct CommunityResultsTemplate {
     pub pages: i32,
     pub query: String,
     pub search_path: &'static str,
+    pub media: Arc<dyn MediaStore>,
 }
 
 #[derive(Template)]
@@ -179,9 +271,18 @@ pub struct AvatarFormTemplate {
 #[derive(Template)]
 #[template(path = "avatar-result.html")]
 pub struct AvatarResultTemplate {
-    pub avatar: bool,
-    pub username: String,
-    pub timestamp: i64,
+    pub avatar: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "banner-form.html")]
+pub struct BannerFormTemplate {
+}
+
+#[derive(Template)]
+#[template(path = "banner-result.html")]
+pub struct BannerResultTemplate {
+    pub banner: Option<String>,
 }
 
 #[derive(Template)]
@@ -204,6 +305,7 @@ pub struct FriendsTemplate {
     pub friends: Vec<FriendshipDetails>,
     pub records: Option<i64>,
     pub pages: i32,
+    pub media: Arc<dyn MediaStore>,
 }
 
 #[derive(Template)]
@@ -266,21 +368,45 @@ pub struct PostsResultTemplate {
     pub page: i32,
 }
 
+/// Albums on the profile, loaded into it a page at a time.
 #[derive(Template)]
-#[template(path = "post-form.html")]
-pub struct PostFormTemplate {
+#[template(path = "albums.html")]
+pub struct AlbumsTemplate {
+    pub username: String,
+    pub albums: Vec<AlbumDetails>,
+    pub owner: bool,
+    pub page: i32,
+    pub pages: i32,
+    pub media: Arc<dyn MediaStore>,
+}
+
+#[derive(Template)]
+#[template(path = "album.html")]
+pub struct AlbumTemplate {
     pub path: &'static str,
     pub user: UserData,
+    pub album: AlbumModel,
+    pub username: String,
+    pub owner: bool,
+    pub max_photos: usize,
 }
 
 #[derive(Template)]
-#[template(path = "post-error.html")]
-pub struct PostNotFoundTemplate {
+#[template(path = "album-photos.html")]
+pub struct AlbumPhotosTemplate {
+    pub album: AlbumModel,
+    pub photos: Vec<PhotoModel>,
+    pub owner: bool,
+    pub page: i32,
+    pub pages: i32,
+    pub media: Arc<dyn MediaStore>,
 }
 
 #[derive(Template)]
-#[template(path = "db-error.html")]
-pub struct DbErrorTemplate {
+#[template(path = "post-form.html")]
+pub struct PostFormTemplate {
+    pub path: &'static str,
+    pub user: UserData,
 }
 
 #[derive(Template)]
@@ -298,6 +424,7 @@ pub struct CommentsTemplate {
     pub user: UserData,
     pub pages: i32,
     pub page: i32,
+    pub media: Arc<dyn MediaStore>,
 }
 
 #[derive(Template)]
@@ -329,6 +456,7 @@ pub struct FriendsResultTemplate {
     pub friends: Vec<FriendshipDetails>,
     pub pages: i32,
     pub page: i32,
+    pub media: Arc<dyn MediaStore>,
 }
 
 #[derive(Template)]
@@ -336,6 +464,7 @@ pub struct FriendsResultTemplate {
 pub struct CommentAddResultTemplate {
     pub comment: String,
     pub screen_name: String,
+    pub avatar: Option<String>,
     pub id: String,
 }
 
@@ -345,6 +474,21 @@ pub struct DeletedCommentTemplate {
     pub id: i32,
 }
 
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub struct SessionsTemplate {
+    pub path: &'static str,
+    pub user: UserData,
+    pub sessions: Vec<SessionModel>,
+    pub current: String,
+}
+
+#[derive(Template)]
+#[template(path = "deleted-session.html")]
+pub struct DeletedSessionTemplate {
+    pub id: i32,
+}
+
 pub struct HtmlTemplate<T>(pub T);
 
 impl<T> IntoResponse for HtmlTemplate<T> where T: Template, {
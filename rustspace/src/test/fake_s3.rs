@@ -0,0 +1,165 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use axum::{Router, body::{Body, to_bytes}, extract::{Request, State}, http::{StatusCode, HeaderMap, Method}, response::{IntoResponse, Response}};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::config::S3Config;
+
+pub const BUCKET: &str = "rustspace";
+pub const ACCESS_KEY: &str = "test-access-key";
+pub const SECRET_KEY: &str = "test-secret-key";
+const REGION: &str = "us-east-1";
+
+type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// Stand-in for an S3 compatible service, like a local MinIO. It keeps objects of a single
+/// bucket in memory and checks the signature of every request, signed headers and
+/// presigned URLs alike, with the test credentials.
+pub struct FakeS3 {
+    pub endpoint: String,
+    objects: Objects,
+}
+
+impl FakeS3 {
+    pub async fn start() -> FakeS3 {
+        let objects = Objects::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().fallback(handle).with_state(objects.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        FakeS3 { endpoint, objects }
+    }
+
+    pub fn config(&self) -> S3Config {
+        S3Config {
+            endpoint: self.endpoint.clone(),
+            region: String::from(REGION),
+            bucket: String::from(BUCKET),
+            access_key: String::from(ACCESS_KEY),
+            secret_key: String::from(SECRET_KEY),
+            public_url: None,
+            url_lifetime: 60*60,
+            timeout: 5,
+        }
+    }
+
+    pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.objects.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+}
+
+async fn handle(State(objects): State<Objects>, request: Request) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap();
+    let path = parts.uri.path().to_string();
+    let query = parts.uri.query().unwrap_or_default().to_string();
+    let authorized = match query.contains("X-Amz-Signature=") {
+        true => parts.method == Method::GET && check_presigned(&path, &query, &parts.headers),
+        false => check_signed(&parts.method, &path, &parts.headers, &body),
+    };
+    if !authorized {
+        return StatusCode::FORBIDDEN.into_response()
+    }
+    let Some(key) = path.strip_prefix(&format!("/{}/", BUCKET)) else {
+        return StatusCode::NOT_FOUND.into_response()
+    };
+    let mut objects = objects.lock().unwrap();
+    match parts.method {
+        Method::PUT => {
+            objects.insert(key.to_string(), body.to_vec());
+            StatusCode::OK.into_response()
+        },
+        Method::GET => match objects.get(key) {
+            Some(data) => Response::new(Body::from(data.clone())),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+        Method::DELETE => {
+            objects.remove(key);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+    }
+}
+
+fn check_signed(method: &Method, path: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let payload_hash = hex(&Sha256::digest(body));
+    if header("x-amz-content-sha256") != payload_hash {
+        return false
+    }
+    let Some(fields) = header("authorization").strip_prefix("AWS4-HMAC-SHA256 ") else {
+        return false
+    };
+    let fields: HashMap<&str, &str> = fields.split(", ")
+        .filter_map(|field| field.split_once('='))
+        .collect();
+    let (Some(credential), Some(signed_headers), Some(signature)) = (fields.get("Credential"), fields.get("SignedHeaders"), fields.get("Signature")) else {
+        return false
+    };
+    let canonical_headers: String = signed_headers.split(';')
+        .map(|name| format!("{}:{}\n", name, header(name).trim()))
+        .collect();
+    let canonical_request = format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed_headers, payload_hash);
+    check_signature(credential, header("x-amz-date"), &canonical_request, signature)
+}
+
+fn check_presigned(path: &str, query: &str, headers: &HeaderMap) -> bool {
+    let mut params: Vec<(&str, &str)> = query.split('&')
+        .filter_map(|param| param.split_once('='))
+        .collect();
+    let Some(signature) = params.iter().find(|(name, _)| *name == "X-Amz-Signature").map(|(_, value)| *value) else {
+        return false
+    };
+    let param = |name: &str| params.iter().find(|(param, _)| *param == name).map(|(_, value)| *value).unwrap_or_default();
+    let date = param("X-Amz-Date").to_string();
+    let credential = param("X-Amz-Credential").replace("%2F", "/");
+    let Ok(expires) = param("X-Amz-Expires").parse::<i64>() else {
+        return false
+    };
+    let Ok(signed_at) = NaiveDateTime::parse_from_str(&date, "%Y%m%dT%H%M%SZ") else {
+        return false
+    };
+    if DateTime::<Utc>::from_naive_utc_and_offset(signed_at, Utc) + chrono::Duration::seconds(expires) < Utc::now() {
+        return false
+    }
+    params.retain(|(name, _)| *name != "X-Amz-Signature");
+    params.sort();
+    let canonical_query = params.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&");
+    let host = headers.get("host").and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let canonical_request = format!("GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD", path, canonical_query, host);
+    check_signature(&credential, &date, &canonical_request, signature)
+}
+
+fn check_signature(credential: &str, date: &str, canonical_request: &str, signature: &str) -> bool {
+    let day = date.get(..8).unwrap_or_default();
+    let scope = format!("{}/{}/s3/aws4_request", day, REGION);
+    if credential != format!("{}/{}", ACCESS_KEY, scope) {
+        return false
+    }
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", date, scope, hex(&Sha256::digest(canonical_request.as_bytes())));
+    let mut key = hmac(format!("AWS4{}", SECRET_KEY).as_bytes(), day.as_bytes());
+    for part in [REGION, "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    hex(&hmac(&key, string_to_sign.as_bytes())) == signature
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
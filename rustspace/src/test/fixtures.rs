@@ -0,0 +1,288 @@
+use std::path::PathBuf;
+
+use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+use axum::{extract::Request, body::{Body, to_bytes}, response::Response};
+use image::{RgbaImage, Rgba, DynamicImage, ImageOutputFormat};
+use rand_core::OsRng;
+use sqlx::PgPool;
+
+use crate::images::sized_key;
+
+use super::{test_token_id, test_user_id, get_token, test_media_dir};
+
+const BOUNDARY: &str = "upload-boundary";
+
+/// User with the password `password`, stored in plain text unless hashed,
+/// for tests that don't go through the login.
+pub struct UserFixture {
+    username: String,
+    email: String,
+    hash_password: bool,
+    verified: bool,
+}
+
+impl UserFixture {
+    pub fn new(username: &str) -> UserFixture {
+        UserFixture {
+            username: String::from(username),
+            email: format!("{}@email.com", username.to_lowercase()),
+            hash_password: false,
+            verified: false,
+        }
+    }
+
+    pub fn email(mut self, email: &str) -> UserFixture {
+        self.email = String::from(email);
+        self
+    }
+
+    pub fn hashed_password(mut self) -> UserFixture {
+        self.hash_password = true;
+        self
+    }
+
+    pub fn verified(mut self) -> UserFixture {
+        self.verified = true;
+        self
+    }
+
+    /// Also opens the session the tokens from `get_token` refer to. Returns the id of the user.
+    pub async fn insert(self, db: &PgPool) -> i32 {
+        let password = match self.hash_password {
+            true => {
+                let salt = SaltString::generate(&mut OsRng);
+                Argon2::default()
+                .hash_password("password".as_bytes(), &salt)
+                .map(|hash| hash.to_string()).unwrap()
+            },
+            false => String::from("password")
+        };
+        let id = sqlx::query_scalar("INSERT INTO users (id, screen_name, email, password, email_verified_at)
+                                    VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN now() END) RETURNING id")
+            .bind(test_user_id(&self.username))
+            .bind(&self.username)
+            .bind(&self.email)
+            .bind(password)
+            .bind(self.verified)
+            .fetch_one(db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO sessions (token_id, user_id, expires_at) VALUES ($1, $2, now() + interval '1 hour')")
+            .bind(test_token_id(&self.username))
+            .bind(id)
+            .execute(db)
+            .await
+            .unwrap();
+        id
+    }
+}
+
+/// Pending request from the user to the friend.
+pub struct FriendshipFixture {
+    user_id: i32,
+    friend_id: i32,
+    accepted: bool,
+    rejected: bool,
+}
+
+impl FriendshipFixture {
+    pub fn new(user_id: i32, friend_id: i32) -> FriendshipFixture {
+        FriendshipFixture { user_id, friend_id, accepted: false, rejected: false }
+    }
+
+    pub fn accepted(mut self) -> FriendshipFixture {
+        self.accepted = true;
+        self
+    }
+
+    pub fn rejected(mut self) -> FriendshipFixture {
+        self.rejected = true;
+        self
+    }
+
+    pub async fn insert(self, db: &PgPool) -> i32 {
+        sqlx::query_scalar("INSERT INTO friendships (user_id, friend_id, accepted, rejected, accepted_at)
+                           VALUES ($1, $2, $3, $4, CASE WHEN $3 THEN now() END) RETURNING id")
+            .bind(self.user_id)
+            .bind(self.friend_id)
+            .bind(self.accepted)
+            .bind(self.rejected)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+}
+
+pub struct PostFixture {
+    user_id: i32,
+    title: String,
+    content: String,
+}
+
+impl PostFixture {
+    pub fn new(user_id: i32) -> PostFixture {
+        PostFixture { user_id, title: String::from("Title"), content: String::from("Content") }
+    }
+
+    pub fn title(mut self, title: &str) -> PostFixture {
+        self.title = String::from(title);
+        self
+    }
+
+    pub fn content(mut self, content: &str) -> PostFixture {
+        self.content = String::from(content);
+        self
+    }
+
+    pub async fn insert(self, db: &PgPool) -> i32 {
+        sqlx::query_scalar("INSERT INTO posts (user_id, title, content) VALUES ($1, $2, $3) RETURNING id")
+            .bind(self.user_id)
+            .bind(&self.title)
+            .bind(&self.content)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+}
+
+pub struct CommentFixture {
+    user_id: i32,
+    post_id: i32,
+    content: String,
+}
+
+impl CommentFixture {
+    pub fn new(user_id: i32, post_id: i32) -> CommentFixture {
+        CommentFixture { user_id, post_id, content: String::from("Comment") }
+    }
+
+    pub fn content(mut self, content: &str) -> CommentFixture {
+        self.content = String::from(content);
+        self
+    }
+
+    pub async fn insert(self, db: &PgPool) -> i32 {
+        sqlx::query_scalar("INSERT INTO comments (user_id, post_id, content) VALUES ($1, $2, $3) RETURNING id")
+            .bind(self.user_id)
+            .bind(self.post_id)
+            .bind(&self.content)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+}
+
+/// Public album without photos.
+pub struct AlbumFixture {
+    user_id: i32,
+    name: String,
+    visibility: String,
+}
+
+impl AlbumFixture {
+    pub fn new(user_id: i32) -> AlbumFixture {
+        AlbumFixture { user_id, name: String::from("Album"), visibility: String::from("public") }
+    }
+
+    pub fn name(mut self, name: &str) -> AlbumFixture {
+        self.name = String::from(name);
+        self
+    }
+
+    pub fn visibility(mut self, visibility: &str) -> AlbumFixture {
+        self.visibility = String::from(visibility);
+        self
+    }
+
+    pub async fn insert(self, db: &PgPool) -> i32 {
+        sqlx::query_scalar("INSERT INTO albums (user_id, name, visibility) VALUES ($1, $2, $3) RETURNING id")
+            .bind(self.user_id)
+            .bind(&self.name)
+            .bind(&self.visibility)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+}
+
+/// Photo whose files were never stored.
+pub async fn insert_photo(album_id: i32, key: &str, db: &PgPool) -> i32 {
+    sqlx::query_scalar("INSERT INTO photos (album_id, key) VALUES ($1, $2) RETURNING id")
+        .bind(album_id)
+        .bind(key)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+/// Id of a user inserted earlier in the test.
+pub async fn user_id(username: &str, db: &PgPool) -> i32 {
+    sqlx::query_scalar("SELECT id FROM users WHERE screen_name = $1")
+        .bind(username)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+/// Image with a red top left quarter and the rest blue, so crops show which part they kept.
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = RgbaImage::from_fn(width, height, |x, y| match x < width / 2 && y < height / 2 {
+        true => Rgba([255, 0, 0, 255]),
+        false => Rgba([0, 0, 255, 255]),
+    });
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgba8(image).write_to(&mut std::io::Cursor::new(&mut bytes), ImageOutputFormat::Png).unwrap();
+    bytes
+}
+
+/// Part of a multipart form.
+pub enum Part<'a> {
+    Field(&'a str, &'a str),
+    /// File of the `image` input.
+    Image(&'a [u8]),
+}
+
+/// Multipart form sent by the user, with the parts in the given order.
+pub fn upload_request(username: &str, uri: &str, parts: &[Part]) -> Request<Body> {
+    let (token, _) = get_token(&Some(String::from(username)));
+    let mut body = Vec::new();
+    for part in parts {
+        match part {
+            Part::Field(name, value) => {
+                body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value).as_bytes());
+            },
+            Part::Image(image) => {
+                body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"image.png\"\r\nContent-Type: image/png\r\n\r\n", BOUNDARY).as_bytes());
+                body.extend(*image);
+                body.extend(b"\r\n");
+            },
+        }
+    }
+    body.extend(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    Request::builder()
+        .method("POST")
+        .header("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY))
+        .header("Cookie", format!("Token={};", token))
+        .uri(uri)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+pub async fn body_text(response: Response) -> String {
+    let bytes = to_bytes(response.into_body(), 1000000).await.unwrap();
+    String::from(std::str::from_utf8(&bytes).unwrap())
+}
+
+/// Media key kept in the column of the user, `avatar_key` or `banner_key`.
+pub async fn stored_key(column: &str, user_id: i32, db: &PgPool) -> Option<String> {
+    sqlx::query_scalar(&format!("SELECT {} FROM users WHERE id = $1", column))
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+/// Local file of one size of the upload stored under `key`.
+pub fn media_file(db: &PgPool, key: &str, size: u32) -> PathBuf {
+    test_media_dir(db).join(sized_key(key, size))
+}
@@ -0,0 +1,194 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_db, insert_default_user, get_token, test_config, test_state_with_clock, test_router, fixtures::{UserFixture, PostFixture, CommentFixture, FriendshipFixture}}, accounts::purge_deleted_accounts, clock::FixedClock};
+
+const NOW: i64 = 1_700_000_000;
+
+fn now() -> DateTime<Utc> {
+    DateTime::from_timestamp(NOW, 0).unwrap()
+}
+
+async fn deletion_due_at(username: &str, db: &PgPool) -> Option<DateTime<Utc>> {
+    sqlx::query_scalar("SELECT deletion_due_at FROM users WHERE screen_name = $1")
+        .bind(username)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+async fn schedule_deletion(username: &str, due_at: DateTime<Utc>, db: &PgPool) {
+    sqlx::query("UPDATE users SET deletion_due_at = $1 WHERE screen_name = $2")
+        .bind(due_at)
+        .bind(username)
+        .execute(db)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_getting_delete_account_form() {
+    let db = prepare_db().await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .uri("/forms/account/delete")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 1000).await;
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&bytes).unwrap();
+    assert!(content.contains("form"));
+    assert!(content.contains("psw"));
+}
+
+#[tokio::test]
+async fn test_deleting_account_with_wrong_password() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/user/delete")
+            .body(Body::from("psw=wrong_password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(deletion_due_at("Test", &db).await.is_none());
+}
+
+#[tokio::test]
+async fn test_deleting_account_while_unauthenticated() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .header("HX-Request", "true")
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/user/delete")
+            .body(Body::from("psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(deletion_due_at("Test", &db).await.is_none());
+}
+
+#[tokio::test]
+async fn test_deleting_account() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let state = test_state_with_clock(db.clone(), test_config(), Box::new(FixedClock(now())));
+    let grace_period = state.config.accounts.deletion_grace_period;
+    let response = test_router(state)
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/user/delete")
+            .body(Body::from("psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("HX-redirect").unwrap(), "/");
+    assert_eq!(deletion_due_at("Test", &db).await, Some(now() + chrono::Duration::seconds(grace_period)));
+    let sessions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE revoked_at IS NULL")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(sessions, 0);
+}
+
+#[tokio::test]
+async fn test_logging_in_cancels_deletion() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    schedule_deletion("Test", Utc::now() + chrono::Duration::days(1), &db).await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(deletion_due_at("Test", &db).await.is_none());
+}
+
+#[tokio::test]
+async fn test_purging_deleted_accounts() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let other_id = UserFixture::new("User").insert(&db).await;
+    UserFixture::new("Later").insert(&db).await;
+    let post_id = PostFixture::new(user_id).insert(&db).await;
+    let other_post_id = PostFixture::new(other_id).insert(&db).await;
+    CommentFixture::new(other_id, post_id).insert(&db).await;
+    CommentFixture::new(user_id, other_post_id).insert(&db).await;
+    FriendshipFixture::new(user_id, other_id).accepted().insert(&db).await;
+    schedule_deletion("Test", now() - chrono::Duration::seconds(1), &db).await;
+    schedule_deletion("Later", now() + chrono::Duration::seconds(1), &db).await;
+
+    let state = test_state_with_clock(db.clone(), test_config(), Box::new(FixedClock(now())));
+    state.media.put("avatars/1/key-64.png", b"avatar".to_vec(), "image/png").await.unwrap();
+    state.storage.users.set_avatar(user_id, Some("avatars/1/key")).await.unwrap();
+    state.media.put("banners/1/key-1500.webp", b"banner".to_vec(), "image/webp").await.unwrap();
+    state.storage.users.set_banner(user_id, Some("banners/1/key.webp")).await.unwrap();
+
+    assert_eq!(purge_deleted_accounts(&state).await.unwrap(), 1);
+    assert!(state.media.get("avatars/1/key-64.png").await.unwrap().is_none());
+    assert!(state.media.get("banners/1/key-1500.webp").await.unwrap().is_none());
+
+    let users: Vec<String> = sqlx::query_scalar("SELECT screen_name FROM users ORDER BY screen_name")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(users, vec!["Later", "User"]);
+    let posts: Vec<i32> = sqlx::query_scalar("SELECT id FROM posts")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(posts, vec![other_post_id]);
+    let comments: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM comments")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(comments, 0);
+    let friendships: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM friendships")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(friendships, 0);
+}
@@ -0,0 +1,355 @@
+use axum::{extract::Request, body::Body, http::StatusCode};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_db, get_token, test_media_dir, fixtures::{UserFixture, FriendshipFixture, AlbumFixture, Part::{Field, Image}, insert_photo, png, upload_request, body_text, media_file}}, albums::{PHOTO_SIZES, THUMBNAIL_SIZE}, images::sized_key};
+
+fn form_request(username: &str, method: &str, uri: &str, body: &str) -> Request<Body> {
+    let (token, _) = get_token(&Some(String::from(username)));
+    Request::builder()
+        .method(method)
+        .header("Cookie", format!("Token={};", token))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .uri(uri)
+        .body(Body::from(String::from(body)))
+        .unwrap()
+}
+
+fn get_request(username: Option<&str>, uri: &str) -> Request<Body> {
+    let builder = Request::builder().uri(uri);
+    let builder = match username {
+        Some(username) => builder.header("Cookie", format!("Token={};", get_token(&Some(String::from(username))).0)),
+        None => builder,
+    };
+    builder.body(Body::empty()).unwrap()
+}
+
+async fn photos(album_id: i32, db: &PgPool) -> Vec<(i32, String, Option<String>)> {
+    sqlx::query_as("SELECT id, key, caption FROM photos WHERE album_id = $1 ORDER BY id")
+        .bind(album_id)
+        .fetch_all(db)
+        .await
+        .unwrap()
+}
+
+fn photo_files_exist(db: &PgPool, key: &str) -> bool {
+    PHOTO_SIZES.iter().all(|size| media_file(db, key, *size).exists())
+}
+
+#[tokio::test]
+async fn test_creating_album() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "POST", "/albums", "name=Holidays&visibility=friends"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let (id, visibility): (i32, String) = sqlx::query_as("SELECT id, visibility FROM albums WHERE user_id = $1 AND name = 'Holidays'")
+        .bind(user_id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(visibility, "friends");
+    assert_eq!(response.headers().get("HX-redirect").unwrap(), format!("/albums/{}", id).as_str());
+}
+
+#[tokio::test]
+async fn test_creating_album_without_name() {
+    let db = prepare_db().await;
+    UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "POST", "/albums", "name=&visibility=everyone"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let content = body_text(response).await;
+    assert!(content.contains("Album name cannot be empty!"));
+    assert!(content.contains("Unknown album visibility!"));
+}
+
+#[tokio::test]
+async fn test_uploading_photos_with_captions() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", &format!("/albums/{}/photos", album_id), &[Image(&png(400, 300)), Field("caption", "Beach"), Image(&png(300, 400)), Field("caption", "")]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("HX-redirect").unwrap(), format!("/albums/{}", album_id).as_str());
+    let photos = photos(album_id, &db).await;
+    assert_eq!(photos.len(), 2);
+    assert_eq!(photos[0].2.as_deref(), Some("Beach"));
+    assert_eq!(photos[1].2, None);
+    for (_, key, _) in &photos {
+        assert!(key.starts_with(&format!("photos/{}/", user_id)));
+        assert!(photo_files_exist(&db, key));
+    }
+}
+
+#[tokio::test]
+async fn test_uploading_invalid_photo_adds_none() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", &format!("/albums/{}/photos", album_id), &[Image(&png(400, 300)), Field("caption", "Beach"), Image(b"not an image"), Field("caption", "")]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(photos(album_id, &db).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_uploading_photos_to_album_of_other_user() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let other_id = UserFixture::new("Other").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Other", &format!("/albums/{}/photos", album_id), &[Image(&png(400, 300)), Field("caption", "Beach")]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(photos(album_id, &db).await.is_empty());
+    assert!(!test_media_dir(&db).join(format!("photos/{}", other_id)).exists());
+}
+
+#[tokio::test]
+async fn test_album_visibility() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let friend_id = UserFixture::new("Friend").insert(&db).await;
+    UserFixture::new("Other").insert(&db).await;
+    FriendshipFixture::new(friend_id, user_id).accepted().insert(&db).await;
+    AlbumFixture::new(user_id).name("Everyone's album").insert(&db).await;
+    AlbumFixture::new(user_id).name("Friends' album").visibility("friends").insert(&db).await;
+    let private_id = AlbumFixture::new(user_id).name("Private album").visibility("private").insert(&db).await;
+
+    let cases = [
+        (Some("Test"), true, true),
+        (Some("Friend"), true, false),
+        (Some("Other"), false, false),
+        (None, false, false),
+    ];
+    for (viewer, friends, private) in cases {
+        let response = prepare_server_with_db(db.clone())
+            .await
+            .oneshot(get_request(viewer, "/user/Test/albums?page=0"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let content = body_text(response).await;
+        assert!(content.contains("Everyone&#x27;s album"), "{:?}", viewer);
+        assert_eq!(content.contains("Friends&#x27; album"), friends, "{:?}", viewer);
+        assert_eq!(content.contains("Private album"), private, "{:?}", viewer);
+    }
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(get_request(Some("Friend"), &format!("/albums/{}", private_id)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_updating_album() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    UserFixture::new("Other").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    let uri = format!("/albums/{}", album_id);
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Other", "PUT", &uri, "name=Mine&visibility=public"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "PUT", &uri, "name=Renamed&visibility=private"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let (name, visibility): (String, String) = sqlx::query_as("SELECT name, visibility FROM albums WHERE id = $1")
+        .bind(album_id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(name, "Renamed");
+    assert_eq!(visibility, "private");
+}
+
+#[tokio::test]
+async fn test_setting_cover() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    insert_photo(album_id, "photos/1/first.webp", &db).await;
+    let cover_id = insert_photo(album_id, "photos/1/second.webp", &db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "PUT", &format!("/albums/{}/cover", album_id), &format!("photo_id={}", cover_id)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(get_request(None, "/user/Test/albums?page=0"))
+        .await
+        .unwrap();
+    let content = body_text(response).await;
+    assert!(content.contains(&sized_key("photos/1/second.webp", THUMBNAIL_SIZE)));
+    assert!(!content.contains("first"));
+}
+
+#[tokio::test]
+async fn test_setting_cover_from_other_album() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    let other_album_id = AlbumFixture::new(user_id).insert(&db).await;
+    let photo_id = insert_photo(other_album_id, "photos/1/photo.webp", &db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "PUT", &format!("/albums/{}/cover", album_id), &format!("photo_id={}", photo_id)))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let cover_id: Option<i32> = sqlx::query_scalar("SELECT cover_id FROM albums WHERE id = $1")
+        .bind(album_id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(cover_id, None);
+}
+
+#[tokio::test]
+async fn test_albums_are_paginated() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    AlbumFixture::new(user_id).name("Oldest album").insert(&db).await;
+    for _ in 0..12 {
+        AlbumFixture::new(user_id).insert(&db).await;
+    }
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(get_request(None, "/user/Test/albums?page=0"))
+        .await
+        .unwrap();
+    let content = body_text(response).await;
+    assert!(!content.contains("Oldest album"));
+    assert!(content.contains("/user/Test/albums?page=1"));
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(get_request(None, "/user/Test/albums?page=1"))
+        .await
+        .unwrap();
+    let content = body_text(response).await;
+    assert!(content.contains("Oldest album"));
+    assert_eq!(content.matches("class=\"album\"").count(), 1);
+}
+
+#[tokio::test]
+async fn test_deleting_photo_removes_its_files() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", &format!("/albums/{}/photos", album_id), &[Image(&png(400, 300)), Field("caption", "Beach"), Image(&png(200, 300)), Field("caption", "Hills")]))
+        .await
+        .unwrap();
+    let uploaded = photos(album_id, &db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "DELETE", &format!("/albums/photos/{}", uploaded[0].0), ""))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let remaining = photos(album_id, &db).await;
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].2.as_deref(), Some("Hills"));
+    assert!(PHOTO_SIZES.iter().all(|size| !media_file(&db, &uploaded[0].1, *size).exists()));
+    assert!(photo_files_exist(&db, &uploaded[1].1));
+}
+
+#[tokio::test]
+async fn test_deleting_photo_keeps_files_of_same_image() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    for _ in 0..2 {
+        prepare_server_with_db(db.clone())
+            .await
+            .oneshot(upload_request("Test", &format!("/albums/{}/photos", album_id), &[Image(&png(400, 300))]))
+            .await
+            .unwrap();
+    }
+    let uploaded = photos(album_id, &db).await;
+    assert_eq!(uploaded.len(), 2);
+    assert_eq!(uploaded[0].1, uploaded[1].1);
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "DELETE", &format!("/albums/photos/{}", uploaded[0].0), ""))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(photo_files_exist(&db, &uploaded[1].1));
+}
+
+#[tokio::test]
+async fn test_deleting_album_removes_photos() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", &format!("/albums/{}/photos", album_id), &[Image(&png(400, 300)), Field("caption", "Beach")]))
+        .await
+        .unwrap();
+    let uploaded = photos(album_id, &db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(form_request("Test", "DELETE", &format!("/albums/{}", album_id), ""))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("HX-redirect").unwrap(), "/profile/Test");
+    assert!(photos(album_id, &db).await.is_empty());
+    assert!(PHOTO_SIZES.iter().all(|size| !media_file(&db, &uploaded[0].1, *size).exists()));
+}
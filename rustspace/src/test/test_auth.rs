@@ -2,13 +2,11 @@ use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
 use sqlx::Row;
 use tower::ServiceExt;
 
-use crate::security::get_token;
-use serial_test::serial;
+use crate::test::get_token;
 
 use crate::test::{prepare_server, prepare_db, prepare_server_with_db, prepare_server_with_user};
 
 #[tokio::test]
-#[serial]
 async fn test_getting_register_form() {
     let response = prepare_server()
         .await
@@ -22,7 +20,7 @@ async fn test_getting_register_form() {
         .unwrap();
 
     assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), 2000).await;
+    let body = to_bytes(response.into_body(), 5000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
@@ -30,7 +28,6 @@ async fn test_getting_register_form() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_validating_duplicated_username() {
     let response = prepare_server_with_user(false)
         .await
@@ -45,7 +42,7 @@ async fn test_validating_duplicated_username() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::CONFLICT);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -56,7 +53,6 @@ async fn test_validating_duplicated_username() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_validating_duplicated_email() {
     let response = prepare_server_with_user(false)
         .await
@@ -71,7 +67,7 @@ async fn test_validating_duplicated_email() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::CONFLICT);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -82,7 +78,6 @@ async fn test_validating_duplicated_email() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_redirecting_after_successful_registration() {
     let response = prepare_server_with_user(false)
         .await
@@ -106,7 +101,6 @@ async fn test_redirecting_after_successful_registration() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_adding_user_to_db() {
     let db = prepare_db().await;
     _ = prepare_server_with_db(db.clone())
@@ -132,7 +126,6 @@ async fn test_adding_user_to_db() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_authentication_for_nonexistent_user() {
     let response = prepare_server_with_user(false)
         .await
@@ -147,16 +140,15 @@ async fn test_authentication_for_nonexistent_user() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
-    assert!(content.contains("No such user"));
+    assert!(content.contains("Invalid username or password"));
 }
 
 #[tokio::test]
-#[serial]
 async fn test_authentication_with_wrong_password() {
     let response = prepare_server_with_user(true)
         .await
@@ -171,16 +163,15 @@ async fn test_authentication_with_wrong_password() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
-    assert!(content.contains("password"));
+    assert!(content.contains("Invalid username or password"));
 }
 
 #[tokio::test]
-#[serial]
 async fn test_authentication() {
     let response = prepare_server_with_user(true)
         .await
@@ -209,7 +200,6 @@ async fn test_authentication() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_logging_out() {
     let response = prepare_server_with_user(false)
         .await
@@ -233,12 +223,13 @@ async fn test_logging_out() {
     let header = response.headers().get("Set-Cookie");
     assert!(header.is_some());
     if let Some(header) = header {
-        assert_eq!(header.to_str().unwrap(), "Token=");
+        let cookie = header.to_str().unwrap();
+        assert!(cookie.starts_with("Token=;"));
+        assert!(cookie.contains("Max-Age=0"));
     }
 }
 
 #[tokio::test]
-#[serial]
 async fn test_navigation_for_guest() {
     let response = prepare_server_with_user(false)
         .await
@@ -265,7 +256,6 @@ async fn test_navigation_for_guest() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_navigation_for_authorized_user() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -294,7 +284,6 @@ async fn test_navigation_for_authorized_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_user_redir_for_unauthenticated() {
     let response = prepare_server_with_user(false)
         .await
@@ -309,7 +298,7 @@ async fn test_user_redir_for_unauthenticated() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -319,7 +308,6 @@ async fn test_user_redir_for_unauthenticated() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_user_redir_for_authenticated() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -337,7 +325,7 @@ async fn test_user_redir_for_authenticated() {
         .unwrap();
 
     assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), 2000).await;
+    let body = to_bytes(response.into_body(), 5000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
@@ -345,7 +333,6 @@ async fn test_user_redir_for_authenticated() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_login_page_add_friendly_redirect_to_form() {
     let response = prepare_server_with_user(false)
         .await
@@ -369,7 +356,6 @@ async fn test_login_page_add_friendly_redirect_to_form() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_friendly_redir_after_authentication() {
     let response = prepare_server_with_user(true)
         .await
@@ -393,7 +379,6 @@ async fn test_friendly_redir_after_authentication() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_friendly_redir_after_registration() {
     let response = prepare_server_with_user(false)
         .await
@@ -417,7 +402,6 @@ async fn test_friendly_redir_after_registration() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_remembering() {
     let response = prepare_server_with_user(true)
         .await
@@ -438,9 +422,101 @@ async fn test_remembering() {
     if let Some(header) = header {
         assert_eq!(header.to_str().unwrap(), "/user");
     }
-    let header = response.headers().get("Set-Cookie");
+    let refresh = response.headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|header| header.to_str().unwrap())
+        .find(|cookie| cookie.starts_with("Refresh="));
+    assert!(refresh.is_some());
+    if let Some(refresh) = refresh {
+        assert!(refresh.contains("Max-Age=2592000"));
+    }
+}
+
+#[tokio::test]
+async fn test_redirect_to_other_site_after_authentication() {
+    let response = prepare_server_with_user(true)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=password&redir=%2F%2Fevil.com"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/user");
+    }
+}
+
+#[tokio::test]
+async fn test_redirect_to_other_site_after_registration() {
+    let response = prepare_server()
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/register")
+            .body(Body::from("username=User&email=user%40email.com&psw=password&psw_repeat=password&redir=https%3A%2F%2Fevil.com"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/user");
+    }
+}
+
+#[tokio::test]
+async fn test_redirecting_to_login_without_path() {
+    let response = prepare_server()
+        .await
+        .oneshot(
+            Request::builder()
+            .uri("/to_login")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/login?path=/user");
+    }
+}
+
+#[tokio::test]
+async fn test_redirecting_to_login_keeps_query() {
+    let response = prepare_server()
+        .await
+        .oneshot(
+            Request::builder()
+            .uri("/to_login?path=%2Fcommunity%2Fsearch%3Fpage%3D2")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
     assert!(header.is_some());
     if let Some(header) = header {
-        assert!(header.to_str().unwrap().contains("Max-Age=3600"));
+        assert_eq!(header.to_str().unwrap(), "/login?path=/community/search%3Fpage%3D2");
     }
 }
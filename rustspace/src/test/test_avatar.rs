@@ -0,0 +1,437 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
+use image::{RgbaImage, Rgba, RgbImage, Rgb, DynamicImage, ImageOutputFormat, Frame, Delay, AnimationDecoder, codecs::gif::{GifEncoder, GifDecoder}};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_db, prepare_server_with_config, get_token, test_config, test_state, test_media_dir, fixtures::{UserFixture, PostFixture, CommentFixture, FriendshipFixture, Part::{Field, Image}, png, upload_request, body_text, stored_key, media_file}}, accounts::convert_old_avatars, sized_image::AVATARS, images::sized_key};
+
+fn gif(frames: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut bytes);
+        for i in 0..frames {
+            let image = RgbaImage::from_pixel(100, 100, Rgba([(i * 50) as u8, 0, 0, 255]));
+            encoder.encode_frame(Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(100, 1))).unwrap();
+        }
+    }
+    bytes
+}
+
+/// Photo with a red left half and a blue right half, taken with the camera turned
+/// clockwise, and with the place it was taken in its EXIF data.
+fn jpeg_with_exif(width: u32, height: u32) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, _| match x < width / 2 {
+        true => Rgb([255, 0, 0]),
+        false => Rgb([0, 0, 255]),
+    });
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(image).write_to(&mut std::io::Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90)).unwrap();
+    // orientation 6, then a string standing in for the GPS tags
+    let mut exif = b"Exif\0\0MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0".to_vec();
+    exif.extend(b"GPS 52.2297N 21.0122E");
+    let mut data = jpeg[..2].to_vec();
+    data.extend([0xff, 0xe1]);
+    data.extend((exif.len() as u16 + 2).to_be_bytes());
+    data.extend(exif);
+    data.extend(&jpeg[2..]);
+    data
+}
+
+fn get_request(uri: &str) -> Request<Body> {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    Request::builder()
+        .header("Cookie", format!("Token={};", token))
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn set_key(user_id: i32, key: &str, db: &PgPool) {
+    sqlx::query("UPDATE users SET avatar = true, avatar_key = $1 WHERE id = $2")
+        .bind(key)
+        .bind(user_id)
+        .execute(db)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_uploading_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&png(300, 200))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    assert!(key.starts_with(&format!("avatars/{}/", user_id)));
+    assert!(key.ends_with(".webp"));
+    assert!(body_text(response).await.contains(&format!("/media/{}", sized_key(&key, 256))));
+    for &size in AVATARS.widths {
+        let image = image::open(media_file(&db, &key, size)).unwrap();
+        assert_eq!((image.width(), image.height()), (size, size));
+    }
+}
+
+#[tokio::test]
+async fn test_uploading_avatar_with_crop_area() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Field("x", "200"), Field("y", "50"), Field("width", "100"), Field("height", "100"), Image(&png(300, 200))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    let image = image::open(media_file(&db, &key, 64)).unwrap().to_rgba8();
+    assert!(image.get_pixel(0, 0)[2] > 200);
+    assert!(image.get_pixel(63, 63)[2] > 200);
+}
+
+#[tokio::test]
+async fn test_uploading_avatar_with_crop_area_outside_of_image() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Field("x", "250"), Field("y", "0"), Field("width", "100"), Field("height", "100"), Image(&png(300, 200))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(stored_key("avatar_key", user_id, &db).await.is_none());
+    assert!(!test_media_dir(&db).exists());
+}
+
+#[tokio::test]
+async fn test_uploading_too_large_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let mut config = test_config();
+    config.assets.max_avatar_dimension = 250;
+
+    let response = prepare_server_with_config(db.clone(), config)
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&png(300, 200))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(stored_key("avatar_key", user_id, &db).await.is_none());
+    assert!(!test_media_dir(&db).exists());
+}
+
+#[tokio::test]
+async fn test_uploading_avatar_that_is_not_an_image() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(b"not an image")]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(stored_key("avatar_key", user_id, &db).await.is_none());
+    assert!(!test_media_dir(&db).exists());
+}
+
+#[tokio::test]
+async fn test_uploading_webp_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let mut webp = Vec::new();
+    DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 100, Rgba([0, 255, 0, 255])))
+        .write_to(&mut std::io::Cursor::new(&mut webp), ImageOutputFormat::WebP)
+        .unwrap();
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&webp)]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    let image = image::open(media_file(&db, &key, 64)).unwrap().to_rgba8();
+    assert!(image.get_pixel(32, 32)[1] > 200);
+}
+
+#[tokio::test]
+async fn test_uploading_animated_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&gif(3))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    assert!(key.ends_with(".gif"));
+    for &size in AVATARS.widths {
+        let data = std::fs::read(media_file(&db, &key, size)).unwrap();
+        let frames = GifDecoder::new(std::io::Cursor::new(data)).unwrap().into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].buffer().dimensions(), (size, size));
+    }
+}
+
+#[tokio::test]
+async fn test_uploading_animation_with_too_many_frames() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let mut config = test_config();
+    config.assets.max_animation_frames = 2;
+
+    let response = prepare_server_with_config(db.clone(), config)
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&gif(3))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(stored_key("avatar_key", user_id, &db).await.is_none());
+}
+
+#[tokio::test]
+async fn test_metadata_is_removed_from_photos() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&jpeg_with_exif(300, 200))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    for &size in AVATARS.widths {
+        let data = std::fs::read(media_file(&db, &key, size)).unwrap();
+        assert!(!data.windows(4).any(|window| window == b"Exif" || window == b"GPS "));
+    }
+    // the photo is turned upright, so the red half is on top
+    let image = image::open(media_file(&db, &key, 64)).unwrap().to_rgba8();
+    assert!(image.get_pixel(32, 4)[0] > 200);
+    assert!(image.get_pixel(32, 59)[2] > 200);
+}
+
+#[cfg(not(feature = "avif"))]
+#[tokio::test]
+async fn test_refusing_avif_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let mut avif = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf".to_vec();
+    avif.extend([0; 64]);
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&avif)]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body_text(response).await.contains("AVIF images aren&#x27;t supported"));
+    assert!(stored_key("avatar_key", user_id, &db).await.is_none());
+}
+
+#[cfg(feature = "avif")]
+#[tokio::test]
+async fn test_uploading_avif_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(include_bytes!("images/red-quarter.avif"))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    assert!(key.ends_with(".webp"));
+    let image = image::open(media_file(&db, &key, 64)).unwrap().to_rgba8();
+    assert_eq!((image.width(), image.height()), (64, 64));
+}
+
+#[tokio::test]
+async fn test_uploading_too_large_file() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let mut config = test_config();
+    config.assets.max_upload_size = 1000;
+    let image = png(300, 200);
+    assert!(image.len() > 1000);
+
+    let response = prepare_server_with_config(db.clone(), config)
+        .await
+        .oneshot(upload_request("Test", "/avatar", &[Image(&image)]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(stored_key("avatar_key", user_id, &db).await.is_none());
+    assert!(!test_media_dir(&db).exists());
+}
+
+#[tokio::test]
+async fn test_deleting_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let app = prepare_server_with_db(db.clone()).await;
+    app.clone()
+        .oneshot(upload_request("Test", "/avatar", &[Image(&png(300, 200))]))
+        .await
+        .unwrap();
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let response = app
+        .oneshot(
+            Request::builder()
+            .method("DELETE")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/avatar")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(stored_key("avatar_key", user_id, &db).await.is_none());
+    for &size in AVATARS.widths {
+        assert!(!media_file(&db, &key, size).exists());
+    }
+}
+
+#[tokio::test]
+async fn test_replacing_avatar() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let app = prepare_server_with_db(db.clone()).await;
+    app.clone()
+        .oneshot(upload_request("Test", "/avatar", &[Image(&png(300, 200))]))
+        .await
+        .unwrap();
+    let old_key = stored_key("avatar_key", user_id, &db).await.unwrap();
+
+    let response = app
+        .oneshot(upload_request("Test", "/avatar", &[Field("x", "0"), Field("y", "0"), Field("width", "100"), Field("height", "100"), Image(&png(300, 200))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    assert_ne!(key, old_key);
+    assert!(body_text(response).await.contains(&format!("/media/{}", sized_key(&key, 256))));
+    for &size in AVATARS.widths {
+        assert!(media_file(&db, &key, size).exists());
+        assert!(!media_file(&db, &old_key, size).exists());
+    }
+}
+
+#[tokio::test]
+async fn test_uploading_same_avatar_again() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let app = prepare_server_with_db(db.clone()).await;
+    for _ in 0..2 {
+        let response = app.clone()
+            .oneshot(upload_request("Test", "/avatar", &[Image(&png(300, 200))]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+    for &size in AVATARS.widths {
+        assert!(media_file(&db, &key, size).exists());
+    }
+}
+
+#[tokio::test]
+async fn test_media_files_are_cached() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let app = prepare_server_with_db(db.clone()).await;
+    app.clone()
+        .oneshot(upload_request("Test", "/avatar", &[Image(&png(300, 200))]))
+        .await
+        .unwrap();
+    let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+
+    let response = app
+        .oneshot(get_request(&format!("/media/{}", sized_key(&key, 64))))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("Cache-Control").unwrap().to_str().unwrap().contains("immutable"));
+    let bytes = to_bytes(response.into_body(), 100000).await.unwrap();
+    let image = image::load_from_memory(&bytes).unwrap();
+    assert_eq!((image.width(), image.height()), (64, 64));
+}
+
+#[tokio::test]
+async fn test_lists_show_avatar_thumbnails() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let friend_id = UserFixture::new("Anna").insert(&db).await;
+    FriendshipFixture::new(friend_id, user_id).accepted().insert(&db).await;
+    let post_id = PostFixture::new(user_id).insert(&db).await;
+    CommentFixture::new(friend_id, post_id).insert(&db).await;
+    set_key(friend_id, "avatars/1/abc", &db).await;
+    let app = prepare_server_with_db(db).await;
+
+    let thumbnail = |size: u32| format!("/media/avatars/1/abc-{}.png", size);
+    let response = app.clone().oneshot(get_request("/community")).await.unwrap();
+    assert!(body_text(response).await.contains(&thumbnail(64)));
+    let response = app.clone().oneshot(get_request("/friends")).await.unwrap();
+    assert!(body_text(response).await.contains(&thumbnail(64)));
+    let response = app.oneshot(get_request(&format!("/blog/{}/comments", post_id))).await.unwrap();
+    assert!(body_text(response).await.contains(&thumbnail(32)));
+}
+
+#[tokio::test]
+async fn test_converting_old_avatars() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let sized_id = UserFixture::new("Sized").insert(&db).await;
+    let other_id = UserFixture::new("User").insert(&db).await;
+    sqlx::query("UPDATE users SET avatar = true")
+        .execute(&db)
+        .await
+        .unwrap();
+    // already in the media store
+    set_key(other_id, "avatars/1/abc", &db).await;
+    let state = test_state(db.clone(), test_config());
+    let avatars = &state.config.assets.avatars;
+    std::fs::create_dir_all(avatars).unwrap();
+    std::fs::write(avatars.join("Test.png"), png(300, 200)).unwrap();
+    for &size in AVATARS.widths {
+        std::fs::write(avatars.join(format!("{}-{}.png", sized_id, size)), png(size, size)).unwrap();
+    }
+
+    assert_eq!(convert_old_avatars(&state).await.unwrap(), 2);
+    assert_eq!(std::fs::read_dir(avatars).unwrap().count(), 0);
+    for user_id in [user_id, sized_id] {
+        let key = stored_key("avatar_key", user_id, &db).await.unwrap();
+        for &size in AVATARS.widths {
+            assert!(media_file(&db, &key, size).exists());
+        }
+    }
+    assert_eq!(stored_key("avatar_key", other_id, &db).await.as_deref(), Some("avatars/1/abc"));
+}
@@ -0,0 +1,181 @@
+use axum::{extract::Request, body::Body, http::StatusCode};
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_db, get_token, fixtures::{UserFixture, Part::{Field, Image}, png, upload_request, body_text, stored_key, media_file}}, sized_image::BANNERS, images::sized_key};
+
+fn request(method: &str, uri: &str) -> Request<Body> {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    Request::builder()
+        .method(method)
+        .header("Cookie", format!("Token={};", token))
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_uploading_banner() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Image(&png(900, 600))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("banner_key", user_id, &db).await.unwrap();
+    assert!(key.starts_with(&format!("banners/{}/", user_id)));
+    assert!(body_text(response).await.contains(&sized_key(&key, BANNERS.largest())));
+    for &width in BANNERS.widths {
+        let image = image::open(media_file(&db, &key, width)).unwrap();
+        assert_eq!((image.width(), image.height()), (width, width / 3));
+    }
+}
+
+#[tokio::test]
+async fn test_uploading_cropped_banner() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Field("x", "0"), Field("y", "400"), Field("width", "600"), Field("height", "200"), Image(&png(900, 600))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let key = stored_key("banner_key", user_id, &db).await.unwrap();
+    let image = image::open(media_file(&db, &key, BANNERS.widths[0])).unwrap().to_rgba8();
+    assert!(image.get_pixel(10, 10)[2] > 200);
+}
+
+#[tokio::test]
+async fn test_uploading_banner_with_crop_outside_of_image() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Field("x", "500"), Field("y", "0"), Field("width", "600"), Field("height", "200"), Image(&png(900, 600))]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body_text(response).await.contains("Crop area must lie within the image!"));
+    assert!(stored_key("banner_key", user_id, &db).await.is_none());
+}
+
+#[tokio::test]
+async fn test_uploading_invalid_banner() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Image(b"not an image")]))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(stored_key("banner_key", user_id, &db).await.is_none());
+}
+
+#[tokio::test]
+async fn test_replacing_banner_removes_old_files() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Image(&png(900, 600))]))
+        .await
+        .unwrap();
+    let old_key = stored_key("banner_key", user_id, &db).await.unwrap();
+
+    prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Field("x", "0"), Field("y", "0"), Field("width", "600"), Field("height", "200"), Image(&png(900, 600))]))
+        .await
+        .unwrap();
+
+    let key = stored_key("banner_key", user_id, &db).await.unwrap();
+    assert_ne!(key, old_key);
+    assert!(BANNERS.widths.iter().all(|width| !media_file(&db, &old_key, *width).exists()));
+    assert!(BANNERS.widths.iter().all(|width| media_file(&db, &key, *width).exists()));
+}
+
+#[tokio::test]
+async fn test_deleting_banner() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Image(&png(900, 600))]))
+        .await
+        .unwrap();
+    let key = stored_key("banner_key", user_id, &db).await.unwrap();
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(request("DELETE", "/banner"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_text(response).await.contains("Upload banner"));
+    assert!(stored_key("banner_key", user_id, &db).await.is_none());
+    assert!(BANNERS.widths.iter().all(|width| !media_file(&db, &key, *width).exists()));
+}
+
+#[tokio::test]
+async fn test_deleting_missing_banner() {
+    let db = prepare_db().await;
+    UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(request("DELETE", "/banner"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_banner_on_profile() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    UserFixture::new("Other").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(request("GET", "/profile/Other"))
+        .await
+        .unwrap();
+    assert!(!body_text(response).await.contains("class=\"banner\""));
+
+    prepare_server_with_db(db.clone())
+        .await
+        .oneshot(upload_request("Test", "/banner", &[Image(&png(900, 600))]))
+        .await
+        .unwrap();
+    let key = stored_key("banner_key", user_id, &db).await.unwrap();
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(request("GET", "/profile/Test"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let content = body_text(response).await;
+    assert!(content.contains("class=\"banner\""));
+    assert!(content.contains(&sized_key(&key, BANNERS.largest())));
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(request("GET", "/user"))
+        .await
+        .unwrap();
+    assert!(body_text(response).await.contains("Delete banner"));
+}
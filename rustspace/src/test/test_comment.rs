@@ -1,20 +1,19 @@
 use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
 use sqlx::{Row, PgPool, Postgres};
 use tower::ServiceExt;
-use serial_test::serial;
 
-use crate::{test::{prepare_server_with_user, prepare_db, prepare_server_with_db, insert_new_user, clear_posts, clear_comments}, security::get_token, UserModel, BlogCommentModel};
+use crate::{test::{fixtures::{PostFixture, CommentFixture, user_id}, prepare_server_with_user, prepare_db, prepare_server_with_db, insert_new_user, get_token}, BlogCommentModel};
 
 // adding comment
 
 #[tokio::test]
-#[serial]
 async fn test_making_comment_request_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("POST")
+            .header("HX-Request", "true")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/blog/1/comments")
             .body(Body::from("content=content"))
@@ -32,26 +31,14 @@ async fn test_making_comment_request_by_unauthenticated_user() {
 }
 
 async fn insert_post(username: &str, title: &str, content: &str, db: &PgPool) -> i32 {
-    let user_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&username)
-        .fetch_optional(db)
-        .await;
-    let Ok(Some(user)) = user_db else {
-        panic!("No such user!");
-    };
-
-    let result = sqlx::query_scalar("INSERT INTO posts (user_id, title, content) VALUES ($1, $2, $3) RETURNING id")
-        .bind(&user.id)
-        .bind(title)
-        .bind(content)
-        .fetch_one(db)
-        .await;
-
-    result.unwrap()
+    PostFixture::new(user_id(username, db).await)
+        .title(title)
+        .content(content)
+        .insert(db)
+        .await
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_comment_request_with_no_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -70,9 +57,8 @@ async fn test_making_comment_request_with_no_content() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -83,7 +69,6 @@ async fn test_making_comment_request_with_no_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_comment_request_with_empty_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -102,9 +87,8 @@ async fn test_making_comment_request_with_empty_content() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -115,7 +99,6 @@ async fn test_making_comment_request_with_empty_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_comment_request_to_nonexistent_post() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -143,7 +126,6 @@ async fn test_making_comment_request_to_nonexistent_post() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_adding_comment() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -166,8 +148,6 @@ async fn test_adding_comment() {
     let result = sqlx::query("SELECT COUNT(*) FROM comments")
         .fetch_one(&db)
         .await;
-    clear_posts(&db).await;
-    clear_comments(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     assert!(result.is_ok());
@@ -179,13 +159,13 @@ async fn test_adding_comment() {
 // deleting comment
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_comment_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("DELETE")
+            .header("HX-Request", "true")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/blog/comment/1")
             .body(Body::empty())
@@ -203,7 +183,6 @@ async fn test_deleting_comment_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_nonexistent_comment() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -220,7 +199,7 @@ async fn test_deleting_nonexistent_comment() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -230,26 +209,13 @@ async fn test_deleting_nonexistent_comment() {
 }
 
 async fn insert_comment(username: &str, post_id: &i32, content: &str, db: &PgPool) -> i32 {
-    let user_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&username)
-        .fetch_optional(db)
-        .await;
-    let Ok(Some(user)) = user_db else {
-        panic!("No such user!");
-    };
-
-    let result = sqlx::query_scalar("INSERT INTO comments (user_id, post_id, content) VALUES ($1, $2, $3) RETURNING id")
-        .bind(&user.id)
-        .bind(post_id)
-        .bind(content)
-        .fetch_one(db)
-        .await;
-
-    result.unwrap()
+    CommentFixture::new(user_id(username, db).await, *post_id)
+        .content(content)
+        .insert(db)
+        .await
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_comment_authored_by_different_user() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -270,10 +236,8 @@ async fn test_deleting_comment_authored_by_different_user() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
-    clear_comments(&db).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -283,7 +247,6 @@ async fn test_deleting_comment_authored_by_different_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_comment() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -308,8 +271,6 @@ async fn test_deleting_comment() {
     let result = sqlx::query("SELECT COUNT(*) FROM comments")
         .fetch_one(&db)
         .await;
-    clear_posts(&db).await;
-    clear_comments(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     assert!(result.is_ok());
@@ -321,13 +282,13 @@ async fn test_deleting_comment() {
 // editing comment
 
 #[tokio::test]
-#[serial]
 async fn test_editing_comment_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("PUT")
+            .header("HX-Request", "true")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/blog/comment/1")
             .body(Body::from("content=content"))
@@ -345,7 +306,6 @@ async fn test_editing_comment_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_nonexistent_comment() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -362,7 +322,7 @@ async fn test_editing_nonexistent_comment() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -372,7 +332,6 @@ async fn test_editing_nonexistent_comment() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_comment_authored_by_different_user() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -393,10 +352,8 @@ async fn test_editing_comment_authored_by_different_user() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
-    clear_comments(&db).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -406,7 +363,6 @@ async fn test_editing_comment_authored_by_different_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_comment_with_no_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -428,9 +384,7 @@ async fn test_editing_comment_with_no_content() {
         .await
         .unwrap();
 
-    clear_posts(&db).await;
-    clear_comments(&db).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -441,7 +395,6 @@ async fn test_editing_comment_with_no_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_comment_with_empty_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -463,9 +416,7 @@ async fn test_editing_comment_with_empty_content() {
         .await
         .unwrap();
 
-    clear_posts(&db).await;
-    clear_comments(&db).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -476,7 +427,6 @@ async fn test_editing_comment_with_empty_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_comment() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -503,8 +453,6 @@ async fn test_editing_comment() {
         .bind(&comment_id)
         .fetch_optional(&db)
         .await;
-    clear_posts(&db).await;
-    clear_comments(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     assert!(comment.is_ok());
@@ -1,12 +1,10 @@
 use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
 use regex::Regex;
 use tower::ServiceExt;
-use serial_test::serial;
 
-use crate::{test::{prepare_server_with_user, prepare_db, prepare_server_with_db, insert_users, insert_new_user}, security::get_token};
+use crate::test::{prepare_server_with_user, prepare_db, prepare_server_with_db, insert_users, insert_new_user, insert_default_user, get_token};
 
 #[tokio::test]
-#[serial]
 async fn test_getting_community_page_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
@@ -28,7 +26,6 @@ async fn test_getting_community_page_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_community_page() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -52,7 +49,6 @@ async fn test_getting_community_page() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_community_page_with_correct_users() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -83,10 +79,10 @@ async fn test_getting_community_page_with_correct_users() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_community_page_with_two_pages() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(30, "aaa", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -112,7 +108,6 @@ async fn test_getting_community_page_with_two_pages() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_users_catalogue_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
@@ -134,7 +129,6 @@ async fn test_getting_users_catalogue_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_users_catalogue() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -164,10 +158,10 @@ async fn test_getting_users_catalogue() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_users_catalogue_with_two_pages() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(30, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -195,10 +189,10 @@ async fn test_getting_users_catalogue_with_two_pages() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_second_page_of_catalogue() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -227,10 +221,10 @@ async fn test_getting_second_page_of_catalogue() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_third_page_of_catalogue() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -260,10 +254,10 @@ async fn test_getting_third_page_of_catalogue() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_page_in_the_middle() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -295,10 +289,10 @@ async fn test_getting_page_in_the_middle() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_last_page() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -326,7 +320,6 @@ async fn test_getting_last_page() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_search_page_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
@@ -348,7 +341,6 @@ async fn test_getting_search_page_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_search_page() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -372,10 +364,10 @@ async fn test_getting_search_page() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_searching_users() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_new_user("user1", "user1@mail.com", &db).await;
     insert_new_user("auser", "user2@mail.com", &db).await;
     insert_new_user("aaauser55", "user3@mail.com", &db).await;
@@ -408,10 +400,10 @@ async fn test_searching_users() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_users_search_results_with_two_pages() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(30, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -439,10 +431,10 @@ async fn test_getting_users_search_results_with_two_pages() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_second_page_of_user_search_results() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -471,10 +463,10 @@ async fn test_getting_second_page_of_user_search_results() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_third_page_of_user_search_results() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -504,10 +496,10 @@ async fn test_getting_third_page_of_user_search_results() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_page_of_user_search_results_in_the_middle() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -539,10 +531,10 @@ async fn test_getting_page_of_user_search_results_in_the_middle() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_last_page_of_user_search_result() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_users(300, "user", &db).await;
     let response = prepare_server_with_db(db)
         .await
@@ -570,10 +562,10 @@ async fn test_getting_last_page_of_user_search_result() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_requesting_negative_page_of_catalogue() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     let response = prepare_server_with_db(db)
         .await
         .oneshot(
@@ -586,7 +578,7 @@ async fn test_requesting_negative_page_of_catalogue() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 9000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -596,10 +588,10 @@ async fn test_requesting_negative_page_of_catalogue() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_requesting_negative_page_of_user_search_results() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     let response = prepare_server_with_db(db)
         .await
         .oneshot(
@@ -612,7 +604,7 @@ async fn test_requesting_negative_page_of_user_search_results() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 9000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -622,10 +614,10 @@ async fn test_requesting_negative_page_of_user_search_results() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_requesting_too_long_search_query_in_catalogue() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     let response = prepare_server_with_db(db)
         .await
         .oneshot(
@@ -638,7 +630,7 @@ async fn test_requesting_too_long_search_query_in_catalogue() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 9000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -0,0 +1,123 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode, response::Response};
+use tower::ServiceExt;
+
+use crate::{get_router, test::{prepare_db, test_state, test_config, insert_default_user, get_token}};
+
+async fn send(request: Request) -> Response {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    get_router(test_state(db, test_config()))
+        .oneshot(request)
+        .await
+        .unwrap()
+}
+
+fn csrf_cookie(response: &Response) -> Option<String> {
+    response.headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .find(|cookie| cookie.starts_with("Csrf="))
+        .map(String::from)
+}
+
+#[tokio::test]
+async fn test_page_issues_csrf_token() {
+    let response = send(
+        Request::builder()
+        .uri("/login")
+        .body(Body::empty())
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = csrf_cookie(&response);
+    assert!(cookie.is_some());
+    let cookie = cookie.unwrap();
+    let token = cookie.split(';').next().unwrap().trim_start_matches("Csrf=");
+    let body = to_bytes(response.into_body(), 5000).await.unwrap();
+    let content = std::str::from_utf8(&*body).unwrap();
+    assert!(content.contains(&format!("\"X-CSRF-Token\": \"{}\"", token)));
+}
+
+#[tokio::test]
+async fn test_existing_csrf_token_is_kept() {
+    let response = send(
+        Request::builder()
+        .uri("/login")
+        .header("Cookie", "Csrf=token")
+        .body(Body::empty())
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(csrf_cookie(&response).is_none());
+    let body = to_bytes(response.into_body(), 5000).await.unwrap();
+    let content = std::str::from_utf8(&*body).unwrap();
+    assert!(content.contains("\"X-CSRF-Token\": \"token\""));
+}
+
+#[tokio::test]
+async fn test_forged_request_without_token_is_rejected() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let response = send(
+        Request::builder()
+        .method("PUT")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Cookie", format!("Token={}; Csrf=token", token))
+        .uri("/email")
+        .body(Body::from("email=attacker%40email.com"))
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_forged_request_with_wrong_token_is_rejected() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let response = send(
+        Request::builder()
+        .method("POST")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Cookie", format!("Token={}; Csrf=token", token))
+        .header("X-CSRF-Token", "guessed")
+        .uri("/blog")
+        .body(Body::from("title=Title&content=Content"))
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_request_without_csrf_cookie_is_rejected() {
+    let response = send(
+        Request::builder()
+        .method("POST")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-CSRF-Token", "token")
+        .uri("/login")
+        .body(Body::from("username=Test&psw=password"))
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_request_with_matching_token_is_accepted() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let response = send(
+        Request::builder()
+        .method("DELETE")
+        .header("Cookie", format!("Token={}; Csrf=token", token))
+        .header("X-CSRF-Token", "token")
+        .uri("/avatar")
+        .body(Body::empty())
+        .unwrap()
+        ).await;
+
+    // the request gets past the check, there's just no avatar to delete
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
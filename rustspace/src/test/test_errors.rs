@@ -0,0 +1,105 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode, response::Response};
+use tower::ServiceExt;
+
+use crate::test::{prepare_server_with_user, prepare_db, prepare_server_with_db, insert_default_user};
+
+async fn send(request: Request) -> Response {
+    prepare_server_with_user(false)
+        .await
+        .oneshot(request)
+        .await
+        .unwrap()
+}
+
+async fn body(response: Response) -> String {
+    let bytes = to_bytes(response.into_body(), 5000).await.unwrap();
+    String::from(std::str::from_utf8(&*bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_htmx_request_gets_error_fragment() {
+    let response = send(
+        Request::builder()
+        .header("HX-Request", "true")
+        .uri("/blog/1000")
+        .body(Body::empty())
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let content = body(response).await;
+    assert!(content.contains("No such post!"));
+    assert!(!content.contains("<html"));
+}
+
+#[tokio::test]
+async fn test_page_request_gets_error_page() {
+    let response = send(
+        Request::builder()
+        .uri("/blog/1000")
+        .body(Body::empty())
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.headers().get("Content-Type").unwrap().to_str().unwrap().starts_with("text/html"));
+    let content = body(response).await;
+    assert!(content.contains("<html"));
+    assert!(content.contains("Not found!"));
+    assert!(content.contains("No such post!"));
+}
+
+#[tokio::test]
+async fn test_unauthenticated_page_request_links_back_to_page() {
+    let response = send(
+        Request::builder()
+        .uri("/friends/page?page=2")
+        .body(Body::empty())
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let content = body(response).await;
+    assert!(content.contains("Authentication Error"));
+    assert!(content.contains("/login?path=/friends/page%3Fpage%3D2"));
+}
+
+#[tokio::test]
+async fn test_unauthenticated_htmx_request_gets_fragment() {
+    let response = send(
+        Request::builder()
+        .header("HX-Request", "true")
+        .uri("/community/users")
+        .body(Body::empty())
+        .unwrap()
+        ).await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let content = body(response).await;
+    assert!(content.contains("Unauthenticated!"));
+    assert!(!content.contains("<html"));
+}
+
+#[tokio::test]
+async fn test_unique_constraint_is_reported_as_conflict() {
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("HX-Request", "true")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/register")
+            .body(Body::from("username=Test&email=other%40email.com&psw=password&psw_repeat=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let content = body(response).await;
+    assert!(content.contains("Username must be unique!"));
+    assert!(!content.contains("Database error"));
+}
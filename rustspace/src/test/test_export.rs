@@ -0,0 +1,244 @@
+use std::io::{Cursor, Read};
+
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_db, get_token, test_config, test_state, test_state_with_clock, test_export_dir, test_media_dir, fixtures::{UserFixture, PostFixture, CommentFixture, FriendshipFixture}}, export::remove_expired_exports, clock::FixedClock};
+
+fn export_request(method: &str, uri: &str) -> Request<Body> {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    Request::builder()
+        .method(method)
+        .header("Cookie", format!("Token={};", token))
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn insert_export(user_id: i32, completed: bool, expires_in: Duration, db: &PgPool) -> i32 {
+    let id = sqlx::query_scalar("INSERT INTO data_exports (user_id, completed_at, expires_at)
+                                VALUES ($1, CASE WHEN $2 THEN now() END, now() + $3) RETURNING id")
+        .bind(user_id)
+        .bind(completed)
+        .bind(expires_in)
+        .fetch_one(db)
+        .await
+        .unwrap();
+    std::fs::create_dir_all(test_export_dir(db)).unwrap();
+    std::fs::write(test_export_dir(db).join(format!("{}.zip", id)), "archive").unwrap();
+    id
+}
+
+async fn count_exports(db: &PgPool) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM data_exports")
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+async fn wait_for_export(db: &PgPool) {
+    for _ in 0..100 {
+        let completed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM data_exports WHERE completed_at IS NOT NULL")
+            .fetch_one(db)
+            .await
+            .unwrap();
+        if completed > 0 {
+            return
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("Export wasn't generated!");
+}
+
+fn read_file(archive: &mut zip::ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+    let mut content = String::new();
+    archive.by_name(name).unwrap().read_to_string(&mut content).unwrap();
+    content
+}
+
+#[tokio::test]
+async fn test_exporting_data() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").verified().insert(&db).await;
+    let other_id = UserFixture::new("Friend").insert(&db).await;
+    let post_id = PostFixture::new(user_id).title("My first post").insert(&db).await;
+    CommentFixture::new(user_id, post_id).content("My own comment").insert(&db).await;
+    FriendshipFixture::new(other_id, user_id).accepted().insert(&db).await;
+    sqlx::query("INSERT INTO profiles (user_id, city) VALUES ($1, 'Springfield')")
+        .bind(user_id)
+        .execute(&db)
+        .await
+        .unwrap();
+    let banner = test_media_dir(&db).join("banners/1/key-1500.webp");
+    std::fs::create_dir_all(banner.parent().unwrap()).unwrap();
+    std::fs::write(banner, b"banner").unwrap();
+    sqlx::query("UPDATE users SET banner_key = 'banners/1/key.webp' WHERE id = $1")
+        .bind(user_id)
+        .execute(&db)
+        .await
+        .unwrap();
+    let app = prepare_server_with_db(db.clone()).await;
+
+    let response = app.clone()
+        .oneshot(export_request("POST", "/user/export"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), 10000).await.unwrap();
+    let content = std::str::from_utf8(&bytes).unwrap();
+    assert!(content.contains("Preparing"));
+
+    wait_for_export(&db).await;
+    let response = app
+        .oneshot(export_request("GET", "/user/export"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "application/zip");
+    let bytes = to_bytes(response.into_body(), 1_000_000).await.unwrap();
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes.to_vec())).unwrap();
+
+    let account = read_file(&mut archive, "account.json");
+    assert!(account.contains("test@email.com"));
+    assert!(!account.contains("password"));
+    assert!(read_file(&mut archive, "profile.json").contains("Springfield"));
+    assert!(read_file(&mut archive, "friendships.json").contains("Friend"));
+    assert!(read_file(&mut archive, "posts.json").contains("My first post"));
+    assert!(read_file(&mut archive, "comments.json").contains("My own comment"));
+    let summary = read_file(&mut archive, "README.md");
+    assert!(summary.contains("Springfield"));
+    assert!(summary.contains("Friend invited Test"));
+    assert!(summary.contains("### My first post"));
+    assert!(summary.contains("My own comment"));
+    assert!(archive.by_name("avatar.png").is_err());
+    assert_eq!(read_file(&mut archive, "banner.webp"), "banner");
+}
+
+#[tokio::test]
+async fn test_requesting_export_while_one_is_prepared() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    insert_export(user_id, false, Duration::days(1), &db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(export_request("POST", "/user/export"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(count_exports(&db).await, 1);
+}
+
+#[tokio::test]
+async fn test_requesting_export_while_unauthenticated() {
+    let db = prepare_db().await;
+    UserFixture::new("Test").insert(&db).await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .header("HX-Request", "true")
+            .method("POST")
+            .uri("/user/export")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(count_exports(&db).await, 0);
+}
+
+#[tokio::test]
+async fn test_downloading_export_before_it_is_ready() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    insert_export(user_id, false, Duration::days(1), &db).await;
+
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(export_request("GET", "/user/export"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_downloading_expired_export() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    insert_export(user_id, true, Duration::days(-1), &db).await;
+
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(export_request("GET", "/user/export"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_downloading_export_of_other_user() {
+    let db = prepare_db().await;
+    UserFixture::new("Test").insert(&db).await;
+    let other_id = UserFixture::new("User").insert(&db).await;
+    insert_export(other_id, true, Duration::days(1), &db).await;
+
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(export_request("GET", "/user/export"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_removing_expired_exports() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    insert_export(user_id, true, Duration::days(-1), &db).await;
+    let valid_id = insert_export(user_id, true, Duration::days(1), &db).await;
+    // left behind by a deleted account
+    std::fs::write(test_export_dir(&db).join("0.zip"), "archive").unwrap();
+    let state = test_state_with_clock(db.clone(), test_config(), Box::new(FixedClock(Utc::now() + Duration::hours(2))));
+
+    assert_eq!(remove_expired_exports(&state).await.unwrap(), 2);
+    assert_eq!(count_exports(&db).await, 1);
+    let files: Vec<_> = std::fs::read_dir(test_export_dir(&db)).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(files, vec![format!("{}.zip", valid_id)]);
+}
+
+#[tokio::test]
+async fn test_removing_interrupted_exports() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    insert_export(user_id, false, Duration::days(1), &db).await;
+    let state = test_state_with_clock(db.clone(), test_config(), Box::new(FixedClock(Utc::now() + Duration::hours(2))));
+
+    assert_eq!(remove_expired_exports(&state).await.unwrap(), 1);
+    assert_eq!(count_exports(&db).await, 0);
+}
+
+#[tokio::test]
+async fn test_keeping_exports_in_progress() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let id = insert_export(user_id, false, Duration::days(1), &db).await;
+    // written by another server, whose row is newer than the ones read
+    std::fs::write(test_export_dir(&db).join("0.zip"), "archive").unwrap();
+    let state = test_state(db.clone(), test_config());
+
+    assert_eq!(remove_expired_exports(&state).await.unwrap(), 0);
+    assert_eq!(count_exports(&db).await, 1);
+    assert!(test_export_dir(&db).join(format!("{}.zip", id)).exists());
+    assert!(test_export_dir(&db).join("0.zip").exists());
+}
@@ -1,18 +1,17 @@
 use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
 use sqlx::{Postgres, PgPool, Row};
 use tower::ServiceExt;
-use serial_test::serial;
 
-use crate::{test::{prepare_server_with_user, prepare_db, prepare_server_with_db, insert_new_user, clear_friendships, insert_users, insert_requests}, security::get_token, UserModel, FriendshipModel};
+use crate::{test::{fixtures::{FriendshipFixture, user_id}, prepare_server_with_user, prepare_db, prepare_server_with_db, insert_new_user, insert_default_user, insert_users, insert_requests, get_token}, FriendshipModel};
 
 #[tokio::test]
-#[serial]
 async fn test_making_friendship_request_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("POST")
+            .header("HX-Request", "true")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/friendships")
             .body(Body::from("username=User"))
@@ -30,7 +29,6 @@ async fn test_making_friendship_request_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_friendship_request_with_no_name() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -47,7 +45,7 @@ async fn test_making_friendship_request_with_no_name() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -58,7 +56,6 @@ async fn test_making_friendship_request_with_no_name() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_friendship_request_with_empty_name() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -75,7 +72,7 @@ async fn test_making_friendship_request_with_empty_name() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -86,7 +83,6 @@ async fn test_making_friendship_request_with_empty_name() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_user_trying_to_befriend_themself() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -103,7 +99,7 @@ async fn test_user_trying_to_befriend_themself() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -113,7 +109,6 @@ async fn test_user_trying_to_befriend_themself() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_user_trying_to_befriend_nonexistent_user() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -130,7 +125,7 @@ async fn test_user_trying_to_befriend_nonexistent_user() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -140,28 +135,14 @@ async fn test_user_trying_to_befriend_nonexistent_user() {
 }
 
 async fn insert_friendship(username: &str, username2: &str, accepted: bool, rejected: bool, db: &PgPool) {
-    let user_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&username)
-        .fetch_optional(db)
-        .await;
-    let Ok(Some(user)) = user_db else {
-        panic!("No such user!");
-    };
-    let friend_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&username2)
-        .fetch_optional(db)
-        .await;
-    let Ok(Some(friend)) = friend_db else {
-        panic!("No such user!");
-    };
-
-    _ = sqlx::query("INSERT INTO friendships (user_id, friend_id, accepted, rejected) VALUES ($1, $2, $3, $4)")
-        .bind(&user.id)
-        .bind(&friend.id)
-        .bind(&accepted)
-        .bind(&rejected)
-        .execute(db)
-        .await;
+    let mut friendship = FriendshipFixture::new(user_id(username, db).await, user_id(username2, db).await);
+    if accepted {
+        friendship = friendship.accepted();
+    }
+    if rejected {
+        friendship = friendship.rejected();
+    }
+    friendship.insert(db).await;
 }
 
 
@@ -170,7 +151,6 @@ async fn insert_friendship_request(username: &str, username2: &str, db: &PgPool)
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_request_while_its_already_created() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -191,17 +171,15 @@ async fn test_making_request_while_its_already_created() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::CONFLICT);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
     assert!(content.contains("already created"));
-    clear_friendships(&db).await;
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_request_while_reverse_one_is_already_created() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -222,17 +200,15 @@ async fn test_making_request_while_reverse_one_is_already_created() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::CONFLICT);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
     assert!(content.contains("already created"));
-    clear_friendships(&db).await;
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -257,7 +233,6 @@ async fn test_making_request() {
     let result = sqlx::query("SELECT COUNT(*) FROM friendships")
         .fetch_one(&db)
         .await;
-    clear_friendships(&db).await;
 
     assert!(result.is_ok());
     if let Ok(result) = result {
@@ -269,7 +244,6 @@ async fn test_making_request() {
 // friendship requests view
 
 #[tokio::test]
-#[serial]
 async fn test_getting_friendship_requests_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
@@ -292,7 +266,6 @@ async fn test_getting_friendship_requests_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_if_friendship_requests_endpoint_exists() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -316,7 +289,6 @@ async fn test_if_friendship_requests_endpoint_exists() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_friendship_requests() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -350,7 +322,6 @@ async fn test_getting_friendship_requests() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
 
     let body = to_bytes(response.into_body(), 9000).await;
     assert!(body.is_ok());
@@ -364,7 +335,6 @@ async fn test_getting_friendship_requests() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_pagination_on_friend_requests_view() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -385,7 +355,6 @@ async fn test_pagination_on_friend_requests_view() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     let body = to_bytes(response.into_body(), 20000).await;
@@ -403,7 +372,6 @@ async fn test_pagination_on_friend_requests_view() {
 // friends view
 
 #[tokio::test]
-#[serial]
 async fn test_getting_friends_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
@@ -426,7 +394,6 @@ async fn test_getting_friends_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_if_friends_endpoint_exists() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -450,7 +417,6 @@ async fn test_if_friends_endpoint_exists() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_friends() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -484,7 +450,6 @@ async fn test_getting_friends() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
 
     let body = to_bytes(response.into_body(), 9000).await;
     assert!(body.is_ok());
@@ -498,7 +463,6 @@ async fn test_getting_friends() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_pagination_on_friend_view() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -519,7 +483,6 @@ async fn test_pagination_on_friend_view() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     let body = to_bytes(response.into_body(), 20000).await;
@@ -537,13 +500,13 @@ async fn test_pagination_on_friend_view() {
 // changing request's state
 
 #[tokio::test]
-#[serial]
 async fn test_accepting_request_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("PUT")
+            .header("HX-Request", "true")
             .uri("/friends/requests/1")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(Body::from("state=accepted"))
@@ -561,7 +524,6 @@ async fn test_accepting_request_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_changing_request_state_without_specyfying_state() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -588,7 +550,6 @@ async fn test_changing_request_state_without_specyfying_state() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_changing_request_state_with_wrong_state() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -614,7 +575,6 @@ async fn test_changing_request_state_with_wrong_state() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_accepting_nonexistent_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -640,7 +600,6 @@ async fn test_accepting_nonexistent_request() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_accepting_users_own_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -680,12 +639,10 @@ async fn test_accepting_users_own_request() {
         panic!("No request in db!");
     };
 
-    clear_friendships(&db).await;
     assert!(!request_updated.accepted);
 }
 
 #[tokio::test]
-#[serial]
 async fn test_accepting_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -724,13 +681,11 @@ async fn test_accepting_request() {
         panic!("No request in db!");
     };
 
-    clear_friendships(&db).await;
     assert!(request_updated.accepted);
     assert!(!request_updated.rejected);
 }
 
 #[tokio::test]
-#[serial]
 async fn test_rejecting_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -769,7 +724,6 @@ async fn test_rejecting_request() {
         panic!("No request in db!");
     };
 
-    clear_friendships(&db).await;
     assert!(!request_updated.accepted);
     assert!(request_updated.rejected);
 }
@@ -777,10 +731,10 @@ async fn test_rejecting_request() {
 // friendship state on the profile page
 
 #[tokio::test]
-#[serial]
 async fn test_if_friendship_button_is_present() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
+    insert_default_user(false, &db).await;
     insert_new_user("User", "user1@mail.com", &db).await;
 
     let response = prepare_server_with_db(db.clone())
@@ -805,7 +759,6 @@ async fn test_if_friendship_button_is_present() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_friendship_button_if_there_is_new_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -827,7 +780,6 @@ async fn test_friendship_button_if_there_is_new_request() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
     let body = to_bytes(response.into_body(), 2000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -836,7 +788,6 @@ async fn test_friendship_button_if_there_is_new_request() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_friendship_button_if_there_is_accepted_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -858,7 +809,6 @@ async fn test_friendship_button_if_there_is_accepted_request() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
     let body = to_bytes(response.into_body(), 2000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -867,7 +817,6 @@ async fn test_friendship_button_if_there_is_accepted_request() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_friendship_button_if_there_is_rejected_request() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -889,7 +838,6 @@ async fn test_friendship_button_if_there_is_rejected_request() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
     let body = to_bytes(response.into_body(), 2000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -898,7 +846,6 @@ async fn test_friendship_button_if_there_is_rejected_request() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_friendship_button_presence_while_visiting_self_profile() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -917,7 +864,6 @@ async fn test_friendship_button_presence_while_visiting_self_profile() {
         .await
         .unwrap();
 
-    clear_friendships(&db).await;
     let body = to_bytes(response.into_body(), 2000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -0,0 +1,113 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode, response::Response};
+use tower::ServiceExt;
+
+use crate::test::{prepare_server, prepare_server_with_user, prepare_server_with_config, prepare_db, test_config};
+
+async fn get(uri: &str) -> Response {
+    prepare_server()
+        .await
+        .oneshot(
+            Request::builder()
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap()
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> &'a str {
+    response.headers().get(name).unwrap().to_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_pages_have_security_headers() {
+    let response = get("/").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "X-Content-Type-Options"), "nosniff");
+    assert_eq!(header(&response, "X-Frame-Options"), "DENY");
+    assert_eq!(header(&response, "Referrer-Policy"), "same-origin");
+    assert_eq!(header(&response, "Strict-Transport-Security"), "max-age=31536000; includeSubDomains");
+    assert!(header(&response, "Content-Security-Policy").contains("frame-ancestors 'none'"));
+}
+
+#[tokio::test]
+async fn test_script_nonce_matches_policy() {
+    let response = get("/").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let policy = String::from(header(&response, "Content-Security-Policy"));
+    let start = policy.find("'nonce-").unwrap() + "'nonce-".len();
+    let nonce = &policy[start..start + 32];
+    let body = to_bytes(response.into_body(), 5000).await.unwrap();
+    let content = std::str::from_utf8(&*body).unwrap();
+    assert!(content.contains(&format!("<script nonce=\"{}\" src=\"/assets/js/htmx.min.js\">", nonce)));
+    assert!(content.contains(&format!("\"inlineScriptNonce\": \"{}\"", nonce)));
+    assert!(content.contains("\"includeIndicatorStyles\": false"));
+}
+
+#[tokio::test]
+async fn test_nonce_changes_with_every_request() {
+    let first = get("/").await;
+    let second = get("/").await;
+    assert_ne!(header(&first, "Content-Security-Policy"), header(&second, "Content-Security-Policy"));
+}
+
+#[tokio::test]
+async fn test_assets_have_security_headers() {
+    let response = get("/assets/js/htmx.min.js").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "X-Content-Type-Options"), "nosniff");
+}
+
+#[tokio::test]
+async fn test_disabling_security_headers() {
+    let mut config = test_config();
+    config.headers.enabled = false;
+    let response = prepare_server_with_config(prepare_db().await, config)
+        .await
+        .oneshot(
+            Request::builder()
+            .uri("/")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("Content-Security-Policy").is_none());
+    assert!(response.headers().get("X-Frame-Options").is_none());
+}
+
+#[tokio::test]
+async fn test_session_cookies_are_hardened() {
+    let response = prepare_server_with_user(true)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookies: Vec<&str> = response.headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|header| header.to_str().unwrap())
+        .collect();
+    assert_eq!(cookies.len(), 2);
+    for cookie in cookies {
+        assert!(cookie.contains("HttpOnly"));
+        assert!(cookie.contains("Secure"));
+        assert!(cookie.contains("Path=/"));
+        assert!(cookie.contains("SameSite=Lax"));
+    }
+}
@@ -0,0 +1,155 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_config, get_token, test_config, fake_s3::{FakeS3, BUCKET}, fixtures::{UserFixture, png}}, config::{MediaBackend, S3Config}, media::{MediaStore, MediaError, LocalMediaStore, S3MediaStore}};
+
+/// Fetches the URL like a browser would, without signing anything, and returns
+/// the status code and body.
+async fn fetch(url: &str) -> (u16, Vec<u8>) {
+    let address = url.strip_prefix("http://").unwrap();
+    let (host, path) = address.split_at(address.find('/').unwrap());
+    let mut stream = tokio::net::TcpStream::connect(host).await.unwrap();
+    stream.write_all(format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host).as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let split = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let status = head[9..12].parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+#[tokio::test]
+async fn test_local_store() {
+    let dir = std::env::temp_dir().join(format!("rustspace-test-local-media-{}", std::process::id()));
+    let store = LocalMediaStore::new(dir.clone());
+
+    store.put("avatars/1/abc-64.png", b"image".to_vec(), "image/png").await.unwrap();
+    assert_eq!(store.get("avatars/1/abc-64.png").await.unwrap(), Some(b"image".to_vec()));
+    assert!(dir.join("avatars/1/abc-64.png").exists());
+    assert_eq!(store.url("avatars/1/abc-64.png"), "/media/avatars/1/abc-64.png");
+
+    store.delete("avatars/1/abc-64.png").await.unwrap();
+    store.delete("avatars/1/abc-64.png").await.unwrap();
+    assert_eq!(store.get("avatars/1/abc-64.png").await.unwrap(), None);
+    _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn test_s3_store() {
+    let s3 = FakeS3::start().await;
+    let store = S3MediaStore::new(s3.config()).unwrap();
+
+    store.put("avatars/1/abc-64.png", b"image".to_vec(), "image/png").await.unwrap();
+    assert_eq!(s3.object("avatars/1/abc-64.png"), Some(b"image".to_vec()));
+    assert_eq!(store.get("avatars/1/abc-64.png").await.unwrap(), Some(b"image".to_vec()));
+
+    store.delete("avatars/1/abc-64.png").await.unwrap();
+    assert!(s3.keys().is_empty());
+    assert_eq!(store.get("avatars/1/abc-64.png").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_s3_store_with_wrong_secret() {
+    let s3 = FakeS3::start().await;
+    let mut config = s3.config();
+    config.secret_key = String::from("wrong");
+    let store = S3MediaStore::new(config).unwrap();
+
+    let result = store.put("avatars/1/abc-64.png", b"image".to_vec(), "image/png").await;
+
+    assert!(matches!(result, Err(MediaError::Status(403))));
+    assert!(s3.keys().is_empty());
+}
+
+#[tokio::test]
+async fn test_s3_store_gives_up_on_silent_service() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    // accepts connections and never answers
+    tokio::spawn(async move {
+        let mut connections = vec![];
+        while let Ok((stream, _)) = listener.accept().await {
+            connections.push(stream);
+        }
+    });
+    let s3 = FakeS3::start().await;
+    let config = S3Config { endpoint, timeout: 1, ..s3.config() };
+    let store = S3MediaStore::new(config).unwrap();
+
+    let result = store.put("avatars/1/abc-64.png", b"image".to_vec(), "image/png").await;
+
+    assert!(matches!(result, Err(MediaError::Timeout)));
+}
+
+#[tokio::test]
+async fn test_s3_presigned_url() {
+    let s3 = FakeS3::start().await;
+    let store = S3MediaStore::new(s3.config()).unwrap();
+    store.put("avatars/1/abc-64.png", b"image".to_vec(), "image/png").await.unwrap();
+
+    let url = store.url("avatars/1/abc-64.png");
+    assert!(url.starts_with(&format!("{}/{}/avatars/1/abc-64.png?", s3.endpoint, BUCKET)));
+    assert_eq!(fetch(&url).await, (200, b"image".to_vec()));
+
+    let (status, _) = fetch(&url.replace("abc-64", "abc-32")).await;
+    assert_eq!(status, 403);
+    let (status, _) = fetch(&format!("{}/{}/avatars/1/abc-64.png", s3.endpoint, BUCKET)).await;
+    assert_eq!(status, 403);
+}
+
+#[tokio::test]
+async fn test_uploading_avatar_to_s3() {
+    let s3 = FakeS3::start().await;
+    let db = prepare_db().await;
+    UserFixture::new("Test").insert(&db).await;
+    let mut config = test_config();
+    config.media.backend = MediaBackend::S3;
+    config.media.s3 = s3.config();
+    let app = prepare_server_with_config(db, config).await;
+
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let mut body = Vec::new();
+    body.extend(b"--boundary\r\nContent-Disposition: form-data; name=\"image\"; filename=\"avatar.png\"\r\nContent-Type: image/png\r\n\r\n");
+    body.extend(png(100, 100));
+    body.extend(b"\r\n--boundary--\r\n");
+    let response = app.clone()
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "multipart/form-data; boundary=boundary")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/avatar")
+            .body(Body::from(body))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(s3.keys().len(), 3);
+    let csp = response.headers().get("Content-Security-Policy").unwrap().to_str().unwrap().to_string();
+    assert!(csp.contains(&s3.endpoint));
+    let bytes = to_bytes(response.into_body(), 100000).await.unwrap();
+    let content = std::str::from_utf8(&bytes).unwrap();
+    let start = content.find(&s3.endpoint).unwrap();
+    let url = content[start..content[start..].find('"').unwrap() + start].replace("&amp;", "&");
+    assert!(url.contains("-256.webp?"));
+    let (status, data) = fetch(&url).await;
+    assert_eq!(status, 200);
+    assert_eq!(image::load_from_memory(&data).unwrap().width(), 256);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+            .method("DELETE")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/avatar")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(s3.keys().is_empty());
+}
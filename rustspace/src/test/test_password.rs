@@ -0,0 +1,245 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::test::{prepare_db, prepare_server_with_db, insert_default_user, clear_mail, read_mail, token_from_mail};
+
+async fn request_reset(db: &PgPool) -> String {
+    clear_mail(&db);
+    _ = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/password/forgot")
+            .body(Body::from("email=test%40email.com"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+    let Some(mail) = read_mail(&db) else {
+        panic!("No mail sent!");
+    };
+    token_from_mail(&mail)
+}
+
+#[tokio::test]
+async fn test_getting_forgot_password_form() {
+    let db = prepare_db().await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .uri("/password/forgot")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 5000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Forgot password"));
+}
+
+#[tokio::test]
+async fn test_requesting_reset_sends_mail() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    clear_mail(&db);
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/password/forgot")
+            .body(Body::from("email=test%40email.com"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("we have sent"));
+
+    let mail = read_mail(&db);
+    assert!(mail.is_some());
+    let mail = mail.unwrap();
+    assert!(mail.contains("To: test@email.com"));
+    assert!(mail.contains("http://localhost:3000/password/reset?token="));
+
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM password_resets WHERE token_hash = $1")
+        .bind(crate::security::hash_token(&token_from_mail(&mail)))
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(stored, 1);
+}
+
+#[tokio::test]
+async fn test_requesting_reset_for_unknown_email() {
+    let db = prepare_db().await;
+    clear_mail(&db);
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/password/forgot")
+            .body(Body::from("email=nobody%40email.com"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("we have sent"));
+    assert!(read_mail(&db).is_none());
+}
+
+#[tokio::test]
+async fn test_getting_reset_form() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let token = request_reset(&db).await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .uri(format!("/password/reset?token={}", token))
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 5000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains(&token));
+    assert!(!content.contains("has expired"));
+}
+
+#[tokio::test]
+async fn test_getting_reset_form_with_expired_token() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let token = request_reset(&db).await;
+    _ = sqlx::query("UPDATE password_resets SET expires_at = now() - interval '1 minute'")
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .uri(format!("/password/reset?token={}", token))
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 5000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("has expired"));
+}
+
+#[tokio::test]
+async fn test_resetting_password() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let token = request_reset(&db).await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/password/reset")
+            .body(Body::from(format!("token={}&psw=new_password&psw_repeat=new_password", token)))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/login");
+    }
+
+    let active: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE revoked_at IS NULL")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(active, 0);
+
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=new_password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/user");
+    }
+}
+
+#[tokio::test]
+async fn test_reset_token_cannot_be_reused() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let token = request_reset(&db).await;
+    _ = sqlx::query("UPDATE password_resets SET used_at = now()")
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/password/reset")
+            .body(Body::from(format!("token={}&psw=new_password&psw_repeat=new_password", token)))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(response.headers().get("HX-redirect").is_none());
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("invalid or has expired"));
+}
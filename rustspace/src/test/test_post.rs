@@ -1,20 +1,19 @@
 use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
 use sqlx::{Row, PgPool, Postgres};
 use tower::ServiceExt;
-use serial_test::serial;
 
-use crate::{test::{prepare_server_with_user, prepare_db, prepare_server_with_db, insert_new_user, clear_posts, clear_comments}, security::get_token, UserModel, BlogPostModel};
+use crate::{test::{fixtures::{PostFixture, CommentFixture, user_id}, prepare_server_with_user, prepare_db, prepare_server_with_db, insert_new_user, get_token}, BlogPostModel};
 
 // adding post
 
 #[tokio::test]
-#[serial]
 async fn test_making_post_request_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("POST")
+            .header("HX-Request", "true")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/blog")
             .body(Body::from("title=title&content=content"))
@@ -32,7 +31,6 @@ async fn test_making_post_request_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_post_request_with_no_title() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -49,7 +47,7 @@ async fn test_making_post_request_with_no_title() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -60,7 +58,6 @@ async fn test_making_post_request_with_no_title() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_post_request_with_empty_title() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -77,7 +74,7 @@ async fn test_making_post_request_with_empty_title() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -88,7 +85,6 @@ async fn test_making_post_request_with_empty_title() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_post_request_with_no_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -105,7 +101,7 @@ async fn test_making_post_request_with_no_content() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -116,7 +112,6 @@ async fn test_making_post_request_with_no_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_making_post_request_with_empty_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -133,7 +128,7 @@ async fn test_making_post_request_with_empty_content() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -144,7 +139,6 @@ async fn test_making_post_request_with_empty_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_adding_post() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -166,7 +160,6 @@ async fn test_adding_post() {
     let result = sqlx::query("SELECT COUNT(*) FROM posts")
         .fetch_one(&db)
         .await;
-    clear_posts(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     assert!(result.is_ok());
@@ -178,32 +171,21 @@ async fn test_adding_post() {
 // deleting post
 
 async fn insert_post(username: &str, title: &str, content: &str, db: &PgPool) -> i32 {
-    let user_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&username)
-        .fetch_optional(db)
-        .await;
-    let Ok(Some(user)) = user_db else {
-        panic!("No such user!");
-    };
-
-    let result = sqlx::query_scalar("INSERT INTO posts (user_id, title, content) VALUES ($1, $2, $3) RETURNING id")
-        .bind(&user.id)
-        .bind(title)
-        .bind(content)
-        .fetch_one(db)
-        .await;
-
-    result.unwrap()
+    PostFixture::new(user_id(username, db).await)
+        .title(title)
+        .content(content)
+        .insert(db)
+        .await
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_post_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("DELETE")
+            .header("HX-Request", "true")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/blog/1")
             .body(Body::empty())
@@ -221,7 +203,6 @@ async fn test_deleting_post_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_nonexistent_post() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -238,7 +219,7 @@ async fn test_deleting_nonexistent_post() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -248,7 +229,6 @@ async fn test_deleting_nonexistent_post() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_post_authored_by_different_user() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -268,9 +248,8 @@ async fn test_deleting_post_authored_by_different_user() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -280,7 +259,6 @@ async fn test_deleting_post_authored_by_different_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_post() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -303,7 +281,6 @@ async fn test_deleting_post() {
     let result = sqlx::query("SELECT COUNT(*) FROM posts")
         .fetch_one(&db)
         .await;
-    clear_posts(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     let header = response.headers().get("HX-redirect");
@@ -318,24 +295,13 @@ async fn test_deleting_post() {
 }
 
 async fn insert_comment(username: &str, post_id: &i32, content: &str, db: &PgPool) {
-    let user_db = sqlx::query_as::<Postgres, UserModel>("SELECT * FROM users WHERE screen_name = $1")
-        .bind(&username)
-        .fetch_optional(db)
-        .await;
-    let Ok(Some(user)) = user_db else {
-        panic!("No such user!");
-    };
-
-    _ = sqlx::query("INSERT INTO comments (user_id, post_id, content) VALUES ($1, $2, $3)")
-        .bind(&user.id)
-        .bind(post_id)
-        .bind(content)
-        .execute(db)
+    CommentFixture::new(user_id(username, db).await, *post_id)
+        .content(content)
+        .insert(db)
         .await;
 }
 
 #[tokio::test]
-#[serial]
 async fn test_deleting_post_with_comments() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -361,8 +327,6 @@ async fn test_deleting_post_with_comments() {
     let result = sqlx::query("SELECT COUNT(*) FROM posts")
         .fetch_one(&db)
         .await;
-    clear_posts(&db).await;
-    clear_comments(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     let header = response.headers().get("HX-redirect");
@@ -379,13 +343,13 @@ async fn test_deleting_post_with_comments() {
 // editing post
 
 #[tokio::test]
-#[serial]
 async fn test_editing_post_by_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
             .method("PUT")
+            .header("HX-Request", "true")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/blog/1")
             .body(Body::from("title=title&content=content"))
@@ -403,7 +367,6 @@ async fn test_editing_post_by_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_nonexistent_post() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -420,7 +383,7 @@ async fn test_editing_nonexistent_post() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -430,7 +393,6 @@ async fn test_editing_nonexistent_post() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_post_authored_by_different_user() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -450,9 +412,8 @@ async fn test_editing_post_authored_by_different_user() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -462,7 +423,6 @@ async fn test_editing_post_authored_by_different_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_post_with_no_title() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -481,9 +441,8 @@ async fn test_editing_post_with_no_title() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -494,7 +453,6 @@ async fn test_editing_post_with_no_title() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_post_with_empty_title() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -514,8 +472,7 @@ async fn test_editing_post_with_empty_title() {
         .await
         .unwrap();
 
-    clear_posts(&db).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -526,7 +483,6 @@ async fn test_editing_post_with_empty_title() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_post_with_no_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -546,8 +502,7 @@ async fn test_editing_post_with_no_content() {
         .await
         .unwrap();
 
-    clear_posts(&db).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -558,7 +513,6 @@ async fn test_editing_post_with_no_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_post_with_empty_content() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -578,8 +532,7 @@ async fn test_editing_post_with_empty_content() {
         .await
         .unwrap();
 
-    clear_posts(&db).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -590,7 +543,6 @@ async fn test_editing_post_with_empty_content() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_editing_post() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let db = prepare_db().await;
@@ -615,15 +567,14 @@ async fn test_editing_post() {
         .bind(&post_id)
         .fetch_optional(&db)
         .await;
-    clear_posts(&db).await;
 
     assert_eq!(response.status(), StatusCode::OK);
     assert!(post.is_ok());
     if let Ok(post) = post {
         assert!(post.is_some());
         if let Some(post) = post {
-            assert_eq!(post.content.unwrap(), "new_content");
-            assert_eq!(post.title.unwrap(), "new");
+            assert_eq!(post.content, "new_content");
+            assert_eq!(post.title, "new");
         }
     }
 }
@@ -631,7 +582,6 @@ async fn test_editing_post() {
 // getting post
 
 #[tokio::test]
-#[serial]
 async fn test_getting_nonexistent_post() {
     let response = prepare_server_with_user(false)
         .await
@@ -649,11 +599,10 @@ async fn test_getting_nonexistent_post() {
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
-    assert!(content.contains("no such post"));
+    assert!(content.contains("No such post"));
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_post() {
     let db = prepare_db().await;
     insert_new_user("Test", "test@mail.com", &db).await;
@@ -669,9 +618,8 @@ async fn test_getting_post() {
             )
         .await
         .unwrap();
-    clear_posts(&db).await;
 
-    let body = to_bytes(response.into_body(), 1000).await;
+    let body = to_bytes(response.into_body(), 5000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
     let content = std::str::from_utf8(&*bytes).unwrap();
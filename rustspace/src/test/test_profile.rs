@@ -1,12 +1,10 @@
 use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
 use sqlx::{PgPool, Postgres};
 use tower::ServiceExt;
-use serial_test::serial;
 
-use crate::{test::{prepare_server, prepare_server_with_user, prepare_server_with_db, prepare_db, insert_default_user, clear_profiles}, security::get_token, UserModel, ProfileModel};
+use crate::{test::{prepare_server, prepare_server_with_user, prepare_server_with_db, prepare_db, insert_default_user, get_token}, UserModel, ProfileModel};
 
 #[tokio::test]
-#[serial]
 async fn test_getting_nonexistent_profile() {
     let response = prepare_server()
         .await
@@ -19,7 +17,7 @@ async fn test_getting_nonexistent_profile() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -28,7 +26,6 @@ async fn test_getting_nonexistent_profile() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_profile() {
     let response = prepare_server_with_user(false)
         .await
@@ -50,7 +47,6 @@ async fn test_getting_profile() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_profile_by_owner() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -75,7 +71,6 @@ async fn test_getting_profile_by_owner() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_profile_form() {
     let (token, _) = get_token(&Some(String::from("Test")));
     let response = prepare_server_with_user(false)
@@ -100,12 +95,12 @@ async fn test_getting_profile_form() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_getting_profile_form_for_unauthenticated_user() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
+            .header("HX-Request", "true")
             .uri("/forms/profile")
             .body(Body::empty())
             .unwrap()
@@ -113,7 +108,7 @@ async fn test_getting_profile_form_for_unauthenticated_user() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -123,12 +118,12 @@ async fn test_getting_profile_form_for_unauthenticated_user() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_changing_profile_while_unauthenticated() {
     let response = prepare_server_with_user(false)
         .await
         .oneshot(
             Request::builder()
+            .header("HX-Request", "true")
             .method("PUT")
             .header("Content-Type", "application/x-www-form-urlencoded")
             .uri("/profile")
@@ -138,7 +133,7 @@ async fn test_changing_profile_while_unauthenticated() {
         .await
         .unwrap();
     
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
     let bytes = body.unwrap();
@@ -148,7 +143,6 @@ async fn test_changing_profile_while_unauthenticated() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_changing_profile_without_profile() {
     let db = prepare_db().await;
     insert_default_user(false, &db).await;
@@ -167,7 +161,6 @@ async fn test_changing_profile_without_profile() {
         .await
         .unwrap();
     
-    clear_profiles(&db).await;
     assert_eq!(response.status(), StatusCode::OK);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
@@ -216,7 +209,6 @@ async fn get_profile(db: &PgPool) -> Option<ProfileModel> {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_changing_profile_with_existing_profile() {
     let db = prepare_db().await;
     insert_default_user(false, &db).await;
@@ -236,7 +228,6 @@ async fn test_changing_profile_with_existing_profile() {
         .await
         .unwrap();
     
-    clear_profiles(&db).await;
     assert_eq!(response.status(), StatusCode::OK);
     let body = to_bytes(response.into_body(), 1000).await;
     assert!(body.is_ok());
@@ -246,7 +237,6 @@ async fn test_changing_profile_with_existing_profile() {
 }
 
 #[tokio::test]
-#[serial]
 async fn test_changing_profile_in_db() {
     let db = prepare_db().await;
     insert_default_user(false, &db).await;
@@ -266,7 +256,6 @@ async fn test_changing_profile_in_db() {
         .await
         .unwrap();
     let profile = get_profile(&db).await;
-    clear_profiles(&db).await;
     
     assert!(profile.is_some());
     let profile = profile.unwrap();
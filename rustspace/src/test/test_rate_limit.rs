@@ -0,0 +1,82 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode, Router};
+use tower::ServiceExt;
+
+use crate::test::{prepare_db, prepare_server_with_config, test_config, insert_default_user};
+
+async fn login(app: &Router, password: &str) -> (bool, String) {
+    let response = app.clone()
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from(format!("username=Test&psw={}", password)))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+    let logged_in = response.headers().get("HX-redirect").is_some();
+    let body = to_bytes(response.into_body(), 1000).await.unwrap();
+    (logged_in, String::from(std::str::from_utf8(&*body).unwrap()))
+}
+
+#[tokio::test]
+async fn test_account_is_locked_after_failed_logins() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let mut config = test_config();
+    config.rate_limit.max_failures = 3;
+    let app = prepare_server_with_config(db, config).await;
+
+    for _ in 0..3 {
+        let (logged_in, content) = login(&app, "wrong").await;
+        assert!(!logged_in);
+        assert!(content.contains("Invalid username or password"));
+    }
+    let (logged_in, content) = login(&app, "password").await;
+    assert!(!logged_in);
+    assert!(content.contains("Too many attempts"));
+}
+
+#[tokio::test]
+async fn test_successful_login_resets_failures() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let mut config = test_config();
+    config.rate_limit.max_failures = 2;
+    let app = prepare_server_with_config(db, config).await;
+
+    _ = login(&app, "wrong").await;
+    let (logged_in, _) = login(&app, "password").await;
+    assert!(logged_in);
+    _ = login(&app, "wrong").await;
+    let (logged_in, _) = login(&app, "password").await;
+    assert!(logged_in);
+}
+
+#[tokio::test]
+async fn test_requests_are_limited_per_address() {
+    let db = prepare_db().await;
+    let mut config = test_config();
+    config.rate_limit.ip_burst = 2;
+    config.rate_limit.ip_per_minute = 1;
+    let app = prepare_server_with_config(db, config).await;
+
+    for i in 0..3 {
+        let response = app.clone()
+            .oneshot(
+                Request::builder()
+                .method("POST")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .uri("/validation/username")
+                .body(Body::from("username=User"))
+                .unwrap()
+                )
+            .await
+            .unwrap();
+
+        let expected = if i == 2 { StatusCode::TOO_MANY_REQUESTS } else { StatusCode::OK };
+        assert_eq!(response.status(), expected);
+        assert_eq!(response.headers().get("Retry-After").is_some(), i == 2);
+    }
+}
@@ -0,0 +1,136 @@
+use sqlx::PgPool;
+
+use crate::{test::{prepare_db, fixtures::{UserFixture, FriendshipFixture, PostFixture, CommentFixture, AlbumFixture, insert_photo}}, storage::{Storage, ProfileFields}, error::{AppError, Conflict}};
+
+async fn count(table: &str, db: &PgPool) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_deleting_user_removes_their_data() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let other_id = UserFixture::new("User").insert(&db).await;
+    let post_id = PostFixture::new(user_id).insert(&db).await;
+    let other_post_id = PostFixture::new(other_id).insert(&db).await;
+    CommentFixture::new(other_id, post_id).insert(&db).await;
+    CommentFixture::new(user_id, other_post_id).insert(&db).await;
+    FriendshipFixture::new(other_id, user_id).accepted().insert(&db).await;
+    sqlx::query("INSERT INTO profiles (user_id, city) VALUES ($1, 'City')")
+        .bind(user_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(user_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    assert_eq!(count("posts", &db).await, 1);
+    assert_eq!(count("comments", &db).await, 0);
+    assert_eq!(count("friendships", &db).await, 0);
+    assert_eq!(count("profiles", &db).await, 0);
+    assert_eq!(count("sessions", &db).await, 1);
+}
+
+#[tokio::test]
+async fn test_deleting_post_removes_its_comments() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let post_id = PostFixture::new(user_id).insert(&db).await;
+    let other_post_id = PostFixture::new(user_id).insert(&db).await;
+    CommentFixture::new(user_id, post_id).insert(&db).await;
+    CommentFixture::new(user_id, other_post_id).insert(&db).await;
+
+    Storage::postgres(db.clone()).posts.delete(post_id).await.unwrap();
+
+    assert_eq!(count("posts", &db).await, 1);
+    assert_eq!(count("comments", &db).await, 1);
+}
+
+#[tokio::test]
+async fn test_deleting_album_removes_its_photos() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    let other_album_id = AlbumFixture::new(user_id).insert(&db).await;
+    insert_photo(album_id, "photos/1/first.webp", &db).await;
+    insert_photo(other_album_id, "photos/1/second.webp", &db).await;
+
+    Storage::postgres(db.clone()).albums.delete(album_id).await.unwrap();
+
+    assert_eq!(count("albums", &db).await, 1);
+    assert_eq!(count("photos", &db).await, 1);
+}
+
+#[tokio::test]
+async fn test_deleting_cover_photo_clears_cover() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let album_id = AlbumFixture::new(user_id).insert(&db).await;
+    let photo_id = insert_photo(album_id, "photos/1/first.webp", &db).await;
+    let storage = Storage::postgres(db.clone());
+    storage.albums.set_cover(album_id, Some(photo_id)).await.unwrap();
+
+    storage.albums.delete_photo(photo_id).await.unwrap();
+
+    let album = storage.albums.find(album_id).await.unwrap().unwrap();
+    assert_eq!(album.cover_id, None);
+}
+
+#[tokio::test]
+async fn test_friendship_is_unique_in_both_directions() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let other_id = UserFixture::new("User").insert(&db).await;
+    FriendshipFixture::new(user_id, other_id).insert(&db).await;
+
+    let storage = Storage::postgres(db.clone());
+    let result = storage.friendships.create(user_id, other_id).await;
+    assert!(matches!(result, Err(AppError::Conflict(Conflict::FriendRequestPending))));
+    let result = storage.friendships.create(other_id, user_id).await;
+    assert!(matches!(result, Err(AppError::Conflict(Conflict::FriendRequestPending))));
+    assert_eq!(count("friendships", &db).await, 1);
+}
+
+#[tokio::test]
+async fn test_saving_profile_twice_keeps_one_row() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let storage = Storage::postgres(db.clone());
+    let fields = |city: &str| ProfileFields {
+        gender: None,
+        city: Some(String::from(city)),
+        description: None,
+        real_name: None,
+    };
+
+    storage.profiles.save(user_id, &fields("First")).await.unwrap();
+    storage.profiles.save(user_id, &fields("Second")).await.unwrap();
+
+    assert_eq!(count("profiles", &db).await, 1);
+    let profile = storage.profiles.find_by_user(user_id).await.unwrap().unwrap();
+    assert_eq!(profile.city.as_deref(), Some("Second"));
+}
+
+#[tokio::test]
+async fn test_post_requires_title_and_content() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let result = sqlx::query("INSERT INTO posts (user_id, content) VALUES ($1, 'Content')")
+        .bind(user_id)
+        .execute(&db)
+        .await;
+    assert!(result.is_err());
+    let result = sqlx::query("INSERT INTO posts (user_id, title) VALUES ($1, 'Title')")
+        .bind(user_id)
+        .execute(&db)
+        .await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,450 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode, response::Response};
+use sqlx::{PgPool, Postgres};
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_db, insert_default_user, insert_new_user, get_token, test_token_id}, SessionModel};
+
+async fn get_session(token_id: &str, db: &PgPool) -> SessionModel {
+    let session = sqlx::query_as::<Postgres, SessionModel>("SELECT * FROM sessions WHERE token_id = $1")
+        .bind(token_id)
+        .fetch_optional(db)
+        .await;
+    let Ok(Some(session)) = session else {
+        panic!("No such session!");
+    };
+    session
+}
+
+fn get_cookie(response: &Response, name: &str) -> Option<String> {
+    response.headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .filter_map(|cookie| cookie.split(';').next())
+        .filter_map(|cookie| cookie.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| String::from(value))
+}
+
+async fn log_in(db: &PgPool) -> String {
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+    let Some(refresh) = get_cookie(&response, "Refresh") else {
+        panic!("No refresh token!");
+    };
+    refresh
+}
+
+#[tokio::test]
+async fn test_logging_in_creates_session() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    _ = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("User-Agent", "TestBrowser")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    let session = sqlx::query_as::<Postgres, SessionModel>("SELECT * FROM sessions WHERE user_agent = $1")
+        .bind("TestBrowser")
+        .fetch_optional(&db)
+        .await;
+    assert!(session.is_ok());
+    let session = session.unwrap();
+    assert!(session.is_some());
+    assert!(session.unwrap().revoked_at.is_none());
+}
+
+#[tokio::test]
+async fn test_revoked_session_is_unauthenticated() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    _ = sqlx::query("UPDATE sessions SET revoked_at = now()")
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Token={};", token))
+            .uri("/user")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Unauthorized"));
+}
+
+#[tokio::test]
+async fn test_expired_session_is_unauthenticated() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    _ = sqlx::query("UPDATE sessions SET expires_at = now() - interval '1 minute'")
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Token={};", token))
+            .uri("/user")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Unauthorized"));
+}
+
+#[tokio::test]
+async fn test_logging_out_revokes_session() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    _ = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Token={};", token))
+            .uri("/logout")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    let session = get_session(&test_token_id("Test"), &db).await;
+    assert!(session.revoked_at.is_some());
+}
+
+#[tokio::test]
+async fn test_getting_sessions_page() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Token={};", token))
+            .uri("/user/sessions")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 5000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Active sessions"));
+    assert!(content.contains("this device"));
+}
+
+#[tokio::test]
+async fn test_getting_sessions_page_while_unauthenticated() {
+    let db = prepare_db().await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .uri("/user/sessions")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Unauthorized"));
+}
+
+#[tokio::test]
+async fn test_signing_out_other_session() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    let session_id: i32 = sqlx::query_scalar("INSERT INTO sessions (token_id, user_id, expires_at)
+                    SELECT 'other', id, now() + interval '1 hour' FROM users WHERE screen_name = 'Test' RETURNING id")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("DELETE")
+            .header("Cookie", format!("Token={};", token))
+            .uri(format!("/user/sessions/{}", session_id))
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 1000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains(&format!("session-{}", session_id)));
+
+    let other = get_session("other", &db).await;
+    assert!(other.revoked_at.is_some());
+    let current = get_session(&test_token_id("Test"), &db).await;
+    assert!(current.revoked_at.is_none());
+}
+
+#[tokio::test]
+async fn test_signing_out_session_of_other_user() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    insert_new_user("Other", "other@mail.com", &db).await;
+    let other = get_session(&test_token_id("Other"), &db).await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("DELETE")
+            .header("Cookie", format!("Token={};", token))
+            .uri(format!("/user/sessions/{}", other.id))
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = to_bytes(response.into_body(), 1000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("No such session"));
+
+    let other = get_session(&test_token_id("Other"), &db).await;
+    assert!(other.revoked_at.is_none());
+}
+
+#[tokio::test]
+async fn test_signing_out_everywhere() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(false, &db).await;
+    _ = sqlx::query("INSERT INTO sessions (token_id, user_id, expires_at)
+                    SELECT 'other', id, now() + interval '1 hour' FROM users WHERE screen_name = 'Test'")
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("DELETE")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/user/sessions")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/login");
+    }
+
+    let active: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE revoked_at IS NULL")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(active, 0);
+}
+
+#[tokio::test]
+async fn test_refreshing_expired_token() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let refresh = log_in(&db).await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Token=expired; Refresh={}", refresh))
+            .uri("/user")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = get_cookie(&response, "Token");
+    assert!(token.is_some_and(|token| token != ""));
+    let new_refresh = get_cookie(&response, "Refresh");
+    assert!(new_refresh.is_some_and(|new_refresh| new_refresh != "" && new_refresh != refresh));
+    let body = to_bytes(response.into_body(), 5000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(!content.contains("Unauthorized"));
+    assert!(content.contains("test@email.com"));
+}
+
+#[tokio::test]
+async fn test_refresh_token_is_rotated() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let refresh = log_in(&db).await;
+    _ = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Refresh={}", refresh))
+            .uri("/user")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    let tokens: Vec<bool> = sqlx::query_scalar("SELECT used_at IS NOT NULL FROM refresh_tokens ORDER BY id")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(tokens, vec![true, false]);
+}
+
+#[tokio::test]
+async fn test_reusing_refresh_token_revokes_session() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let refresh = log_in(&db).await;
+    _ = sqlx::query("UPDATE refresh_tokens SET used_at = now() - interval '1 minute'")
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Refresh={}", refresh))
+            .uri("/user")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(get_cookie(&response, "Refresh"), Some(String::from("")));
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Unauthorized"));
+
+    let active: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE revoked_at IS NULL AND token_id NOT LIKE 'test-session-%'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(active, 0);
+}
+
+#[tokio::test]
+async fn test_refresh_token_of_revoked_session_is_rejected() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let refresh = log_in(&db).await;
+    _ = sqlx::query("UPDATE sessions SET revoked_at = now()")
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Refresh={}", refresh))
+            .uri("/user")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(get_cookie(&response, "Token").is_none());
+    let body = to_bytes(response.into_body(), 2000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Unauthorized"));
+}
+
+#[tokio::test]
+async fn test_refreshing_once_for_all_extractors() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let refresh = log_in(&db).await;
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Refresh={}", refresh))
+            .uri("/friends")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let tokens: Vec<bool> = sqlx::query_scalar("SELECT used_at IS NOT NULL FROM refresh_tokens ORDER BY id")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(tokens, vec![true, false]);
+    let revoked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE revoked_at IS NOT NULL")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(revoked, 0);
+}
@@ -0,0 +1,297 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode, response::Response};
+use chrono::DateTime;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{test::{prepare_db, prepare_server_with_clock, insert_default_user, get_token}, clock::FixedClock, totp, security::hash_token};
+
+const NOW: i64 = 1_700_000_000;
+
+fn clock() -> Box<FixedClock> {
+    Box::new(FixedClock(DateTime::from_timestamp(NOW, 0).unwrap()))
+}
+
+fn current_code(secret: &str) -> String {
+    totp::code(&totp::base32_decode(secret).unwrap(), totp::step_at(NOW))
+}
+
+fn get_cookie(response: &Response, name: &str) -> Option<String> {
+    response.headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .filter_map(|cookie| cookie.split(';').next())
+        .filter_map(|cookie| cookie.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| String::from(value))
+}
+
+async fn enable_two_factor(db: &PgPool) -> String {
+    let secret = totp::generate_secret();
+    _ = sqlx::query("UPDATE users SET totp_secret = $1, totp_enabled_at = now() WHERE screen_name = 'Test'")
+        .bind(&secret)
+        .execute(db)
+        .await;
+    secret
+}
+
+async fn start_login(db: &PgPool) -> String {
+    let response = prepare_server_with_clock(db.clone(), clock())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+    let Some(challenge) = get_cookie(&response, "Challenge") else {
+        panic!("No login challenge!");
+    };
+    challenge
+}
+
+async fn send_code(challenge: &str, code: &str, db: &PgPool) -> Response {
+    prepare_server_with_clock(db.clone(), clock())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Challenge={}", challenge))
+            .uri("/login/2fa")
+            .body(Body::from(format!("code={}", code)))
+            .unwrap()
+            )
+        .await
+        .unwrap()
+}
+
+async fn body_content(response: Response) -> String {
+    let body = to_bytes(response.into_body(), 50000).await;
+    assert!(body.is_ok());
+    String::from(std::str::from_utf8(&*body.unwrap()).unwrap())
+}
+
+#[tokio::test]
+async fn test_enabling_two_factor() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let response = prepare_server_with_clock(db.clone(), clock())
+        .await
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Token={};", token))
+            .uri("/forms/2fa")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let content = body_content(response).await;
+    assert!(content.contains("<svg"));
+    assert!(content.contains("otpauth://totp/RustSpace:Test?secret="));
+
+    let secret: String = sqlx::query_scalar("SELECT totp_secret FROM users WHERE screen_name = 'Test'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    let response = prepare_server_with_clock(db.clone(), clock())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/2fa")
+            .body(Body::from(format!("code={}", current_code(&secret))))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let content = body_content(response).await;
+    assert!(content.contains("recovery codes"));
+    assert_eq!(content.matches("<li>").count(), 10);
+
+    let enabled: bool = sqlx::query_scalar("SELECT totp_enabled_at IS NOT NULL FROM users WHERE screen_name = 'Test'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert!(enabled);
+    let codes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM recovery_codes")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(codes, 10);
+}
+
+#[tokio::test]
+async fn test_enabling_two_factor_with_wrong_code() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    _ = sqlx::query("UPDATE users SET totp_secret = $1 WHERE screen_name = 'Test'")
+        .bind(totp::generate_secret())
+        .execute(&db)
+        .await;
+    let response = prepare_server_with_clock(db.clone(), clock())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/2fa")
+            .body(Body::from("code=abcdef"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let content = body_content(response).await;
+    assert!(content.contains("Wrong code"));
+    let enabled: bool = sqlx::query_scalar("SELECT totp_enabled_at IS NOT NULL FROM users WHERE screen_name = 'Test'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert!(!enabled);
+}
+
+#[tokio::test]
+async fn test_login_requires_second_factor() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    enable_two_factor(&db).await;
+    let response = prepare_server_with_clock(db.clone(), clock())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Test&psw=password&redir=/blog/post"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/login/2fa?path=/blog/post");
+    }
+    assert!(get_cookie(&response, "Challenge").is_some());
+    assert!(get_cookie(&response, "Token").is_none());
+}
+
+#[tokio::test]
+async fn test_login_with_totp_code() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let secret = enable_two_factor(&db).await;
+    let challenge = start_login(&db).await;
+    let response = send_code(&challenge, &current_code(&secret), &db).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers().get("HX-redirect");
+    assert!(header.is_some());
+    if let Some(header) = header {
+        assert_eq!(header.to_str().unwrap(), "/user");
+    }
+    assert!(get_cookie(&response, "Token").is_some_and(|token| token != ""));
+    assert_eq!(get_cookie(&response, "Challenge"), Some(String::from("")));
+}
+
+#[tokio::test]
+async fn test_totp_code_cannot_be_reused() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let secret = enable_two_factor(&db).await;
+    _ = sqlx::query("UPDATE users SET totp_last_step = $1")
+        .bind(totp::step_at(NOW))
+        .execute(&db)
+        .await;
+    let challenge = start_login(&db).await;
+    let response = send_code(&challenge, &current_code(&secret), &db).await;
+
+    assert!(response.headers().get("HX-redirect").is_none());
+    let content = body_content(response).await;
+    assert!(content.contains("Wrong code"));
+}
+
+#[tokio::test]
+async fn test_login_with_recovery_code() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    enable_two_factor(&db).await;
+    _ = sqlx::query("INSERT INTO recovery_codes (code_hash, user_id) SELECT $1, id FROM users WHERE screen_name = 'Test'")
+        .bind(hash_token("abcde12345"))
+        .execute(&db)
+        .await;
+
+    let challenge = start_login(&db).await;
+    let response = send_code(&challenge, "ABCDE-12345", &db).await;
+    assert!(response.headers().get("HX-redirect").is_some());
+
+    let challenge = start_login(&db).await;
+    let response = send_code(&challenge, "abcde-12345", &db).await;
+    assert!(response.headers().get("HX-redirect").is_none());
+    let content = body_content(response).await;
+    assert!(content.contains("Wrong code"));
+}
+
+#[tokio::test]
+async fn test_login_challenge_allows_limited_attempts() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let secret = enable_two_factor(&db).await;
+    let challenge = start_login(&db).await;
+    for _ in 0..5 {
+        _ = send_code(&challenge, "000000", &db).await;
+    }
+    let response = send_code(&challenge, &current_code(&secret), &db).await;
+
+    assert!(response.headers().get("HX-redirect").is_none());
+    let content = body_content(response).await;
+    assert!(content.contains("Login expired"));
+}
+
+#[tokio::test]
+async fn test_disabling_two_factor() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let secret = enable_two_factor(&db).await;
+    let response = prepare_server_with_clock(db.clone(), clock())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/2fa/disable")
+            .body(Body::from(format!("code={}", current_code(&secret))))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let content = body_content(response).await;
+    assert!(content.contains("Disabled"));
+    let enabled: bool = sqlx::query_scalar("SELECT totp_enabled_at IS NOT NULL FROM users WHERE screen_name = 'Test'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert!(!enabled);
+}
@@ -0,0 +1,270 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::test::{prepare_db, prepare_server_with_db, get_token, fixtures::{UserFixture, PostFixture}};
+
+fn rename_request(username: &str) -> Request<Body> {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    Request::builder()
+        .method("PUT")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Cookie", format!("Token={};", token))
+        .uri("/username")
+        .body(Body::from(format!("username={}", username)))
+        .unwrap()
+}
+
+async fn rename(user_id: i32, username: &str, db: &PgPool) {
+    sqlx::query("INSERT INTO previous_usernames (screen_name, user_id) SELECT screen_name, id FROM users WHERE id = $1")
+        .bind(user_id)
+        .execute(db)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE users SET screen_name = $1 WHERE id = $2")
+        .bind(username)
+        .bind(user_id)
+        .execute(db)
+        .await
+        .unwrap();
+}
+
+async fn username(user_id: i32, db: &PgPool) -> String {
+    sqlx::query_scalar("SELECT screen_name FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+async fn previous_usernames(db: &PgPool) -> Vec<String> {
+    sqlx::query_scalar("SELECT screen_name FROM previous_usernames ORDER BY screen_name")
+        .fetch_all(db)
+        .await
+        .unwrap()
+}
+
+async fn get(uri: &str, db: &PgPool) -> axum::response::Response {
+    prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_getting_username_form() {
+    let db = prepare_db().await;
+    let response = get("/forms/username", &db).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), 1000).await.unwrap();
+    let content = std::str::from_utf8(&bytes).unwrap();
+    assert!(content.contains("<form"));
+    assert!(content.contains("username"));
+}
+
+#[tokio::test]
+async fn test_changing_username() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let app = prepare_server_with_db(db.clone()).await;
+
+    let response = app.clone()
+        .oneshot(rename_request("Renamed"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("HX-redirect").unwrap(), "/user");
+    assert_eq!(username(user_id, &db).await, "Renamed");
+    assert_eq!(previous_usernames(&db).await, vec!["Test"]);
+
+    // the token names the user by id, so it outlives the rename
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let response = app
+        .oneshot(
+            Request::builder()
+            .header("Cookie", format!("Token={};", token))
+            .uri("/user")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), 100000).await.unwrap();
+    let content = std::str::from_utf8(&bytes).unwrap();
+    assert!(content.contains("Hello, Renamed!"));
+}
+
+#[tokio::test]
+async fn test_changing_username_to_invalid_one() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(rename_request("a"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(username(user_id, &db).await, "Test");
+}
+
+#[tokio::test]
+async fn test_changing_username_to_taken_one() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    UserFixture::new("User").insert(&db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(rename_request("User"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(username(user_id, &db).await, "Test");
+    assert!(previous_usernames(&db).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_changing_username_to_previous_name_of_other_user() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").insert(&db).await;
+    let other_id = UserFixture::new("OldName").insert(&db).await;
+    rename(other_id, "NewName", &db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(rename_request("OldName"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(username(user_id, &db).await, "Test");
+}
+
+#[tokio::test]
+async fn test_taking_back_previous_username() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("OldName").insert(&db).await;
+    rename(user_id, "Test", &db).await;
+    // the fixture's session is opened for the name the user had at the time
+    let (token, _) = get_token(&Some(String::from("OldName")));
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("PUT")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/username")
+            .body(Body::from("username=OldName"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(username(user_id, &db).await, "OldName");
+    assert_eq!(previous_usernames(&db).await, vec!["Test"]);
+}
+
+#[tokio::test]
+async fn test_registering_with_previous_username_of_other_user() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("OldName").insert(&db).await;
+    rename(user_id, "NewName", &db).await;
+
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/register")
+            .body(Body::from("username=OldName&email=old%40email.com&psw=password&psw_repeat=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(users, 1);
+}
+
+#[tokio::test]
+async fn test_logging_in_with_new_username() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("Test").hashed_password().insert(&db).await;
+    rename(user_id, "Renamed", &db).await;
+
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/login")
+            .body(Body::from("username=Renamed&psw=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("HX-redirect").is_some());
+}
+
+#[tokio::test]
+async fn test_old_profile_link_redirects_to_new_one() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("OldName").insert(&db).await;
+    rename(user_id, "NewName", &db).await;
+
+    let response = get("/profile/OldName", &db).await;
+
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers().get("Location").unwrap(), "/profile/NewName");
+}
+
+#[tokio::test]
+async fn test_old_blog_links_redirect_to_new_ones() {
+    let db = prepare_db().await;
+    let user_id = UserFixture::new("OldName").insert(&db).await;
+    PostFixture::new(user_id).insert(&db).await;
+    rename(user_id, "NewName", &db).await;
+
+    let response = get("/user/OldName/blog", &db).await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers().get("Location").unwrap(), "/user/NewName/blog");
+
+    let response = get("/user/OldName/blog/page?page=1", &db).await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers().get("Location").unwrap(), "/user/NewName/blog/page?page=1");
+
+    let response = get("/user/OldName/blog/new", &db).await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers().get("Location").unwrap(), "/user/NewName/blog/new");
+}
+
+#[tokio::test]
+async fn test_unknown_profile_is_not_found() {
+    let db = prepare_db().await;
+    let response = get("/profile/Nobody", &db).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,208 @@
+use axum::{extract::Request, body::{Body, to_bytes}, http::StatusCode};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::test::{fixtures::UserFixture, prepare_db, prepare_server_with_db, prepare_server_with_config, test_config, insert_default_user, get_token, clear_mail, read_mail, token_from_mail};
+
+async fn is_verified(db: &PgPool) -> bool {
+    sqlx::query_scalar("SELECT email_verified_at IS NOT NULL FROM users WHERE screen_name = 'Test'")
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+async fn verify(token: &str, db: &PgPool) -> String {
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .uri(format!("/email/verify?token={}", token))
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 5000).await.unwrap();
+    String::from(std::str::from_utf8(&*body).unwrap())
+}
+
+async fn change_email(email: &str, db: &PgPool) {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    _ = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("PUT")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/email")
+            .body(Body::from(format!("email={}", email)))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_registering_sends_verification_mail() {
+    let db = prepare_db().await;
+    clear_mail(&db);
+    _ = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .uri("/register")
+            .body(Body::from("username=User&email=user%40email.com&psw=password&psw_repeat=password"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    let mail = read_mail(&db);
+    assert!(mail.is_some());
+    let mail = mail.unwrap();
+    assert!(mail.contains("To: user@email.com"));
+    assert!(mail.contains("/email/verify?token="));
+
+    let verified: bool = sqlx::query_scalar("SELECT email_verified_at IS NOT NULL FROM users WHERE screen_name = 'User'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert!(!verified);
+}
+
+#[tokio::test]
+async fn test_verifying_email() {
+    let db = prepare_db().await;
+    clear_mail(&db);
+    insert_default_user(true, &db).await;
+    change_email("new%40email.com", &db).await;
+    assert!(!is_verified(&db).await);
+
+    let token = token_from_mail(&read_mail(&db).unwrap());
+    let content = verify(&token, &db).await;
+    assert!(content.contains("Email verified"));
+    assert!(is_verified(&db).await);
+}
+
+#[tokio::test]
+async fn test_verifying_email_with_invalid_token() {
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let content = verify("invalid", &db).await;
+    assert!(content.contains("invalid or has expired"));
+    assert!(!is_verified(&db).await);
+}
+
+#[tokio::test]
+async fn test_verifying_email_that_was_changed_since() {
+    let db = prepare_db().await;
+    clear_mail(&db);
+    insert_default_user(true, &db).await;
+    change_email("first%40email.com", &db).await;
+    let token = token_from_mail(&read_mail(&db).unwrap());
+    change_email("second%40email.com", &db).await;
+
+    let content = verify(&token, &db).await;
+    assert!(content.contains("invalid or has expired"));
+    assert!(!is_verified(&db).await);
+}
+
+#[tokio::test]
+async fn test_changing_email_requires_verification() {
+    let db = prepare_db().await;
+    UserFixture::new("Test").email("test@email.com").hashed_password().verified().insert(&db).await;
+    clear_mail(&db);
+    change_email("new%40email.com", &db).await;
+
+    assert!(!is_verified(&db).await);
+    let mail = read_mail(&db);
+    assert!(mail.is_some());
+    assert!(mail.unwrap().contains("To: new@email.com"));
+}
+
+#[tokio::test]
+async fn test_resending_verification() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    clear_mail(&db);
+    let response = prepare_server_with_db(db.clone())
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/email/verify")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), 1000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("Verification link sent"));
+    assert!(read_mail(&db).is_some());
+}
+
+#[tokio::test]
+async fn test_resending_verification_for_verified_email() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    UserFixture::new("Test").email("test@email.com").hashed_password().verified().insert(&db).await;
+    let response = prepare_server_with_db(db)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/email/verify")
+            .body(Body::empty())
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = to_bytes(response.into_body(), 1000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("already verified"));
+}
+
+#[tokio::test]
+async fn test_posting_with_unverified_email_when_required() {
+    let (token, _) = get_token(&Some(String::from("Test")));
+    let db = prepare_db().await;
+    insert_default_user(true, &db).await;
+    let mut config = test_config();
+    config.accounts.require_verified_email = true;
+    let response = prepare_server_with_config(db, config)
+        .await
+        .oneshot(
+            Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Cookie", format!("Token={};", token))
+            .uri("/blog")
+            .body(Body::from("title=Title&content=Content"))
+            .unwrap()
+            )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = to_bytes(response.into_body(), 1000).await;
+    assert!(body.is_ok());
+    let bytes = body.unwrap();
+    let content = std::str::from_utf8(&*bytes).unwrap();
+    assert!(content.contains("verify your email"));
+}
@@ -0,0 +1,126 @@
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha1::Sha1;
+
+/// Length of a time step in seconds.
+pub const PERIOD: i64 = 30;
+pub const DIGITS: u32 = 6;
+/// Number of steps before and after the current one that are still accepted,
+/// to allow for clock drift between the server and the phone.
+const SKEW: i64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// New random 160-bit secret, base32 encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+pub fn step_at(timestamp: i64) -> i64 {
+    timestamp.div_euclid(PERIOD)
+}
+
+/// HOTP value (RFC 4226) for the given counter.
+pub fn code(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+/// Checks the code against the steps around `timestamp` and returns the matching
+/// step. Steps not later than `last_step` are rejected so a code works only once.
+pub fn verify(secret: &str, code_to_check: &str, timestamp: i64, last_step: Option<i64>) -> Option<i64> {
+    let secret = base32_decode(secret)?;
+    let code_to_check = code_to_check.trim().replace(' ', "");
+    if code_to_check.len() != DIGITS as usize {
+        return None;
+    }
+    let current = step_at(timestamp);
+    (current - SKEW..=current + SKEW)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| code(&secret, *step) == code_to_check)
+}
+
+/// `otpauth://` URI encoded in the QR code scanned by authenticator apps.
+pub fn provisioning_uri(secret: &str, account: &str, issuer: &str) -> String {
+    format!("otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}")
+}
+
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut result = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    result
+}
+
+pub fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut result = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in data.trim_end_matches('=').chars() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::totp::{code, verify, step_at, base32_encode, base32_decode};
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc_6238_vectors() {
+        assert_eq!(code(RFC_SECRET, step_at(59)), "287082");
+        assert_eq!(code(RFC_SECRET, step_at(1111111109)), "081804");
+        assert_eq!(code(RFC_SECRET, step_at(1234567890)), "005924");
+        assert_eq!(code(RFC_SECRET, step_at(2000000000)), "279037");
+    }
+
+    #[test]
+    fn test_base32_roundtrip() {
+        let encoded = base32_encode(RFC_SECRET);
+        assert_eq!(encoded, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode(&encoded).unwrap(), RFC_SECRET);
+        assert!(base32_decode("not base32!").is_none());
+    }
+
+    #[test]
+    fn test_verifying_codes() {
+        let secret = base32_encode(RFC_SECRET);
+        assert_eq!(verify(&secret, "081804", 1111111109, None), Some(step_at(1111111109)));
+        assert_eq!(verify(&secret, "081 804", 1111111109 + 30, None), Some(step_at(1111111109)));
+        assert!(verify(&secret, "081804", 1111111109 + 90, None).is_none());
+        assert!(verify(&secret, "000000", 1111111109, None).is_none());
+    }
+
+    #[test]
+    fn test_code_cannot_be_replayed() {
+        let secret = base32_encode(RFC_SECRET);
+        let step = verify(&secret, "081804", 1111111109, None);
+        assert!(step.is_some());
+        assert!(verify(&secret, "081804", 1111111109, step).is_none());
+    }
+}